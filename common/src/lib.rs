@@ -3,43 +3,88 @@
 
 extern crate x86;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::marker::PhantomData;
+
 pub const COM1 : u16 = 0x3F8;
 
-pub struct Queue<T : Copy+Default> {
-    buffer: [T; 32],
+/// The storage of a `Queue`, which holds as many items as it has slots.
+pub trait Buffer<T: Copy> {
+    fn filled(item: T) -> Self;
+    fn slots(&self) -> &[T];
+    fn slots_mut(&mut self) -> &mut [T];
+}
+
+macro_rules! array_buffers {
+    ($($len:expr),*) => {
+        $(
+            impl<T: Copy> Buffer<T> for [T; $len] {
+                fn filled(item: T) -> Self {
+                    [item; $len]
+                }
+
+                fn slots(&self) -> &[T] {
+                    self
+                }
+
+                fn slots_mut(&mut self) -> &mut [T] {
+                    self
+                }
+            }
+        )*
+    }
+}
+
+array_buffers!(8, 16, 32, 64, 128, 256, 512, 1024);
+
+/// A ring of `T`s, 32 unless the buffer type says otherwise: a
+/// `Queue<u8, [u8; 256]>` holds 256 bytes.
+pub struct Queue<T : Copy+Default, B: Buffer<T> = [T; 32]> {
+    buffer: B,
     first_free_index: usize,
     pub count: usize,
+    item: PhantomData<T>,
 }
 
-impl<T> Queue<T> where T: Copy+Default {
-    pub fn new() -> Queue<T> {
+impl<T, B> Queue<T, B> where T: Copy+Default, B: Buffer<T> {
+    pub fn new() -> Queue<T, B> {
         Queue {
-            buffer: [Default::default(); 32],
+            buffer: B::filled(Default::default()),
             first_free_index: 0,
-            count: 0
+            count: 0,
+            item: PhantomData,
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots().len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count == self.capacity()
+    }
+
     pub fn enqueue(&mut self, item: T) -> Result<(),()> {
-        if self.count == self.buffer.len() {
+        if self.is_full() {
             Err(())
         } else {
-            self.buffer[self.first_free_index] = item;
-            self.first_free_index = (self.first_free_index + 1) % self.buffer.len();
+            let capacity = self.capacity();
+            self.buffer.slots_mut()[self.first_free_index] = item;
+            self.first_free_index = (self.first_free_index + 1) % capacity;
             self.count += 1;
             Ok(())
         }
     }
 
     fn first_value_index(&self) -> usize {
-        (self.buffer.len() + self.first_free_index - self.count) % self.buffer.len()
+        (self.capacity() + self.first_free_index - self.count) % self.capacity()
     }
 
     pub fn try_dequeue(&mut self) -> Option<T> {
         if self.count == 0 {
             None
         } else {
-            let item = self.buffer[self.first_value_index()];
+            let item = self.buffer.slots()[self.first_value_index()];
             self.count -= 1;
             Some(item)
         }
@@ -71,6 +116,62 @@ fn queue_in_out() {
     assert_eq!(None, q.try_dequeue());
 }
 
+#[test]
+fn queue_capacity() {
+    let mut q = Queue::<u8, [u8; 128]>::new();
+    assert_eq!(128, q.capacity());
+    assert_eq!(32, Queue::<u8>::new().capacity());
+
+    for i in 0..128 {
+        q.enqueue(i).unwrap();
+    }
+    assert!(q.is_full());
+    assert_eq!(Err(()), q.enqueue(128));
+
+    for i in 0..64 {
+        assert_eq!(i, q.try_dequeue().unwrap());
+    }
+    for i in 128..192 {
+        q.enqueue(i as u8).unwrap();
+    }
+    for i in 64..192 {
+        assert_eq!(i as u8, q.try_dequeue().unwrap());
+    }
+    assert_eq!(None, q.try_dequeue());
+}
+
+/// A flag that interrupt handlers set to wake up threads waiting for data.
+pub struct Event {
+    signaled: AtomicBool,
+}
+
+impl Event {
+    pub fn new() -> Event {
+        Event { signaled: AtomicBool::new(false) }
+    }
+
+    pub fn signal(&self) {
+        self.signaled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn reset(&self) {
+        self.signaled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_signaled(&self) -> bool {
+        self.signaled.load(Ordering::SeqCst)
+    }
+}
+
+/// Something that can block until one of several `Event`s is signaled.
+///
+/// Implemented by the kernel's threads, so that drivers can offer blocking
+/// reads without depending on the scheduler. To avoid missing a wakeup,
+/// reset the event *before* checking for data, then wait if there was none.
+pub trait Waiter {
+    fn wait(&mut self, events: &[&Event]);
+}
+
 pub struct InterruptData<T> {
     data: T,
 }
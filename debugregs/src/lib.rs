@@ -0,0 +1,282 @@
+//! Hardware breakpoints and watchpoints, through the debug registers: up
+//! to four addresses in DR0 to DR3, what to watch at each in DR7, and which
+//! of them fired in DR6 when the debug exception (vector 1) comes.
+//!
+//! Execution breakpoints fault before the instruction runs; the handler has
+//! to set `FLAGS_RF` in the flags it returns to, or the same breakpoint
+//! fires again straight away. Data watchpoints trap after the access.
+
+#![feature(asm)]
+#![no_std]
+
+use core::fmt;
+
+/// The number of breakpoints and watchpoints the hardware has.
+pub const SLOTS: usize = 4;
+
+/// Resume flag: the instruction returned to doesn't hit its execution
+/// breakpoint again.
+pub const FLAGS_RF: usize = 1 << 16;
+
+/// DR7: exact breakpoint matching, which the manuals recommend setting
+/// whenever breakpoints are.
+const DR7_LE: usize = 1 << 8;
+const DR7_GE: usize = 1 << 9;
+const DR7_CONTROL_SHIFT: usize = 16;
+
+/// DR6: a single step, rather than a breakpoint, caused the exception.
+const DR6_BS: usize = 1 << 14;
+/// What DR6 holds with nothing reported, reserved bits included.
+const DR6_CLEAR: usize = 0xFFFF_0FF0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Condition {
+    /// Fetching the instruction at the address.
+    Execute,
+    Write,
+    /// Reads or writes; there's no condition for reads only.
+    ReadWrite,
+}
+
+impl Condition {
+    fn bits(self) -> usize {
+        match self {
+            Condition::Execute => 0b00,
+            Condition::Write => 0b01,
+            Condition::ReadWrite => 0b11,
+        }
+    }
+
+    fn from_bits(bits: usize) -> Option<Condition> {
+        match bits {
+            0b00 => Some(Condition::Execute),
+            0b01 => Some(Condition::Write),
+            0b11 => Some(Condition::ReadWrite),
+            // I/O breakpoints, never set here
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// Lengths are 1, 2, 4 or 8 bytes, and 1 for `Condition::Execute`.
+    BadLength,
+    /// The address isn't a multiple of the length.
+    Misaligned,
+    NoSuchSlot,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub address: usize,
+    pub condition: Condition,
+    /// In bytes.
+    pub len: usize,
+}
+
+impl Watchpoint {
+    pub fn new(address: usize, condition: Condition, len: usize) -> Result<Watchpoint, Error> {
+        match len {
+            1 => {},
+            2 | 4 | 8 if condition != Condition::Execute => {},
+            _ => return Err(Error::BadLength),
+        }
+        if address % len != 0 {
+            return Err(Error::Misaligned);
+        }
+
+        Ok(Watchpoint { address, condition, len })
+    }
+
+    /// The slot's four bits of DR7: condition, then length.
+    fn control(&self) -> usize {
+        let len = match self.len {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        self.condition.bits() | len << 2
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.condition {
+            Condition::Execute => write!(f, "execute at {:x}", self.address),
+            Condition::Write => write!(f, "write {} at {:x}", self.len, self.address),
+            Condition::ReadWrite => write!(f, "read/write {} at {:x}", self.len, self.address),
+        }
+    }
+}
+
+/// The addresses and DR7, as a thread has them set. DR6 isn't part of it:
+/// the debug exception handler reads and clears it right away.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct DebugRegisters {
+    pub address: [usize; SLOTS],
+    pub control: usize,
+}
+
+impl DebugRegisters {
+    /// Nothing watched.
+    pub fn new() -> DebugRegisters {
+        DebugRegisters::default()
+    }
+
+    /// Sets what `slot` watches, or disables it with `None`.
+    pub fn set(&mut self, slot: usize, watchpoint: Option<Watchpoint>) -> Result<(), Error> {
+        if slot >= SLOTS {
+            return Err(Error::NoSuchSlot);
+        }
+
+        let shift = DR7_CONTROL_SHIFT + slot * 4;
+        self.control &= !(0b11 << (slot * 2) | 0b1111 << shift);
+        match watchpoint {
+            Some(w) => {
+                self.address[slot] = w.address;
+                self.control |= 1 << (slot * 2) | w.control() << shift;
+            },
+            None => self.address[slot] = 0,
+        }
+
+        if self.iter().next().is_some() {
+            self.control |= DR7_LE | DR7_GE;
+        } else {
+            self.control &= !(DR7_LE | DR7_GE);
+        }
+        Ok(())
+    }
+
+    /// What `slot` watches, if it is enabled.
+    pub fn get(&self, slot: usize) -> Option<Watchpoint> {
+        if slot >= SLOTS || self.control & 0b11 << (slot * 2) == 0 {
+            return None;
+        }
+
+        let control = self.control >> (DR7_CONTROL_SHIFT + slot * 4);
+        let len = match control >> 2 & 0b11 {
+            0b00 => 1,
+            0b01 => 2,
+            0b10 => 8,
+            _ => 4,
+        };
+        Condition::from_bits(control & 0b11).map(|condition| Watchpoint {
+            address: self.address[slot],
+            condition,
+            len,
+        })
+    }
+
+    /// The enabled slots and what they watch.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (usize, Watchpoint)> + 'a {
+        (0..SLOTS).filter_map(move |slot| self.get(slot).map(|w| (slot, w)))
+    }
+
+    /// The first slot not in use.
+    pub fn free_slot(&self) -> Option<usize> {
+        (0..SLOTS).find(|&slot| self.get(slot).is_none())
+    }
+
+    /// Reads what the processor has set.
+    pub fn current() -> DebugRegisters {
+        let mut regs = DebugRegisters::new();
+        unsafe {
+            asm!("mov $0, dr0" : "=r"(regs.address[0]) ::: "volatile", "intel");
+            asm!("mov $0, dr1" : "=r"(regs.address[1]) ::: "volatile", "intel");
+            asm!("mov $0, dr2" : "=r"(regs.address[2]) ::: "volatile", "intel");
+            asm!("mov $0, dr3" : "=r"(regs.address[3]) ::: "volatile", "intel");
+            asm!("mov $0, dr7" : "=r"(regs.control) ::: "volatile", "intel");
+        }
+        regs
+    }
+
+    /// Sets the processor's registers to these. DR7 goes last, so nothing
+    /// fires on a half loaded set.
+    pub unsafe fn load(&self) {
+        asm!("mov dr7, $0" :: "r"(0usize) :: "volatile", "intel");
+        asm!("mov dr0, $0" :: "r"(self.address[0]) :: "volatile", "intel");
+        asm!("mov dr1, $0" :: "r"(self.address[1]) :: "volatile", "intel");
+        asm!("mov dr2, $0" :: "r"(self.address[2]) :: "volatile", "intel");
+        asm!("mov dr3, $0" :: "r"(self.address[3]) :: "volatile", "intel");
+        asm!("mov dr7, $0" :: "r"(self.control) :: "volatile", "intel");
+    }
+}
+
+/// DR6: why the debug exception came.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Status(pub usize);
+
+impl Status {
+    /// Reads DR6 and clears it for the next exception, which the processor
+    /// never does itself.
+    pub fn take() -> Status {
+        let value: usize;
+        unsafe {
+            asm!("mov $0, dr6" : "=r"(value) ::: "volatile", "intel");
+            asm!("mov dr6, $0" :: "r"(DR6_CLEAR) :: "volatile", "intel");
+        }
+        Status(value)
+    }
+
+    /// Whether `slot`'s condition was met. Disabled slots can be reported
+    /// too, so check they are set.
+    pub fn triggered(&self, slot: usize) -> bool {
+        slot < SLOTS && self.0 & 1 << slot != 0
+    }
+
+    pub fn single_step(&self) -> bool {
+        self.0 & DR6_BS != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchpoints_are_checked() {
+        assert!(Watchpoint::new(0x1000, Condition::Execute, 1).is_ok());
+        assert_eq!(Watchpoint::new(0x1000, Condition::Execute, 4), Err(Error::BadLength));
+        assert_eq!(Watchpoint::new(0x1000, Condition::Write, 3), Err(Error::BadLength));
+        assert_eq!(Watchpoint::new(0x1004, Condition::ReadWrite, 8), Err(Error::Misaligned));
+        assert!(Watchpoint::new(0x1008, Condition::ReadWrite, 8).is_ok());
+    }
+
+    #[test]
+    fn dr7_encoding() {
+        let mut regs = DebugRegisters::new();
+        let write = Watchpoint::new(0x2000, Condition::Write, 4).unwrap();
+        let exec = Watchpoint::new(0x3001, Condition::Execute, 1).unwrap();
+        regs.set(1, Some(write)).unwrap();
+        regs.set(3, Some(exec)).unwrap();
+        // L1, L3, LE, GE; slot 1 write of 4 bytes, slot 3 execute
+        assert_eq!(regs.control, 0b0100 | 0b0100_0000 | DR7_LE | DR7_GE | 0b1101 << 20);
+        assert_eq!(regs.address, [0, 0x2000, 0, 0x3001]);
+        assert_eq!(regs.get(1), Some(write));
+        assert_eq!(regs.get(3), Some(exec));
+        assert_eq!(regs.get(0), None);
+        assert_eq!(regs.free_slot(), Some(0));
+
+        let rw = Watchpoint::new(0x4000, Condition::ReadWrite, 8).unwrap();
+        regs.set(1, Some(rw)).unwrap();
+        assert_eq!(regs.get(1), Some(rw));
+        assert_eq!(regs.iter().count(), 2);
+
+        regs.set(1, None).unwrap();
+        regs.set(3, None).unwrap();
+        assert_eq!(regs, DebugRegisters::new());
+        assert_eq!(regs.set(4, None), Err(Error::NoSuchSlot));
+    }
+
+    #[test]
+    fn dr6_decoding() {
+        let status = Status(DR6_CLEAR | 0b0100 | DR6_BS);
+        assert!(status.triggered(2));
+        assert!(!status.triggered(0));
+        assert!(!status.triggered(4));
+        assert!(status.single_step());
+        assert!(!Status(DR6_CLEAR).single_step());
+    }
+}
@@ -0,0 +1,749 @@
+//! A stub for the GDB Remote Serial Protocol: lets `gdb` stop the kernel,
+//! look at and change its registers and memory, set breakpoints and step
+//! through it, over a serial line.
+//!
+//! The stub only knows the protocol. Whoever embeds it provides the line,
+//! as a `Connection`, and the stopped code, as a `Target`, and calls in
+//! from its breakpoint and debug exception handlers. Breakpoints are `int3`
+//! instructions written over the code; single steps use the trap flag.
+//!
+//! See https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+#![no_std]
+
+/// Reported when gdb interrupted the target with Ctrl-C.
+pub const SIGINT: u8 = 2;
+/// Reported for breakpoints and steps.
+pub const SIGTRAP: u8 = 5;
+
+/// The largest packet we take or send, not counting `$`, `#` and the
+/// checksum.
+pub const PACKET_SIZE: usize = 1024;
+
+pub const MAX_BREAKPOINTS: usize = 16;
+
+const INT3: u8 = 0xCC;
+/// The trap flag in RFLAGS: a debug exception after the next instruction.
+pub const FLAGS_TF: u64 = 0x100;
+
+/// Registers in the order gdb's amd64 target description lists them.
+pub mod register {
+    pub const RAX: usize = 0;
+    pub const RBX: usize = 1;
+    pub const RCX: usize = 2;
+    pub const RDX: usize = 3;
+    pub const RSI: usize = 4;
+    pub const RDI: usize = 5;
+    pub const RBP: usize = 6;
+    pub const RSP: usize = 7;
+    pub const R8: usize = 8;
+    pub const R15: usize = 15;
+    pub const RIP: usize = 16;
+    pub const EFLAGS: usize = 17;
+    pub const CS: usize = 18;
+    pub const SS: usize = 19;
+    pub const DS: usize = 20;
+    pub const ES: usize = 21;
+    pub const FS: usize = 22;
+    pub const GS: usize = 23;
+
+    pub const COUNT: usize = 24;
+
+    /// The size of register `index` in bytes: RIP and everything before it
+    /// are 64 bits wide, the flags and segment registers 32.
+    pub fn size(index: usize) -> usize {
+        if index <= RIP { 8 } else { 4 }
+    }
+}
+
+/// The registers of the stopped code.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Registers {
+    pub values: [u64; register::COUNT],
+}
+
+/// The serial line to gdb. Both calls wait until they are done.
+pub trait Connection {
+    fn read_byte(&mut self) -> u8;
+    fn write_byte(&mut self, b: u8);
+}
+
+/// The stopped code.
+pub trait Target {
+    fn registers(&self) -> Registers;
+    fn set_registers(&mut self, registers: &Registers);
+    /// Fails if some of the memory isn't there.
+    fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), ()>;
+    fn write_memory(&mut self, address: u64, bytes: &[u8]) -> Result<(), ()>;
+}
+
+/// How gdb let the target go on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resume {
+    Continue,
+    Step,
+    /// Breakpoints are gone and the target runs on as if gdb was never
+    /// there.
+    Detach,
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    /// The byte the `int3` replaced.
+    original: u8,
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Parses a hex number, as in `m<address>,<length>`.
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+
+    s.iter().try_fold(0u64, |n, &b| hex_digit(b).map(|d| n << 4 | d as u64))
+}
+
+/// Parses hex digit pairs into `bytes`, which must be exactly as long.
+fn parse_hex_bytes(s: &[u8], bytes: &mut [u8]) -> Option<()> {
+    if s.len() != bytes.len() * 2 {
+        return None;
+    }
+
+    for (pair, b) in s.chunks(2).zip(bytes.iter_mut()) {
+        *b = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// Splits `s` at the first `separator`.
+fn split(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = s.iter().position(|&b| b == separator)?;
+    Some((&s[..at], &s[at + 1..]))
+}
+
+/// A reply being put together.
+struct Reply {
+    buffer: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn push(&mut self, bytes: &[u8]) {
+        let end = (self.len + bytes.len()).min(PACKET_SIZE);
+        let count = end - self.len;
+        self.buffer[self.len..end].copy_from_slice(&bytes[..count]);
+        self.len = end;
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(&[HEX[(b >> 4) as usize], HEX[(b & 0xF) as usize]]);
+        }
+    }
+}
+
+/// Bytes of memory `m` may ask for at a time, so that the reply fits.
+const MAX_MEMORY_CHUNK: usize = PACKET_SIZE / 2;
+
+/// The protocol state: breakpoints and whatever stepping is going on.
+pub struct Stub {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// The breakpoint lifted for a single step, so that the target could
+    /// get past it; it goes back in at the next debug exception.
+    step_over: Option<u64>,
+    /// Whether gdb asked for a single step.
+    stepping: bool,
+    /// Whether gdb talked to us since the last detach.
+    attached: bool,
+    signal: u8,
+    input: [u8; PACKET_SIZE],
+    reply: Reply,
+    /// A byte read while expecting an acknowledgement.
+    peeked: Option<u8>,
+}
+
+impl Stub {
+    pub fn new() -> Stub {
+        Stub {
+            breakpoints: [None; MAX_BREAKPOINTS],
+            step_over: None,
+            stepping: false,
+            attached: false,
+            signal: SIGTRAP,
+            input: [0; PACKET_SIZE],
+            reply: Reply { buffer: [0; PACKET_SIZE], len: 0 },
+            peeked: None,
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    pub fn is_breakpoint(&self, address: u64) -> bool {
+        self.breakpoints.iter().any(|b| b.map(|b| b.address) == Some(address))
+    }
+
+    /// Called on a breakpoint exception, with the instruction pointer just
+    /// past the `int3`. Returns false if it isn't one of our breakpoints.
+    pub fn on_breakpoint<C: Connection, T: Target>(&mut self, connection: &mut C, target: &mut T) -> bool {
+        let mut registers = target.registers();
+        let address = registers.values[register::RIP].wrapping_sub(1);
+        if !self.is_breakpoint(address) {
+            return false;
+        }
+
+        // gdb wants to see the address of the breakpoint, and that is where
+        // the replaced instruction runs from
+        registers.values[register::RIP] = address;
+        target.set_registers(&registers);
+        self.run(connection, target, Some(SIGTRAP));
+        true
+    }
+
+    /// Called on a single step debug exception. Returns false if we didn't
+    /// ask for it.
+    pub fn on_step<C: Connection, T: Target>(&mut self, connection: &mut C, target: &mut T) -> bool {
+        let stepped_over = self.step_over.take();
+        if stepped_over.is_none() && !self.stepping {
+            return false;
+        }
+
+        if let Some(address) = stepped_over {
+            let _ = target.write_memory(address, &[INT3]);
+        }
+
+        if self.stepping {
+            self.stepping = false;
+            self.run(connection, target, Some(SIGTRAP));
+        } else {
+            // just getting past a breakpoint on the way to continue
+            let mut registers = target.registers();
+            registers.values[register::EFLAGS] &= !FLAGS_TF;
+            target.set_registers(&registers);
+        }
+        true
+    }
+
+    /// Talks to gdb until it lets the target go on. Reports `signal` as the
+    /// reason the target stopped first, if gdb is waiting for that; without
+    /// one, gdb is expected to speak first.
+    pub fn run<C: Connection, T: Target>(&mut self, connection: &mut C, target: &mut T, signal: Option<u8>) -> Resume {
+        if let Some(signal) = signal {
+            self.signal = signal;
+            self.stop_reply();
+            self.send(connection);
+        }
+
+        loop {
+            let len = self.receive(connection);
+            self.attached = true;
+            self.reply.len = 0;
+            let resume = self.handle(target, len);
+
+            // gdb expects no reply when it continues, steps or kills
+            let silent = resume.is_some() && self.input[0] != b'D';
+            if !silent {
+                self.send(connection);
+            }
+
+            if let Some(resume) = resume {
+                self.resume(target, resume);
+                return resume;
+            }
+        }
+    }
+
+    fn stop_reply(&mut self) {
+        self.reply.len = 0;
+        self.reply.push(b"S");
+        let signal = self.signal;
+        self.reply.push_hex(&[signal]);
+    }
+
+    fn next_byte<C: Connection>(&mut self, connection: &mut C) -> u8 {
+        self.peeked.take().unwrap_or_else(|| connection.read_byte())
+    }
+
+    /// Waits for a packet with a good checksum, acknowledging it, and
+    /// returns its length. Anything between packets is skipped.
+    fn receive<C: Connection>(&mut self, connection: &mut C) -> usize {
+        loop {
+            while self.next_byte(connection) != b'$' {}
+
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut overflow = false;
+            loop {
+                let b = self.next_byte(connection);
+                if b == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(b);
+                if len < PACKET_SIZE {
+                    self.input[len] = b;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+
+            let high = hex_digit(self.next_byte(connection));
+            let low = hex_digit(self.next_byte(connection));
+            let good = match (high, low) {
+                (Some(high), Some(low)) => high << 4 | low == sum && !overflow,
+                _ => false,
+            };
+
+            connection.write_byte(if good { b'+' } else { b'-' });
+            if good && len > 0 {
+                return len;
+            }
+        }
+    }
+
+    /// Sends the reply until gdb acknowledges it.
+    fn send<C: Connection>(&mut self, connection: &mut C) {
+        loop {
+            let mut sum = 0u8;
+            connection.write_byte(b'$');
+            for &b in &self.reply.buffer[..self.reply.len] {
+                sum = sum.wrapping_add(b);
+                connection.write_byte(b);
+            }
+            connection.write_byte(b'#');
+            connection.write_byte(HEX[(sum >> 4) as usize]);
+            connection.write_byte(HEX[(sum & 0xF) as usize]);
+
+            match self.next_byte(connection) {
+                b'-' => continue,
+                b'+' => return,
+                // gdb moved on, whatever happened to our acknowledgement
+                b => {
+                    self.peeked = Some(b);
+                    return;
+                },
+            }
+        }
+    }
+
+    /// Carries out the command in the first `len` bytes of the input,
+    /// leaving the reply to it. Returns how to go on if it resumes the
+    /// target.
+    fn handle<T: Target>(&mut self, target: &mut T, len: usize) -> Option<Resume> {
+        let mut input = [0u8; PACKET_SIZE];
+        input[..len].copy_from_slice(&self.input[..len]);
+        let (command, args) = (input[0], &input[1..len]);
+
+        let result = match command {
+            b'?' => {
+                self.stop_reply();
+                Some(())
+            },
+            b'g' => {
+                let registers = target.registers();
+                for (index, &value) in registers.values.iter().enumerate() {
+                    let bytes = le_bytes(value);
+                    self.reply.push_hex(&bytes[..register::size(index)]);
+                }
+                Some(())
+            },
+            b'G' => self.write_registers(target, args),
+            b'p' => parse_hex(args).and_then(|index| {
+                let index = index as usize;
+                if index >= register::COUNT {
+                    return None;
+                }
+                let bytes = le_bytes(target.registers().values[index]);
+                self.reply.push_hex(&bytes[..register::size(index)]);
+                Some(())
+            }),
+            b'P' => self.write_register(target, args),
+            b'm' => self.read_memory(target, args),
+            b'M' => self.write_memory(target, args),
+            b'Z' | b'z' => {
+                match split(args, b',') {
+                    // software breakpoints only
+                    Some((b"0", rest)) => {
+                        let address = split(rest, b',').and_then(|(address, _)| parse_hex(address));
+                        match address {
+                            Some(address) if command == b'Z' => self.insert_breakpoint(target, address),
+                            Some(address) => self.remove_breakpoint(target, address),
+                            None => None,
+                        }
+                    },
+                    _ => return None,
+                }
+            },
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    let address = parse_hex(args)?;
+                    let mut registers = target.registers();
+                    registers.values[register::RIP] = address;
+                    target.set_registers(&registers);
+                }
+                return Some(if command == b'c' { Resume::Continue } else { Resume::Step });
+            },
+            b'D' | b'k' => {
+                self.reply.push(b"OK");
+                return Some(Resume::Detach);
+            },
+            b'H' | b'T' => {
+                // one thread of execution: the machine
+                self.reply.push(b"OK");
+                return None;
+            },
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    self.reply.push(b"PacketSize=");
+                    let size = le_bytes(PACKET_SIZE as u64);
+                    self.reply.push_hex(&[size[1], size[0]]);
+                } else if args.starts_with(b"Attached") {
+                    // detaching rather than killing is all we can do
+                    self.reply.push(b"1");
+                }
+                return None;
+            },
+            // anything else gets the empty reply, for "not supported"
+            _ => return None,
+        };
+
+        if result.is_none() {
+            self.reply.len = 0;
+            self.reply.push(b"E01");
+        } else if self.reply.len == 0 {
+            self.reply.push(b"OK");
+        }
+        None
+    }
+
+    fn write_registers<T: Target>(&mut self, target: &mut T, args: &[u8]) -> Option<()> {
+        let mut registers = target.registers();
+        let mut offset = 0;
+        for (index, value) in registers.values.iter_mut().enumerate() {
+            let size = register::size(index);
+            let mut bytes = [0u8; 8];
+            parse_hex_bytes(args.get(offset..offset + size * 2)?, &mut bytes[..size])?;
+            *value = from_le_bytes(&bytes);
+            offset += size * 2;
+        }
+
+        target.set_registers(&registers);
+        Some(())
+    }
+
+    fn write_register<T: Target>(&mut self, target: &mut T, args: &[u8]) -> Option<()> {
+        let (index, value) = split(args, b'=')?;
+        let index = parse_hex(index)? as usize;
+        if index >= register::COUNT {
+            return None;
+        }
+
+        let mut bytes = [0u8; 8];
+        parse_hex_bytes(value, &mut bytes[..register::size(index)])?;
+        let mut registers = target.registers();
+        registers.values[index] = from_le_bytes(&bytes);
+        target.set_registers(&registers);
+        Some(())
+    }
+
+    fn read_memory<T: Target>(&mut self, target: &mut T, args: &[u8]) -> Option<()> {
+        let (address, len) = split(args, b',')?;
+        let (address, len) = (parse_hex(address)?, parse_hex(len)? as usize);
+        let mut bytes = [0u8; MAX_MEMORY_CHUNK];
+        let bytes = bytes.get_mut(..len)?;
+        target.read_memory(address, bytes).ok()?;
+        self.hide_breakpoints(address, bytes);
+        self.reply.push_hex(bytes);
+        Some(())
+    }
+
+    fn write_memory<T: Target>(&mut self, target: &mut T, args: &[u8]) -> Option<()> {
+        let (location, data) = split(args, b':')?;
+        let (address, len) = split(location, b',')?;
+        let (address, len) = (parse_hex(address)?, parse_hex(len)? as usize);
+        let mut bytes = [0u8; MAX_MEMORY_CHUNK];
+        let bytes = bytes.get_mut(..len)?;
+        parse_hex_bytes(data, bytes)?;
+        target.write_memory(address, bytes).ok()
+    }
+
+    /// Shows the bytes the `int3`s replaced in memory read at `address`,
+    /// so that gdb disassembles the code as it really is.
+    fn hide_breakpoints(&self, address: u64, bytes: &mut [u8]) {
+        for breakpoint in self.breakpoints.iter().filter_map(|b| *b) {
+            let offset = breakpoint.address.wrapping_sub(address);
+            if offset < bytes.len() as u64 && Some(breakpoint.address) != self.step_over {
+                bytes[offset as usize] = breakpoint.original;
+            }
+        }
+    }
+
+    fn insert_breakpoint<T: Target>(&mut self, target: &mut T, address: u64) -> Option<()> {
+        if self.is_breakpoint(address) {
+            return Some(());
+        }
+
+        let slot = self.breakpoints.iter_mut().find(|b| b.is_none())?;
+        let mut original = [0u8];
+        target.read_memory(address, &mut original).ok()?;
+        target.write_memory(address, &[INT3]).ok()?;
+        *slot = Some(Breakpoint { address, original: original[0] });
+        Some(())
+    }
+
+    fn remove_breakpoint<T: Target>(&mut self, target: &mut T, address: u64) -> Option<()> {
+        let slot = self.breakpoints.iter_mut()
+            .find(|b| b.map(|b| b.address) == Some(address))?;
+        let breakpoint = slot.take()?;
+        if self.step_over == Some(address) {
+            // already lifted
+            self.step_over = None;
+        } else {
+            target.write_memory(address, &[breakpoint.original]).ok()?;
+        }
+        Some(())
+    }
+
+    /// Removes every breakpoint from memory.
+    pub fn remove_all<T: Target>(&mut self, target: &mut T) {
+        for index in 0..MAX_BREAKPOINTS {
+            if let Some(breakpoint) = self.breakpoints[index] {
+                let _ = self.remove_breakpoint(target, breakpoint.address);
+            }
+        }
+    }
+
+    fn resume<T: Target>(&mut self, target: &mut T, resume: Resume) {
+        if resume == Resume::Detach {
+            self.remove_all(target);
+            self.attached = false;
+        }
+
+        let mut registers = target.registers();
+        let rip = registers.values[register::RIP];
+        let original = self.breakpoints.iter().filter_map(|b| *b)
+            .find(|b| b.address == rip)
+            .map(|b| b.original);
+
+        // an int3 where we go on from would just stop us again: take it out
+        // for one instruction
+        if let Some(original) = original {
+            let _ = target.write_memory(rip, &[original]);
+            self.step_over = Some(rip);
+        }
+
+        self.stepping = resume == Resume::Step;
+        if self.stepping || self.step_over.is_some() {
+            registers.values[register::EFLAGS] |= FLAGS_TF;
+        } else {
+            registers.values[register::EFLAGS] &= !FLAGS_TF;
+        }
+        target.set_registers(&registers);
+    }
+}
+
+impl Default for Stub {
+    fn default() -> Stub {
+        Stub::new()
+    }
+}
+
+fn le_bytes(value: u64) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for (index, b) in bytes.iter_mut().enumerate() {
+        *b = (value >> (index * 8)) as u8;
+    }
+    bytes
+}
+
+fn from_le_bytes(bytes: &[u8; 8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// gdb's side of the line: packets to send, and everything the stub
+    /// answered.
+    struct Line {
+        input: [u8; 512],
+        input_len: usize,
+        read: usize,
+        output: [u8; 2048],
+        output_len: usize,
+    }
+
+    impl Line {
+        fn new(packets: &[&[u8]]) -> Line {
+            let mut line = Line {
+                input: [0; 512],
+                input_len: 0,
+                read: 0,
+                output: [0; 2048],
+                output_len: 0,
+            };
+            for packet in packets {
+                let sum = packet.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                line.push(b"$");
+                line.push(packet);
+                line.push(&[b'#', HEX[(sum >> 4) as usize], HEX[(sum & 0xF) as usize], b'+']);
+            }
+            line
+        }
+
+        fn push(&mut self, bytes: &[u8]) {
+            self.input[self.input_len..self.input_len + bytes.len()].copy_from_slice(bytes);
+            self.input_len += bytes.len();
+        }
+
+        fn output(&self) -> &[u8] {
+            &self.output[..self.output_len]
+        }
+    }
+
+    impl Connection for Line {
+        fn read_byte(&mut self) -> u8 {
+            assert!(self.read < self.input_len, "stub wants more input after {:?}",
+                core::str::from_utf8(self.output()));
+            self.read += 1;
+            self.input[self.read - 1]
+        }
+
+        fn write_byte(&mut self, b: u8) {
+            self.output[self.output_len] = b;
+            self.output_len += 1;
+        }
+    }
+
+    struct Machine {
+        registers: Registers,
+        memory: [u8; 64],
+    }
+
+    impl Target for Machine {
+        fn registers(&self) -> Registers {
+            self.registers
+        }
+
+        fn set_registers(&mut self, registers: &Registers) {
+            self.registers = *registers;
+        }
+
+        fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), ()> {
+            let start = address as usize;
+            let memory = self.memory.get(start..start + bytes.len()).ok_or(())?;
+            bytes.copy_from_slice(memory);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, address: u64, bytes: &[u8]) -> Result<(), ()> {
+            let start = address as usize;
+            self.memory.get_mut(start..start + bytes.len()).ok_or(())?.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn machine() -> Machine {
+        let mut machine = Machine { registers: Registers::default(), memory: [0x90; 64] };
+        machine.registers.values[register::RIP] = 0x10;
+        machine
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut machine = machine();
+        machine.registers.values[register::RAX] = 0x1122334455667788;
+        let mut line = Line::new(&[b"?", b"p0", b"P10=2000000000000000", b"M8,2:abcd", b"m8,3", b"m100,1", b"c"]);
+
+        let resume = Stub::new().run(&mut line, &mut machine, None);
+        assert_eq!(resume, Resume::Continue);
+        assert_eq!(machine.registers.values[register::RIP], 0x20);
+        assert_eq!(&machine.memory[8..10], &[0xab, 0xcd]);
+
+        let output = line.output();
+        assert!(contains(output, b"$S05#b8"));
+        assert!(contains(output, b"$8877665544332211#"));
+        assert!(contains(output, b"$abcd90#"));
+        assert!(contains(output, b"$E01#"));
+    }
+
+    #[test]
+    fn all_registers() {
+        let mut machine = machine();
+        let mut line = Line::new(&[b"g", b"s"]);
+        Stub::new().run(&mut line, &mut machine, None);
+        // 17 registers of 8 bytes and 7 of 4, two digits a byte, plus "$"
+        // and the acknowledgements
+        let start = line.output().iter().position(|&b| b == b'$').unwrap();
+        let end = line.output().iter().position(|&b| b == b'#').unwrap();
+        assert_eq!(end - start - 1, (17 * 8 + 7 * 4) * 2);
+        assert_ne!(machine.registers.values[register::EFLAGS] & FLAGS_TF, 0);
+    }
+
+    #[test]
+    fn breakpoints_are_stepped_over() {
+        let mut machine = machine();
+        let mut stub = Stub::new();
+        let mut line = Line::new(&[b"Z0,18,1", b"m18,1", b"c"]);
+        stub.run(&mut line, &mut machine, None);
+        assert_eq!(machine.memory[0x18], INT3);
+        assert!(contains(line.output(), b"$90#"));
+        assert_eq!(machine.registers.values[register::EFLAGS] & FLAGS_TF, 0);
+
+        // the int3 goes off
+        machine.registers.values[register::RIP] = 0x19;
+        let mut line = Line::new(&[b"c"]);
+        assert!(stub.on_breakpoint(&mut line, &mut machine));
+        assert!(contains(line.output(), b"$S05#"));
+        assert_eq!(machine.registers.values[register::RIP], 0x18);
+
+        // continuing runs the real instruction with a single step first
+        assert_eq!(machine.memory[0x18], 0x90);
+        assert_ne!(machine.registers.values[register::EFLAGS] & FLAGS_TF, 0);
+        machine.registers.values[register::RIP] = 0x19;
+        let mut line = Line::new(&[]);
+        assert!(stub.on_step(&mut line, &mut machine));
+        assert_eq!(machine.memory[0x18], INT3);
+        assert_eq!(machine.registers.values[register::EFLAGS] & FLAGS_TF, 0);
+
+        // a trap nobody asked for isn't ours
+        assert!(!stub.on_step(&mut line, &mut machine));
+        machine.registers.values[register::RIP] = 0x31;
+        assert!(!stub.on_breakpoint(&mut line, &mut machine));
+
+        // detaching leaves the code as it was
+        let mut line = Line::new(&[b"D"]);
+        stub.run(&mut line, &mut machine, None);
+        assert!(contains(line.output(), b"$OK#"));
+        assert_eq!(machine.memory[0x18], 0x90);
+        assert!(!stub.is_attached());
+    }
+
+    #[test]
+    fn bad_checksums_are_refused() {
+        let mut machine = machine();
+        let mut line = Line::new(&[]);
+        line.push(b"$?#00");
+        line.push(b"$?#3f+");
+        line.push(b"$c#63");
+        Stub::new().run(&mut line, &mut machine, None);
+        assert!(line.output().starts_with(b"-+$S05#b8+"));
+    }
+}
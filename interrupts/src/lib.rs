@@ -66,6 +66,10 @@ pub struct InterruptState {
     pub rip: *mut usize,
     pub cs: *mut usize,
     pub flags: *mut usize,
+    /// The interrupted code's stack, which the CPU saves on every interrupt
+    /// in long mode, not just on a change of privilege level.
+    pub rsp: *mut usize,
+    pub ss: *mut usize,
 }
 
 // impl core::fmt::Debug for InterruptState {
@@ -75,13 +79,34 @@ pub struct InterruptState {
 // }
 
 
+/// Number of times each vector has fired. Only touched with interrupts
+/// disabled on our single CPU, so plain increments cannot race.
+static mut INTERRUPT_COUNTS: [usize; 256] = [0; 256];
+
+/// Counts an interrupt on `vector`; called by the handlers that
+/// `make_idt_entry!` generates.
+pub fn record_interrupt(vector: usize) {
+    unsafe {
+        INTERRUPT_COUNTS[vector] += 1;
+    }
+}
+
+/// Returns how many times `vector` has fired.
+pub fn interrupt_count(vector: usize) -> usize {
+    unsafe {
+        core::ptr::read_volatile(&INTERRUPT_COUNTS[vector])
+    }
+}
+
 /// Creates an IDT entry.
 ///
-/// Creates an IDT entry that executes the expression in `body`.
+/// Creates an IDT entry for interrupt `vector` that executes the expression
+/// in `body`.
 #[macro_export]
 macro_rules! make_idt_entry {
-    ($name:ident, $body:expr) => {{
+    ($name:ident, $vector:expr, $body:expr) => {{
         extern "C" fn body(state: &mut interrupts::InterruptState) {
+            interrupts::record_interrupt($vector);
             $body(state)
         }
 
@@ -0,0 +1,279 @@
+//! Driver for the 8042 PS/2 controller.
+//!
+//! See http://wiki.osdev.org/%228042%22_PS/2_Controller
+
+use x86::shared::io::{inb, outb};
+
+// 8042 input/output ports
+pub const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+// status register bits
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+/// The byte in the output buffer came from the second (auxiliary) port.
+const STATUS_AUX_DATA: u8 = 0x20;
+
+// controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND_PORT: u8 = 0xA7;
+const ENABLE_SECOND_PORT: u8 = 0xA8;
+const TEST_SECOND_PORT: u8 = 0xA9;
+const SELF_TEST: u8 = 0xAA;
+const TEST_FIRST_PORT: u8 = 0xAB;
+const DISABLE_FIRST_PORT: u8 = 0xAD;
+const ENABLE_FIRST_PORT: u8 = 0xAE;
+const WRITE_SECOND_PORT: u8 = 0xD4;
+const PULSE_RESET: u8 = 0xFE;
+
+// configuration byte bits
+const CONFIG_FIRST_IRQ: u8 = 0x01;
+const CONFIG_SECOND_IRQ: u8 = 0x02;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 0x20;
+const CONFIG_TRANSLATION: u8 = 0x40;
+
+// device responses
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// keyboard commands
+pub const SET_LEDS: u8 = 0xED;
+const SCANCODE_SET: u8 = 0xF0;
+const SET_TYPEMATIC: u8 = 0xF3;
+const ENABLE_SCANNING: u8 = 0xF4;
+
+const TIMEOUT: usize = 100_000;
+const RETRIES: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The controller did not become ready in time.
+    Timeout,
+    /// The controller self-test (0xAA) did not return 0x55.
+    SelfTestFailed(u8),
+    /// A port interface test failed with the given code.
+    PortTestFailed(Port, u8),
+    /// The device kept asking for the command to be resent.
+    Resend,
+    /// The device answered a command with something other than ACK.
+    UnexpectedResponse(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Port {
+    First,
+    Second,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScancodeSet {
+    Set1 = 1,
+    Set2 = 2,
+    Set3 = 3,
+}
+
+/// What `init` found out about the controller.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Info {
+    pub dual_channel: bool,
+}
+
+fn wait_for_input_empty() -> Result<(), Error> {
+    for _ in 0..TIMEOUT {
+        if unsafe { inb(STATUS_PORT) } & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+fn wait_for_output_full() -> Result<(), Error> {
+    for _ in 0..TIMEOUT {
+        if unsafe { inb(STATUS_PORT) } & STATUS_OUTPUT_FULL != 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+fn command(cmd: u8) -> Result<(), Error> {
+    wait_for_input_empty()?;
+    unsafe { outb(COMMAND_PORT, cmd) };
+    Ok(())
+}
+
+fn write_data(value: u8) -> Result<(), Error> {
+    wait_for_input_empty()?;
+    unsafe { outb(DATA_PORT, value) };
+    Ok(())
+}
+
+/// Reads the next byte from the output buffer, waiting for it to arrive.
+pub fn read_data() -> Result<u8, Error> {
+    wait_for_output_full()?;
+    Ok(unsafe { inb(DATA_PORT) })
+}
+
+fn command_with_response(cmd: u8) -> Result<u8, Error> {
+    command(cmd)?;
+    read_data()
+}
+
+/// Returns `true` if a byte is waiting in the output buffer.
+pub fn has_data() -> bool {
+    unsafe { inb(STATUS_PORT) & STATUS_OUTPUT_FULL != 0 }
+}
+
+/// Returns `true` if a byte from the device on `port` is waiting in the
+/// output buffer, so that the keyboard and mouse don't take each other's.
+pub fn has_data_from(port: Port) -> bool {
+    let status = unsafe { inb(STATUS_PORT) };
+    let aux = status & STATUS_AUX_DATA != 0;
+    status & STATUS_OUTPUT_FULL != 0 && aux == (port == Port::Second)
+}
+
+/// Discards anything left over in the output buffer.
+fn flush_output() {
+    while has_data() {
+        unsafe { inb(DATA_PORT) };
+    }
+}
+
+pub fn read_config() -> Result<u8, Error> {
+    command_with_response(READ_CONFIG)
+}
+
+pub fn write_config(config: u8) -> Result<(), Error> {
+    command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sends a byte to the device on `port` and waits for it to be acknowledged,
+/// resending it if the device asks for that.
+pub fn send(port: Port, value: u8) -> Result<(), Error> {
+    for _ in 0..RETRIES {
+        if port == Port::Second {
+            command(WRITE_SECOND_PORT)?;
+        }
+        write_data(value)?;
+
+        match read_data()? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            other => return Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    Err(Error::Resend)
+}
+
+/// Brings the controller into a known state: runs the self-tests, enables
+/// the ports that passed and turns on their interrupts. Scan code
+/// translation stays enabled so that keyboards in set 2 deliver set 1.
+pub fn init() -> Result<Info, Error> {
+    command(DISABLE_FIRST_PORT)?;
+    command(DISABLE_SECOND_PORT)?;
+    flush_output();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+    write_config(config)?;
+
+    match command_with_response(SELF_TEST)? {
+        SELF_TEST_PASSED => {},
+        other => return Err(Error::SelfTestFailed(other)),
+    }
+    // some controllers reset their configuration during the self-test
+    write_config(config)?;
+
+    // the second clock only turns on if there is a second port
+    let dual_channel = if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+        command(ENABLE_SECOND_PORT)?;
+        let dual = read_config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        command(DISABLE_SECOND_PORT)?;
+        dual
+    } else {
+        false
+    };
+
+    match command_with_response(TEST_FIRST_PORT)? {
+        PORT_TEST_PASSED => {},
+        other => return Err(Error::PortTestFailed(Port::First, other)),
+    }
+    if dual_channel {
+        match command_with_response(TEST_SECOND_PORT)? {
+            PORT_TEST_PASSED => {},
+            other => return Err(Error::PortTestFailed(Port::Second, other)),
+        }
+    }
+
+    command(ENABLE_FIRST_PORT)?;
+    config |= CONFIG_FIRST_IRQ | CONFIG_TRANSLATION;
+    write_config(config)?;
+
+    Ok(Info { dual_channel })
+}
+
+/// Enables the second (auxiliary) port and its interrupt.
+pub fn enable_second_port() -> Result<(), Error> {
+    command(ENABLE_SECOND_PORT)?;
+    let config = read_config()?;
+    write_config(config | CONFIG_SECOND_IRQ)
+}
+
+pub fn disable_port(port: Port) -> Result<(), Error> {
+    match port {
+        Port::First => command(DISABLE_FIRST_PORT),
+        Port::Second => command(DISABLE_SECOND_PORT),
+    }
+}
+
+pub fn enable_port(port: Port) -> Result<(), Error> {
+    match port {
+        Port::First => command(ENABLE_FIRST_PORT),
+        Port::Second => command(ENABLE_SECOND_PORT),
+    }
+}
+
+/// Selects the scan code set used by the keyboard.
+pub fn set_scancode_set(set: ScancodeSet) -> Result<(), Error> {
+    send(Port::First, SCANCODE_SET)?;
+    send(Port::First, set as u8)
+}
+
+/// Sets the typematic repeat `rate` (0 = 30 Hz .. 31 = 2 Hz) and the
+/// `delay` before repeating starts (0 = 250 ms .. 3 = 1000 ms).
+pub fn set_typematic(rate: u8, delay: u8) -> Result<(), Error> {
+    send(Port::First, SET_TYPEMATIC)?;
+    send(Port::First, ((delay & 0x3) << 5) | (rate & 0x1F))
+}
+
+/// The byte that follows `SET_LEDS` for the given lock states.
+pub fn leds(scroll_lock: bool, num_lock: bool, caps_lock: bool) -> u8 {
+    (scroll_lock as u8) | ((num_lock as u8) << 1) | ((caps_lock as u8) << 2)
+}
+
+/// Sets the keyboard LEDs from the state of the lock keys.
+pub fn set_leds(scroll_lock: bool, num_lock: bool, caps_lock: bool) -> Result<(), Error> {
+    send(Port::First, SET_LEDS)?;
+    send(Port::First, leds(scroll_lock, num_lock, caps_lock))
+}
+
+/// Sends a byte to the keyboard without waiting for its acknowledgement,
+/// for the interrupt handler, which gets the ACK as its next byte.
+pub fn send_no_wait(value: u8) -> Result<(), Error> {
+    write_data(value)
+}
+
+pub fn enable_scanning() -> Result<(), Error> {
+    send(Port::First, ENABLE_SCANNING)
+}
+
+/// Resets the CPU by pulsing the reset line through the controller.
+pub fn reset_cpu() {
+    let _result = command(PULSE_RESET);
+}
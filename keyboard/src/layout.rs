@@ -0,0 +1,376 @@
+//! Keyboard layouts.
+//!
+//! A layout maps PS/2 scan code set 1 make codes onto the characters printed
+//! on the key caps. Every key has an unshifted, a shifted and an AltGr
+//! (right Alt) level; `NONE` marks a level that produces nothing.
+//!
+//! Dead keys are written into the tables as Unicode combining marks
+//! (U+0300..U+036F). They produce no output on their own and instead modify
+//! the next key pressed, see `compose`.
+
+pub const NONE: char = '\0';
+
+const GRAVE: char = '\u{300}';
+const ACUTE: char = '\u{301}';
+const CIRCUMFLEX: char = '\u{302}';
+
+pub struct Layout {
+    pub name: &'static str,
+    /// (make code, unshifted, shifted, AltGr)
+    keys: &'static [(u8, char, char, char)],
+}
+
+/// The modifier levels of a key press.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Level {
+    Normal,
+    Shifted,
+    AltGr,
+}
+
+impl Layout {
+    /// Looks up the character for `code` at `level`, falling back to the
+    /// keys shared by all layouts (Enter, Space, Backspace, keypad operators).
+    pub fn map(&self, code: u8, level: Level) -> Option<char> {
+        let entry = self.keys.iter().chain(COMMON.iter()).find(|k| k.0 == code)?;
+
+        let c = match level {
+            Level::Normal => entry.1,
+            Level::Shifted => entry.2,
+            Level::AltGr => entry.3,
+        };
+
+        if c == NONE {
+            None
+        } else {
+            Some(c)
+        }
+    }
+
+    /// Whether Caps Lock applies to `code`: its shifted character is the
+    /// upper case of its unshifted one, as for letters but not for e.g. the
+    /// German `ß`, whose shifted character is `?`.
+    pub fn has_case_pair(&self, code: u8) -> bool {
+        match (self.map(code, Level::Normal), self.map(code, Level::Shifted)) {
+            (Some(normal), Some(shifted)) if normal != shifted => {
+                let mut upper = normal.to_uppercase();
+                upper.next() == Some(shifted) && upper.next().is_none()
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Returns `true` if `c` is a dead key rather than a printable character.
+pub fn is_dead_key(c: char) -> bool {
+    c >= '\u{300}' && c <= '\u{36f}'
+}
+
+/// The character a dead key produces when followed by a key it does not
+/// combine with (or by Space).
+pub fn spacing_form(dead: char) -> char {
+    match dead {
+        GRAVE => '`',
+        ACUTE => '´',
+        CIRCUMFLEX => '^',
+        _ => '?',
+    }
+}
+
+/// Combines a pending dead key with the following character.
+pub fn compose(dead: char, c: char) -> Option<char> {
+    COMPOSE
+        .iter()
+        .find(|entry| entry.0 == dead && entry.1 == c)
+        .map(|entry| entry.2)
+}
+
+const COMPOSE: &[(char, char, char)] = &[
+    (GRAVE, 'a', 'à'), (GRAVE, 'e', 'è'), (GRAVE, 'i', 'ì'), (GRAVE, 'o', 'ò'), (GRAVE, 'u', 'ù'),
+    (GRAVE, 'A', 'À'), (GRAVE, 'E', 'È'), (GRAVE, 'I', 'Ì'), (GRAVE, 'O', 'Ò'), (GRAVE, 'U', 'Ù'),
+    (ACUTE, 'a', 'á'), (ACUTE, 'e', 'é'), (ACUTE, 'i', 'í'), (ACUTE, 'o', 'ó'), (ACUTE, 'u', 'ú'),
+    (ACUTE, 'A', 'Á'), (ACUTE, 'E', 'É'), (ACUTE, 'I', 'Í'), (ACUTE, 'O', 'Ó'), (ACUTE, 'U', 'Ú'),
+    (ACUTE, 'y', 'ý'), (ACUTE, 'Y', 'Ý'),
+    (CIRCUMFLEX, 'a', 'â'), (CIRCUMFLEX, 'e', 'ê'), (CIRCUMFLEX, 'i', 'î'), (CIRCUMFLEX, 'o', 'ô'), (CIRCUMFLEX, 'u', 'û'),
+    (CIRCUMFLEX, 'A', 'Â'), (CIRCUMFLEX, 'E', 'Ê'), (CIRCUMFLEX, 'I', 'Î'), (CIRCUMFLEX, 'O', 'Ô'), (CIRCUMFLEX, 'U', 'Û'),
+];
+
+/// Keys that are the same in every layout.
+const COMMON: &[(u8, char, char, char)] = &[
+    (0x01, '\x1b', '\x1b', NONE), // Escape
+    (0x0e, '\x08', '\x08', NONE), // Backspace
+    (0x0f, '\t', '\t', NONE),
+    (0x1c, '\n', '\n', NONE),
+    (0x39, ' ', ' ', ' '),
+    (0x37, '*', '*', NONE), // Keypad
+    (0x4a, '-', '-', NONE), // Keypad
+    (0x4e, '+', '+', NONE), // Keypad
+];
+
+pub static US: Layout = Layout {
+    name: "us",
+    keys: &[
+        (0x29, '`', '~', NONE),
+        (0x02, '1', '!', NONE),
+        (0x03, '2', '@', NONE),
+        (0x04, '3', '#', NONE),
+        (0x05, '4', '$', NONE),
+        (0x06, '5', '%', NONE),
+        (0x07, '6', '^', NONE),
+        (0x08, '7', '&', NONE),
+        (0x09, '8', '*', NONE),
+        (0x0a, '9', '(', NONE),
+        (0x0b, '0', ')', NONE),
+        (0x0c, '-', '_', NONE),
+        (0x0d, '=', '+', NONE),
+        (0x10, 'q', 'Q', NONE),
+        (0x11, 'w', 'W', NONE),
+        (0x12, 'e', 'E', NONE),
+        (0x13, 'r', 'R', NONE),
+        (0x14, 't', 'T', NONE),
+        (0x15, 'y', 'Y', NONE),
+        (0x16, 'u', 'U', NONE),
+        (0x17, 'i', 'I', NONE),
+        (0x18, 'o', 'O', NONE),
+        (0x19, 'p', 'P', NONE),
+        (0x1a, '[', '{', NONE),
+        (0x1b, ']', '}', NONE),
+        (0x2b, '\\', '|', NONE),
+        (0x1e, 'a', 'A', NONE),
+        (0x1f, 's', 'S', NONE),
+        (0x20, 'd', 'D', NONE),
+        (0x21, 'f', 'F', NONE),
+        (0x22, 'g', 'G', NONE),
+        (0x23, 'h', 'H', NONE),
+        (0x24, 'j', 'J', NONE),
+        (0x25, 'k', 'K', NONE),
+        (0x26, 'l', 'L', NONE),
+        (0x27, ';', ':', NONE),
+        (0x28, '\'', '"', NONE),
+        (0x56, '\\', '|', NONE),
+        (0x2c, 'z', 'Z', NONE),
+        (0x2d, 'x', 'X', NONE),
+        (0x2e, 'c', 'C', NONE),
+        (0x2f, 'v', 'V', NONE),
+        (0x30, 'b', 'B', NONE),
+        (0x31, 'n', 'N', NONE),
+        (0x32, 'm', 'M', NONE),
+        (0x33, ',', '<', NONE),
+        (0x34, '.', '>', NONE),
+        (0x35, '/', '?', NONE),
+    ],
+};
+
+pub static UK: Layout = Layout {
+    name: "uk",
+    keys: &[
+        (0x29, '`', '¬', '¦'),
+        (0x02, '1', '!', NONE),
+        (0x03, '2', '"', NONE),
+        (0x04, '3', '£', NONE),
+        (0x05, '4', '$', '€'),
+        (0x06, '5', '%', NONE),
+        (0x07, '6', '^', NONE),
+        (0x08, '7', '&', NONE),
+        (0x09, '8', '*', NONE),
+        (0x0a, '9', '(', NONE),
+        (0x0b, '0', ')', NONE),
+        (0x0c, '-', '_', NONE),
+        (0x0d, '=', '+', NONE),
+        (0x10, 'q', 'Q', NONE),
+        (0x11, 'w', 'W', NONE),
+        (0x12, 'e', 'E', 'é'),
+        (0x13, 'r', 'R', NONE),
+        (0x14, 't', 'T', NONE),
+        (0x15, 'y', 'Y', NONE),
+        (0x16, 'u', 'U', 'ú'),
+        (0x17, 'i', 'I', 'í'),
+        (0x18, 'o', 'O', 'ó'),
+        (0x19, 'p', 'P', NONE),
+        (0x1a, '[', '{', NONE),
+        (0x1b, ']', '}', NONE),
+        (0x1e, 'a', 'A', 'á'),
+        (0x1f, 's', 'S', NONE),
+        (0x20, 'd', 'D', NONE),
+        (0x21, 'f', 'F', NONE),
+        (0x22, 'g', 'G', NONE),
+        (0x23, 'h', 'H', NONE),
+        (0x24, 'j', 'J', NONE),
+        (0x25, 'k', 'K', NONE),
+        (0x26, 'l', 'L', NONE),
+        (0x27, ';', ':', NONE),
+        (0x28, '\'', '@', NONE),
+        (0x2b, '#', '~', NONE),
+        (0x56, '\\', '|', NONE),
+        (0x2c, 'z', 'Z', NONE),
+        (0x2d, 'x', 'X', NONE),
+        (0x2e, 'c', 'C', NONE),
+        (0x2f, 'v', 'V', NONE),
+        (0x30, 'b', 'B', NONE),
+        (0x31, 'n', 'N', NONE),
+        (0x32, 'm', 'M', NONE),
+        (0x33, ',', '<', NONE),
+        (0x34, '.', '>', NONE),
+        (0x35, '/', '?', NONE),
+    ],
+};
+
+pub static DE: Layout = Layout {
+    name: "de",
+    keys: &[
+        (0x29, CIRCUMFLEX, '°', NONE),
+        (0x02, '1', '!', NONE),
+        (0x03, '2', '"', '²'),
+        (0x04, '3', '§', '³'),
+        (0x05, '4', '$', NONE),
+        (0x06, '5', '%', NONE),
+        (0x07, '6', '&', NONE),
+        (0x08, '7', '/', '{'),
+        (0x09, '8', '(', '['),
+        (0x0a, '9', ')', ']'),
+        (0x0b, '0', '=', '}'),
+        (0x0c, 'ß', '?', '\\'),
+        (0x0d, ACUTE, GRAVE, NONE),
+        (0x10, 'q', 'Q', '@'),
+        (0x11, 'w', 'W', NONE),
+        (0x12, 'e', 'E', '€'),
+        (0x13, 'r', 'R', NONE),
+        (0x14, 't', 'T', NONE),
+        (0x15, 'z', 'Z', NONE),
+        (0x16, 'u', 'U', NONE),
+        (0x17, 'i', 'I', NONE),
+        (0x18, 'o', 'O', NONE),
+        (0x19, 'p', 'P', NONE),
+        (0x1a, 'ü', 'Ü', NONE),
+        (0x1b, '+', '*', '~'),
+        (0x1e, 'a', 'A', NONE),
+        (0x1f, 's', 'S', NONE),
+        (0x20, 'd', 'D', NONE),
+        (0x21, 'f', 'F', NONE),
+        (0x22, 'g', 'G', NONE),
+        (0x23, 'h', 'H', NONE),
+        (0x24, 'j', 'J', NONE),
+        (0x25, 'k', 'K', NONE),
+        (0x26, 'l', 'L', NONE),
+        (0x27, 'ö', 'Ö', NONE),
+        (0x28, 'ä', 'Ä', NONE),
+        (0x2b, '#', '\'', NONE),
+        (0x56, '<', '>', '|'),
+        (0x2c, 'y', 'Y', NONE),
+        (0x2d, 'x', 'X', NONE),
+        (0x2e, 'c', 'C', NONE),
+        (0x2f, 'v', 'V', NONE),
+        (0x30, 'b', 'B', NONE),
+        (0x31, 'n', 'N', NONE),
+        (0x32, 'm', 'M', 'µ'),
+        (0x33, ',', ';', NONE),
+        (0x34, '.', ':', NONE),
+        (0x35, '-', '_', NONE),
+    ],
+};
+
+pub static DVORAK: Layout = Layout {
+    name: "dvorak",
+    keys: &[
+        (0x29, '`', '~', NONE),
+        (0x02, '1', '!', NONE),
+        (0x03, '2', '@', NONE),
+        (0x04, '3', '#', NONE),
+        (0x05, '4', '$', NONE),
+        (0x06, '5', '%', NONE),
+        (0x07, '6', '^', NONE),
+        (0x08, '7', '&', NONE),
+        (0x09, '8', '*', NONE),
+        (0x0a, '9', '(', NONE),
+        (0x0b, '0', ')', NONE),
+        (0x0c, '[', '{', NONE),
+        (0x0d, ']', '}', NONE),
+        (0x10, '\'', '"', NONE),
+        (0x11, ',', '<', NONE),
+        (0x12, '.', '>', NONE),
+        (0x13, 'p', 'P', NONE),
+        (0x14, 'y', 'Y', NONE),
+        (0x15, 'f', 'F', NONE),
+        (0x16, 'g', 'G', NONE),
+        (0x17, 'c', 'C', NONE),
+        (0x18, 'r', 'R', NONE),
+        (0x19, 'l', 'L', NONE),
+        (0x1a, '/', '?', NONE),
+        (0x1b, '=', '+', NONE),
+        (0x2b, '\\', '|', NONE),
+        (0x1e, 'a', 'A', NONE),
+        (0x1f, 'o', 'O', NONE),
+        (0x20, 'e', 'E', NONE),
+        (0x21, 'u', 'U', NONE),
+        (0x22, 'i', 'I', NONE),
+        (0x23, 'd', 'D', NONE),
+        (0x24, 'h', 'H', NONE),
+        (0x25, 't', 'T', NONE),
+        (0x26, 'n', 'N', NONE),
+        (0x27, 's', 'S', NONE),
+        (0x28, '-', '_', NONE),
+        (0x56, '\\', '|', NONE),
+        (0x2c, ';', ':', NONE),
+        (0x2d, 'q', 'Q', NONE),
+        (0x2e, 'j', 'J', NONE),
+        (0x2f, 'k', 'K', NONE),
+        (0x30, 'x', 'X', NONE),
+        (0x31, 'b', 'B', NONE),
+        (0x32, 'm', 'M', NONE),
+        (0x33, 'w', 'W', NONE),
+        (0x34, 'v', 'V', NONE),
+        (0x35, 'z', 'Z', NONE),
+    ],
+};
+
+/// All built-in layouts, for selecting one by name at runtime.
+pub static LAYOUTS: [&Layout; 4] = [&US, &UK, &DE, &DVORAK];
+
+pub fn by_name(name: &str) -> Option<&'static Layout> {
+    LAYOUTS.iter().find(|l| l.name == name).map(|l| *l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifted_symbols() {
+        assert_eq!(US.map(0x02, Level::Shifted), Some('!'));
+        assert_eq!(US.map(0x27, Level::Shifted), Some(':'));
+        assert_eq!(UK.map(0x04, Level::Shifted), Some('£'));
+        assert_eq!(DE.map(0x15, Level::Normal), Some('z'));
+        assert_eq!(DVORAK.map(0x10, Level::Normal), Some('\''));
+    }
+
+    #[test]
+    fn altgr_and_common_keys() {
+        assert_eq!(DE.map(0x10, Level::AltGr), Some('@'));
+        assert_eq!(US.map(0x10, Level::AltGr), None);
+        assert_eq!(DVORAK.map(0x1c, Level::Normal), Some('\n'));
+    }
+
+    #[test]
+    fn case_pairs() {
+        assert!(US.has_case_pair(0x1e));
+        assert!(DE.has_case_pair(0x27));
+        assert!(!DE.has_case_pair(0x0c));
+        assert!(!US.has_case_pair(0x02));
+        assert!(!US.has_case_pair(0x39));
+    }
+
+    #[test]
+    fn dead_keys() {
+        let circumflex = DE.map(0x29, Level::Normal).unwrap();
+        assert!(is_dead_key(circumflex));
+        assert_eq!(compose(circumflex, 'e'), Some('ê'));
+        assert_eq!(compose(circumflex, 'x'), None);
+        assert_eq!(spacing_form(circumflex), '^');
+    }
+
+    #[test]
+    fn select_by_name() {
+        assert_eq!(by_name("dvorak").map(|l| l.name), Some("dvorak"));
+        assert!(by_name("xx").is_none());
+    }
+}
@@ -1,7 +1,7 @@
 #![no_std]
 
 extern crate common;
-use common::{InterruptData,Queue};
+use common::{Event,InterruptData,Queue,Waiter};
 
 extern crate spin;
 use spin::Mutex;
@@ -9,21 +9,94 @@ use spin::Mutex;
 extern crate x86;
 use x86::shared::io::{inb};
 
+pub mod controller;
+pub mod layout;
+
+use layout::{Layout, Level};
+
 #[derive(Clone,Copy)]
 pub struct ScanCode(u8);
 
+/// Where the interrupt handler is in updating the LEDs: the command and its
+/// argument each wait for an ACK, which arrives as the next interrupt.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum LedUpdate {
+    Idle,
+    /// `controller::SET_LEDS` was sent.
+    Command,
+    /// The LED byte was sent.
+    Leds(u8),
+}
+
+/// Key combinations the kernel acts on itself instead of treating them as
+/// input, see `Keyboard::read_hotkey`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    /// Alt+F1..F6: bring virtual console 0..5 to the screen.
+    SwitchConsole(usize),
+    /// Shift+Page Up: show older lines of the active console.
+    ScrollBack,
+    /// Shift+Page Down: go back towards the live view.
+    ScrollForward,
+}
+
+impl Default for Hotkey {
+    fn default() -> Hotkey {
+        Hotkey::SwitchConsole(0)
+    }
+}
+
+/// Number of decoded characters buffered until `read_key` picks them up.
+pub const BUFFER_SIZE: usize = 128;
+
 struct KeyboardData {
-    in_queue: Queue<char>,
+    in_queue: Queue<char, [char; BUFFER_SIZE]>,
+    hotkeys: Queue<Hotkey>,
+    dropped: usize,
+    layout: &'static Layout,
     lshift: bool,
     rshift: bool,
+    lctrl: bool,
+    rctrl: bool,
+    lalt: bool,
+    altgr: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    /// Lock keys held down, whose typematic repeats don't toggle them again.
+    caps_held: bool,
+    num_held: bool,
+    scroll_held: bool,
+    leds: LedUpdate,
+    /// The lock keys changed while an update was in flight.
+    leds_outdated: bool,
+    extended: bool,
+    dead_key: Option<char>,
 }
 
 impl KeyboardData {
     pub fn new() -> KeyboardData {
         KeyboardData {
-            in_queue: Queue::<char>::new(),
+            in_queue: Queue::new(),
+            hotkeys: Queue::new(),
+            dropped: 0,
+            layout: &layout::US,
             lshift: false,
             rshift: false,
+            lctrl: false,
+            rctrl: false,
+            lalt: false,
+            altgr: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+            caps_held: false,
+            num_held: false,
+            scroll_held: false,
+            leds: LedUpdate::Idle,
+            leds_outdated: false,
+            extended: false,
+            dead_key: None,
         }
     }
 
@@ -35,81 +108,179 @@ impl KeyboardData {
     /// Reference table:
     ///   http://www.computer-engineering.org/ps2keyboard/scancodes1.html
     fn from_scancode(&self, code: ScanCode) -> Option<char> {
-        let mut printable = match code.0 {
-            0x1e => 'a',
-            0x30 => 'b',
-            0x2e => 'c',
-            0x20 => 'd',
-            0x12 => 'e',
-            0x21 => 'f',
-            0x22 => 'g',
-            0x23 => 'h',
-            0x17 => 'i',
-            0x24 => 'j',
-            0x25 => 'k',
-            0x26 => 'l',
-            0x32 => 'm',
-            0x31 => 'n',
-            0x18 => 'o',
-            0x19 => 'p',
-            0x10 => 'q',
-            0x13 => 'r',
-            0x1f => 's',
-            0x14 => 't',
-            0x16 => 'u',
-            0x2f => 'v',
-            0x11 => 'w',
-            0x2d => 'x',
-            0x15 => 'y',
-            0x2c => 'z',
-            0x0b => '0',
-            0x02 => '1',
-            0x03 => '2',
-            0x04 => '3',
-            0x05 => '4',
-            0x06 => '5',
-            0x07 => '6',
-            0x08 => '7',
-            0x09 => '8',
-            0x0a => '9',
-            0x29 => '`',
-            0x0c => '-',
-            0x0d => '=',
-            28 => '\n',
-            0x2b => '\\',
-            0x39 => ' ',
-            0x1a => '[',
-            0x1b => ']',
-            0x27 => ';',
-            0x28 => '\'',
-            0x33 => ',',
-            0x34 => '.',
-            0x35 => '/',
-            0x37 => '*', // Keypad
-            0x4a => '-', // Keypad
-            0x4e => '+', // Keypad
-            _ => { return None; }
-        };
+        if self.extended {
+            // The keypad duplicates of Enter and '/' are the only extended
+            // keys that produce characters.
+            return match code.0 {
+                0x1c => Some('\n'),
+                0x35 => Some('/'),
+                _ => None,
+            };
+        }
 
-        if self.lshift || self.rshift {
-            printable = match printable {
-                a @ 'a' ..= 'z' => (a as u8 - ('a' as u8 - 'A' as u8)) as char,
-                a @ _ => a,
+        if self.num_lock {
+            let digit = match code.0 {
+                0x47 => Some('7'),
+                0x48 => Some('8'),
+                0x49 => Some('9'),
+                0x4b => Some('4'),
+                0x4c => Some('5'),
+                0x4d => Some('6'),
+                0x4f => Some('1'),
+                0x50 => Some('2'),
+                0x51 => Some('3'),
+                0x52 => Some('0'),
+                0x53 => Some('.'),
+                _ => None,
             };
+            if digit.is_some() {
+                return digit;
+            }
+        }
+
+        let shift = self.lshift || self.rshift;
+        let level = if self.altgr {
+            Level::AltGr
+        } else if shift {
+            Level::Shifted
+        } else {
+            Level::Normal
+        };
+
+        // Caps Lock inverts Shift, but only for letters with an upper case
+        // on their shifted level.
+        if self.caps_lock && level != Level::AltGr && self.layout.has_case_pair(code.0) {
+            let level = if shift { Level::Normal } else { Level::Shifted };
+            return self.layout.map(code.0, level);
+        }
+
+        self.layout.map(code.0, level)
+    }
+
+    /// Keys without a character of their own are reported as the VT100
+    /// escape sequences a serial terminal would send for them, so that
+    /// consumers can treat keyboard and serial input the same way.
+    fn escape_sequence(&self, code: ScanCode) -> Option<&'static str> {
+        // without Num Lock the keypad doubles as the navigation block
+        if !self.extended && self.num_lock {
+            return None;
+        }
+
+        match code.0 {
+            0x48 => Some("\x1b[A"), // Up
+            0x50 => Some("\x1b[B"), // Down
+            0x4d => Some("\x1b[C"), // Right
+            0x4b => Some("\x1b[D"), // Left
+            0x47 => Some("\x1b[H"), // Home
+            0x4f => Some("\x1b[F"), // End
+            0x52 => Some("\x1b[2~"), // Insert
+            0x53 => Some("\x1b[3~"), // Delete
+            0x49 => Some("\x1b[5~"), // Page Up
+            0x51 => Some("\x1b[6~"), // Page Down
+            _ => None,
+        }
+    }
+
+    fn hotkey(&self, code: ScanCode) -> Option<Hotkey> {
+        let shift = self.lshift || self.rshift;
+
+        match (self.extended, code.0) {
+            // Alt+F1 .. Alt+F6
+            (false, 0x3b..=0x40) if self.lalt => Some(Hotkey::SwitchConsole((code.0 - 0x3b) as usize)),
+            (true, 0x49) if shift => Some(Hotkey::ScrollBack),
+            (true, 0x51) if shift => Some(Hotkey::ScrollForward),
+            _ => None,
+        }
+    }
+
+    /// Starts sending the lock states to the LEDs, or has them sent once
+    /// the update in flight is acknowledged.
+    fn update_leds(&mut self) {
+        if self.leds != LedUpdate::Idle {
+            self.leds_outdated = true;
+            return;
+        }
+
+        // Nothing useful to do if the keyboard does not listen; the lock
+        // state itself is still tracked.
+        if controller::send_no_wait(controller::SET_LEDS).is_ok() {
+            self.leds = LedUpdate::Command;
+        }
+    }
+
+    /// Moves the LED update on when the keyboard answers `response`.
+    fn on_led_response(&mut self, response: u8) {
+        let resend = response == controller::RESEND;
+        let next = match (self.leds, resend) {
+            (LedUpdate::Idle, _) => return,
+            (LedUpdate::Command, true) => controller::SET_LEDS,
+            (LedUpdate::Leds(leds), true) => leds,
+            (LedUpdate::Command, false) => controller::leds(self.scroll_lock, self.num_lock, self.caps_lock),
+            (LedUpdate::Leds(_), false) => {
+                self.leds = LedUpdate::Idle;
+                if self.leds_outdated {
+                    self.leds_outdated = false;
+                    self.update_leds();
+                }
+                return;
+            },
+        };
+
+        self.leds = match controller::send_no_wait(next) {
+            Ok(()) if next == controller::SET_LEDS => LedUpdate::Command,
+            Ok(()) => LedUpdate::Leds(next),
+            Err(_) => LedUpdate::Idle,
+        };
+    }
+
+    fn enqueue(&mut self, c: char) {
+        if self.in_queue.enqueue(c).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    /// Queues a decoded character, resolving any pending dead key first.
+    fn push_char(&mut self, c: char) {
+        if layout::is_dead_key(c) {
+            if let Some(pending) = self.dead_key.take() {
+                // Pressing a dead key twice produces its spacing form.
+                self.enqueue(layout::spacing_form(pending));
+                if pending == c {
+                    return;
+                }
+            }
+            self.dead_key = Some(c);
+            return;
+        }
+
+        if let Some(pending) = self.dead_key.take() {
+            if let Some(composed) = layout::compose(pending, c) {
+                self.enqueue(composed);
+                return;
+            }
+
+            self.enqueue(layout::spacing_form(pending));
+            if c == ' ' {
+                return;
+            }
         }
 
-        Some(printable)
+        self.enqueue(c);
     }
 }
 
 pub struct Keyboard {
-    data: InterruptData<Mutex<KeyboardData>>
+    data: InterruptData<Mutex<KeyboardData>>,
+    ready: Event,
+    hotkey_ready: Event,
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
         Keyboard {
-            data: InterruptData::new(Mutex::new(KeyboardData::new()))
+            data: InterruptData::new(Mutex::new(KeyboardData::new())),
+            ready: Event::new(),
+            hotkey_ready: Event::new(),
         }
     }
 
@@ -121,13 +292,79 @@ impl Keyboard {
         }) as char
     }
 
+    /// Initializes the PS/2 controller and the keyboard behind it.
+    ///
+    /// Must be called before IRQ1 is unmasked, as it polls for the
+    /// controller's responses.
+    pub fn init(&self) -> Result<controller::Info, controller::Error> {
+        let data = self.data.enter();
+        let data = data.lock();
+
+        let info = controller::init()?;
+        controller::set_scancode_set(controller::ScancodeSet::Set2)?;
+        controller::set_typematic(0x0B, 1)?;
+        controller::set_leds(data.scroll_lock, data.num_lock, data.caps_lock)?;
+        controller::enable_scanning()?;
+        Ok(info)
+    }
+
+    /// Sets the typematic repeat rate and delay, see `controller::set_typematic`.
+    pub fn set_typematic(&self, rate: u8, delay: u8) -> Result<(), controller::Error> {
+        let _data = self.data.enter();
+        controller::set_typematic(rate, delay)
+    }
+
     pub fn isr(&self) {
         let data = self.data.enter();
         let mut data = data.lock();
         
-        let scancode = ScanCode(unsafe { inb(0x60) });
-        if let Some(c) = data.from_scancode(scancode) {
-            let _result = data.in_queue.enqueue(c);
+        if !controller::has_data_from(controller::Port::First) {
+            // nothing to read: the byte was already consumed while waiting
+            // for a command to be acknowledged, or it is the mouse's
+            return;
+        }
+
+        let scancode = ScanCode(unsafe { inb(controller::DATA_PORT) });
+        if scancode.0 == controller::ACK || scancode.0 == controller::RESEND {
+            data.on_led_response(scancode.0);
+            return;
+        }
+
+        if scancode.0 == 0xe0 {
+            data.extended = true;
+            return;
+        }
+
+        if let Some(hotkey) = data.hotkey(scancode) {
+            let _result = data.hotkeys.enqueue(hotkey);
+            self.hotkey_ready.signal();
+        } else if let Some(sequence) = data.escape_sequence(scancode) {
+            for c in sequence.chars() {
+                data.enqueue(c);
+            }
+        } else if let Some(c) = data.from_scancode(scancode) {
+            if (data.lctrl || data.rctrl) && c.is_ascii_alphabetic() {
+                // Ctrl+A .. Ctrl+Z are the control characters 0x01 .. 0x1a
+                data.enqueue(((c as u8) & 0x1f) as char);
+            } else {
+                data.push_char(c);
+            }
+        } else if data.extended {
+            match scancode.0 {
+                0x38 => data.altgr = true,
+                0xb8 => data.altgr = false,
+                0x1d => data.rctrl = true,
+                0x9d => data.rctrl = false,
+                _ => {},
+            }
+        } else if scancode.0 == 0x38 {
+            data.lalt = true;
+        } else if scancode.0 == 0xb8 {
+            data.lalt = false;
+        } else if scancode.0 == 0x1d {
+            data.lctrl = true;
+        } else if scancode.0 == 0x9d {
+            data.lctrl = false;
         } else if scancode.0 == 0x2a {
             data.lshift = true;
         } else if scancode.0 == 0x36 {
@@ -136,20 +373,108 @@ impl Keyboard {
             data.lshift = false;
         } else if scancode.0 == 0xb6 {
             data.rshift = false;
+        } else if scancode.0 == 0x3a {
+            if !data.caps_held {
+                data.caps_held = true;
+                data.caps_lock = !data.caps_lock;
+                data.update_leds();
+            }
+        } else if scancode.0 == 0x45 {
+            if !data.num_held {
+                data.num_held = true;
+                data.num_lock = !data.num_lock;
+                data.update_leds();
+            }
+        } else if scancode.0 == 0x46 {
+            if !data.scroll_held {
+                data.scroll_held = true;
+                data.scroll_lock = !data.scroll_lock;
+                data.update_leds();
+            }
+        } else if scancode.0 == 0xba {
+            data.caps_held = false;
+        } else if scancode.0 == 0xc5 {
+            data.num_held = false;
+        } else if scancode.0 == 0xc6 {
+            data.scroll_held = false;
         } else if scancode.0 > 0x7f {
             // ignore other key releases
         } else {
-            let _result = data.in_queue.enqueue('[');
-            let _result = data.in_queue.enqueue(Keyboard::get_hex(scancode.0 / 16));
-            let _result = data.in_queue.enqueue(Keyboard::get_hex(scancode.0 % 16));
-            let _result = data.in_queue.enqueue(']');            
+            data.enqueue('[');
+            data.enqueue(Keyboard::get_hex(scancode.0 / 16));
+            data.enqueue(Keyboard::get_hex(scancode.0 % 16));
+            data.enqueue(']');            
+        }
+
+        data.extended = false;
+
+        if data.in_queue.count > 0 {
+            self.ready.signal();
         }
     }
 
+    /// Switches to a different keyboard layout.
+    pub fn set_layout(&self, layout: &'static Layout) {
+        let data = self.data.enter();
+        let mut data = data.lock();
+
+        data.layout = layout;
+        data.dead_key = None;
+    }
+
+    pub fn layout(&self) -> &'static Layout {
+        let data = self.data.enter();
+        let data = data.lock();
+
+        data.layout
+    }
+
     pub fn try_dequeue(&self) -> Option<char> {
         let data = self.data.enter();
         let mut data = data.lock();
 
         data.in_queue.try_dequeue()
     }
+
+    /// Returns the next character, parking the calling thread until one
+    /// is typed.
+    pub fn read_key<W: Waiter>(&self, waiter: &mut W) -> char {
+        loop {
+            self.ready.reset();
+            if let Some(c) = self.try_dequeue() {
+                return c;
+            }
+            waiter.wait(&[&self.ready]);
+        }
+    }
+
+    /// Returns the next hotkey, parking the calling thread until one is
+    /// pressed.
+    pub fn read_hotkey<W: Waiter>(&self, waiter: &mut W) -> Hotkey {
+        loop {
+            self.hotkey_ready.reset();
+            {
+                let data = self.data.enter();
+                let mut data = data.lock();
+                if let Some(hotkey) = data.hotkeys.try_dequeue() {
+                    return hotkey;
+                }
+            }
+            waiter.wait(&[&self.hotkey_ready]);
+        }
+    }
+
+    /// The event signaled whenever characters are queued, for waiting on
+    /// the keyboard together with other input sources.
+    pub fn ready_event(&self) -> &Event {
+        &self.ready
+    }
+
+    /// Number of characters dropped because the buffer was full.
+    pub fn overflow_count(&self) -> usize {
+        let data = self.data.enter();
+        let data = data.lock();
+
+        data.dropped
+    }
 }
@@ -0,0 +1,214 @@
+#![no_std]
+
+extern crate common;
+use common::{InterruptData,Queue};
+
+extern crate keyboard;
+use keyboard::controller::{self, Port};
+
+extern crate spin;
+use spin::Mutex;
+
+extern crate x86;
+use x86::shared::io::{inb};
+
+// mouse commands
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_DEVICE_ID: u8 = 0xF2;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+
+/// Device id reported by an IntelliMouse once its wheel is unlocked.
+const INTELLIMOUSE_ID: u8 = 3;
+
+// first packet byte
+const LEFT_BUTTON: u8 = 0x01;
+const RIGHT_BUTTON: u8 = 0x02;
+const MIDDLE_BUTTON: u8 = 0x04;
+const ALWAYS_ONE: u8 = 0x08;
+const X_SIGN: u8 = 0x10;
+const Y_SIGN: u8 = 0x20;
+const X_OVERFLOW: u8 = 0x40;
+const Y_OVERFLOW: u8 = 0x80;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Movement since the previous event. `dy` is positive when the mouse moves
+/// away from the user and `wheel` is positive when scrolling towards them.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+/// Decodes a standard 3-byte packet, or a 4-byte IntelliMouse packet with
+/// the wheel movement in the last byte.
+pub fn decode(packet: &[u8]) -> Option<MouseEvent> {
+    if packet.len() < 3 {
+        return None;
+    }
+
+    let flags = packet[0];
+    if flags & ALWAYS_ONE == 0 || flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+        return None;
+    }
+
+    let mut dx = packet[1] as i16;
+    if flags & X_SIGN != 0 {
+        dx -= 0x100;
+    }
+    let mut dy = packet[2] as i16;
+    if flags & Y_SIGN != 0 {
+        dy -= 0x100;
+    }
+
+    // the wheel movement is a 4-bit two's complement number
+    let wheel = if packet.len() > 3 {
+        ((packet[3] << 4) as i8) >> 4
+    } else {
+        0
+    };
+
+    Some(MouseEvent {
+        dx,
+        dy,
+        wheel,
+        buttons: Buttons {
+            left: flags & LEFT_BUTTON != 0,
+            right: flags & RIGHT_BUTTON != 0,
+            middle: flags & MIDDLE_BUTTON != 0,
+        },
+    })
+}
+
+struct MouseData {
+    in_queue: Queue<MouseEvent>,
+    packet: [u8; 4],
+    received: usize,
+    packet_size: usize,
+}
+
+pub struct Mouse {
+    data: InterruptData<Mutex<MouseData>>
+}
+
+impl Mouse {
+    pub fn new() -> Mouse {
+        Mouse {
+            data: InterruptData::new(Mutex::new(MouseData {
+                in_queue: Queue::new(),
+                packet: [0; 4],
+                received: 0,
+                packet_size: 3,
+            }))
+        }
+    }
+
+    fn set_sample_rate(rate: u8) -> Result<(), controller::Error> {
+        controller::send(Port::Second, SET_SAMPLE_RATE)?;
+        controller::send(Port::Second, rate)
+    }
+
+    /// Enables the auxiliary device on the PS/2 controller's second port.
+    /// Returns `true` if the mouse has a scroll wheel.
+    ///
+    /// Must be called after `Keyboard::init` and before IRQ12 is unmasked.
+    pub fn init(&self) -> Result<bool, controller::Error> {
+        let data = self.data.enter();
+        let mut data = data.lock();
+
+        controller::enable_second_port()?;
+        controller::send(Port::Second, SET_DEFAULTS)?;
+
+        // The magic sample rate sequence unlocks the IntelliMouse wheel.
+        Mouse::set_sample_rate(200)?;
+        Mouse::set_sample_rate(100)?;
+        Mouse::set_sample_rate(80)?;
+        controller::send(Port::Second, GET_DEVICE_ID)?;
+        let has_wheel = controller::read_data()? == INTELLIMOUSE_ID;
+
+        data.packet_size = if has_wheel { 4 } else { 3 };
+        data.received = 0;
+
+        controller::send(Port::Second, ENABLE_REPORTING)?;
+        Ok(has_wheel)
+    }
+
+    pub fn isr(&self) {
+        let data = self.data.enter();
+        let mut data = data.lock();
+
+        // with both IRQs pending, the byte may be the keyboard's
+        if !controller::has_data_from(Port::Second) {
+            return;
+        }
+        let b = unsafe { inb(controller::DATA_PORT) };
+
+        // Resynchronize if we lost track of where a packet starts.
+        if data.received == 0 && b & ALWAYS_ONE == 0 {
+            return;
+        }
+
+        let i = data.received;
+        data.packet[i] = b;
+        data.received += 1;
+
+        if data.received == data.packet_size {
+            data.received = 0;
+            let size = data.packet_size;
+            if let Some(event) = decode(&data.packet[..size]) {
+                let _result = data.in_queue.enqueue(event);
+            }
+        }
+    }
+
+    pub fn try_dequeue(&self) -> Option<MouseEvent> {
+        let data = self.data.enter();
+        let mut data = data.lock();
+
+        data.in_queue.try_dequeue()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_standard_packet() {
+        let event = decode(&[0x08 | LEFT_BUTTON, 5, 3]).unwrap();
+        assert_eq!(event.dx, 5);
+        assert_eq!(event.dy, 3);
+        assert_eq!(event.wheel, 0);
+        assert!(event.buttons.left);
+        assert!(!event.buttons.right);
+    }
+
+    #[test]
+    fn decode_negative_movement() {
+        let event = decode(&[0x08 | X_SIGN | Y_SIGN, 0xFE, 0x80]).unwrap();
+        assert_eq!(event.dx, -2);
+        assert_eq!(event.dy, -128);
+    }
+
+    #[test]
+    fn decode_wheel() {
+        assert_eq!(decode(&[0x08, 0, 0, 0x0F]).unwrap().wheel, -1);
+        assert_eq!(decode(&[0x08, 0, 0, 0x01]).unwrap().wheel, 1);
+    }
+
+    #[test]
+    fn reject_bad_packets() {
+        assert_eq!(decode(&[0x00, 1, 1]), None);
+        assert_eq!(decode(&[0x08 | X_OVERFLOW, 1, 1]), None);
+        assert_eq!(decode(&[0x08, 1]), None);
+        assert_eq!(decode(&[]), None);
+    }
+}
@@ -67,7 +67,7 @@ pub fn enable_irq(index: u8) {
     unsafe {
         let address = if index >= 8 { PIC2_DATA_IO_PORT } else {PIC1_DATA_IO_PORT};
         let mut mask = inb(address);
-        mask = mask & (!(1 << index));
+        mask = mask & (!(1 << (index % 8)));
         outb(address, mask);
     }
 }
@@ -76,7 +76,7 @@ pub fn disable_irq(index: u8) {
     unsafe {
         let address = if index >= 8 { PIC2_DATA_IO_PORT } else {PIC1_DATA_IO_PORT};
         let mut mask = inb(address);
-        mask = mask | (1 << index);
+        mask = mask | (1 << (index % 8));
         outb(address, mask);
     }
 }
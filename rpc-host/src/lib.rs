@@ -0,0 +1,396 @@
+//! The host end of the `rpc` protocol, for tools and test harnesses that
+//! drive the kernel through a serial port.
+//!
+//! The kernel answers on the port with the RPC role: the third one it
+//! finds, or any other picked with `serial rpc <1-4>` in the shell. With
+//! QEMU, for instance,
+//!
+//! ```text
+//! qemu-system-x86_64 ... -serial stdio -serial null -serial tcp::4555,server,nowait
+//! ```
+//!
+//! makes COM3 a socket to connect to with `Client::connect("localhost:4555")`.
+
+extern crate rpc;
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rpc::{command, status, value, Decoder, Frame, Kind, MAX_ENCODED, MAX_PAYLOAD};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// No answer, even after sending the request again and again.
+    Timeout,
+    /// The request doesn't fit in a frame.
+    TooLong,
+    /// The kernel answered with a `status` other than OK, and its reason.
+    Status(u8, String),
+    /// The response didn't hold what it should have.
+    BadResponse,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Timeout => write!(f, "no answer from the kernel"),
+            Error::TooLong => write!(f, "request too long"),
+            Error::Status(status::UNKNOWN_COMMAND, _) => write!(f, "unknown command"),
+            Error::Status(status::BAD_REQUEST, _) => write!(f, "bad request"),
+            Error::Status(code, ref reason) => write!(f, "failed ({}): {}", code, reason),
+            Error::BadResponse => write!(f, "malformed response"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// What `Client::status` reports.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Status {
+    pub ticks: u64,
+    pub heap_used: u32,
+    pub heap_capacity: u32,
+}
+
+/// A value returned by a wasm export.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+}
+
+/// How long a read may block before the client checks its deadlines; the
+/// transport must give up after about this long when nothing arrives.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Sends requests to the kernel and waits for the answers, one at a time.
+pub struct Client<T: Read + Write> {
+    transport: T,
+    decoder: Decoder,
+    /// Frames read along with the one being waited for.
+    pending: VecDeque<Frame>,
+    seq: u8,
+    /// How long to wait for the kernel to acknowledge a request before
+    /// sending it again.
+    pub ack_timeout: Duration,
+    /// How long to wait for the answer once the request was acknowledged.
+    /// A request may take a while, e.g. running a wasm export.
+    pub response_timeout: Duration,
+    /// How many times a request is sent again before giving up.
+    pub retries: usize,
+}
+
+impl Client<TcpStream> {
+    /// Connects to a serial port QEMU serves on a TCP socket.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Client<TcpStream>> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        stream.set_nodelay(true)?;
+        Ok(Client::new(stream))
+    }
+}
+
+impl<T: Read + Write> Client<T> {
+    /// Talks over `transport`, whose reads must time out after about
+    /// `POLL_INTERVAL`, failing with `TimedOut` or `WouldBlock`.
+    pub fn new(transport: T) -> Client<T> {
+        // the kernel repeats its last response for a request with the same
+        // number, so don't start where the last client may have stopped
+        let seq = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u8)
+            .unwrap_or(0);
+
+        Client {
+            transport,
+            decoder: Decoder::new(),
+            pending: VecDeque::new(),
+            seq,
+            ack_timeout: Duration::from_millis(500),
+            response_timeout: Duration::from_secs(10),
+            retries: 5,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// The next frame to arrive before `deadline`, if any.
+    fn next_frame(&mut self, deadline: Instant) -> Result<Option<Frame>> {
+        let mut buffer = [0u8; 256];
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Ok(Some(frame));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            let count = match self.transport.read(&mut buffer) {
+                Ok(0) => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(count) => count,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut ||
+                              e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e)),
+            };
+
+            for &b in &buffer[..count] {
+                // broken frames are dropped, the retransmission makes up
+                // for them
+                if let Some(Ok(frame)) = self.decoder.push(b) {
+                    self.pending.push_back(frame);
+                }
+            }
+        }
+    }
+
+    /// Sends a request and returns the payload of the answer, sending the
+    /// request again whenever an acknowledgement or the answer is overdue.
+    pub fn call(&mut self, code: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = Frame::with_payload(Kind::Request, self.seq, code, payload)
+            .map_err(|_| Error::TooLong)?;
+        let mut encoded = [0u8; MAX_ENCODED];
+        let len = request.encode(&mut encoded);
+
+        for _ in 0..self.retries + 1 {
+            self.transport.write_all(&encoded[..len])?;
+            self.transport.flush()?;
+
+            let mut deadline = Instant::now() + self.ack_timeout;
+            while let Some(frame) = self.next_frame(deadline)? {
+                // late answers to earlier requests
+                if frame.seq != self.seq {
+                    continue;
+                }
+
+                match frame.kind {
+                    Kind::Ack => deadline = Instant::now() + self.response_timeout,
+                    Kind::Response if frame.code == status::OK => return Ok(frame.payload().to_vec()),
+                    Kind::Response => {
+                        let reason = String::from_utf8_lossy(frame.payload()).into_owned();
+                        return Err(Error::Status(frame.code, reason));
+                    },
+                    Kind::Request => {},
+                }
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    /// Sends `data` to be sent back, to check the line.
+    pub fn ping(&mut self, data: &[u8]) -> Result<()> {
+        if self.call(command::PING, data)? == data {
+            Ok(())
+        } else {
+            Err(Error::BadResponse)
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        let payload = self.call(command::STATUS, &[])?;
+        match (rpc::read_u64(&payload, 0), rpc::read_u32(&payload, 8), rpc::read_u32(&payload, 12)) {
+            (Some(ticks), Some(heap_used), Some(heap_capacity)) => Ok(Status { ticks, heap_used, heap_capacity }),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    /// Uploads a wasm module for `invoke`, replacing the last one.
+    pub fn upload(&mut self, module: &[u8]) -> Result<()> {
+        self.call(command::UPLOAD_BEGIN, &rpc::write_u32(module.len() as u32))?;
+
+        let mut offset = 0;
+        for piece in module.chunks(MAX_PAYLOAD - 4) {
+            let mut payload = rpc::write_u32(offset as u32).to_vec();
+            payload.extend_from_slice(piece);
+            let received = rpc::read_u32(&self.call(command::UPLOAD_DATA, &payload)?, 0);
+            offset += piece.len();
+            if received != Some(offset as u32) {
+                return Err(Error::BadResponse);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `export` in the uploaded module.
+    pub fn invoke(&mut self, export: &str, args: &[i32]) -> Result<Option<Value>> {
+        if export.len() > 255 {
+            return Err(Error::TooLong);
+        }
+
+        let mut payload = vec![export.len() as u8];
+        payload.extend_from_slice(export.as_bytes());
+        for &arg in args {
+            payload.extend_from_slice(&rpc::write_u32(arg as u32));
+        }
+
+        let result = self.call(command::INVOKE, &payload)?;
+        match result.first() {
+            Some(&value::NONE) => Ok(None),
+            Some(&value::I32) => rpc::read_u32(&result, 1)
+                .map(|v| Some(Value::I32(v as i32)))
+                .ok_or(Error::BadResponse),
+            Some(&value::I64) => rpc::read_u64(&result, 1)
+                .map(|v| Some(Value::I64(v as i64)))
+                .ok_or(Error::BadResponse),
+            _ => Err(Error::BadResponse),
+        }
+    }
+
+    /// Runs a shell command line; its output goes to the shell console.
+    pub fn run(&mut self, line: &str) -> Result<()> {
+        self.call(command::RUN, line.as_bytes()).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The kernel's side of the protocol, answering pings over a line that
+    /// loses the frames it is told to.
+    struct FakeKernel {
+        decoder: Decoder,
+        output: VecDeque<u8>,
+        last: Option<Frame>,
+        handled: usize,
+        lose_requests: usize,
+        lose_responses: usize,
+    }
+
+    impl FakeKernel {
+        fn new() -> FakeKernel {
+            FakeKernel {
+                decoder: Decoder::new(),
+                output: VecDeque::new(),
+                last: None,
+                handled: 0,
+                lose_requests: 0,
+                lose_responses: 0,
+            }
+        }
+
+        fn send(&mut self, frame: &Frame) {
+            let mut encoded = [0u8; MAX_ENCODED];
+            let len = frame.encode(&mut encoded);
+            self.output.extend(&encoded[..len]);
+        }
+
+        fn handle(&mut self, request: Frame) {
+            if self.lose_requests > 0 {
+                self.lose_requests -= 1;
+                return;
+            }
+
+            self.send(&Frame::new(Kind::Ack, request.seq, status::OK));
+            let response = match self.last {
+                Some(response) if response.seq == request.seq => response,
+                _ => {
+                    self.handled += 1;
+                    let code = if request.code == command::PING { status::OK } else { status::UNKNOWN_COMMAND };
+                    Frame::with_payload(Kind::Response, request.seq, code, request.payload()).unwrap()
+                },
+            };
+            self.last = Some(response);
+
+            if self.lose_responses > 0 {
+                self.lose_responses -= 1;
+                return;
+            }
+            self.send(&response);
+        }
+    }
+
+    impl Read for FakeKernel {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.output.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            let count = buffer.len().min(self.output.len());
+            for b in buffer[..count].iter_mut() {
+                *b = self.output.pop_front().unwrap();
+            }
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeKernel {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            for &b in bytes {
+                if let Some(Ok(frame)) = self.decoder.push(b) {
+                    self.handle(frame);
+                }
+            }
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fast_client(kernel: FakeKernel) -> Client<FakeKernel> {
+        let mut client = Client::new(kernel);
+        client.ack_timeout = Duration::from_millis(10);
+        client.response_timeout = Duration::from_millis(10);
+        client
+    }
+
+    #[test]
+    fn lost_requests_are_sent_again() {
+        let mut kernel = FakeKernel::new();
+        kernel.lose_requests = 2;
+        let mut client = fast_client(kernel);
+
+        client.ping(b"\x00hello\x00").unwrap();
+        assert_eq!(client.into_inner().handled, 1);
+    }
+
+    #[test]
+    fn requests_run_once_when_the_response_is_lost() {
+        let mut kernel = FakeKernel::new();
+        kernel.lose_responses = 1;
+        let mut client = fast_client(kernel);
+
+        client.ping(b"one").unwrap();
+        client.ping(b"two").unwrap();
+        assert_eq!(client.into_inner().handled, 2);
+    }
+
+    #[test]
+    fn errors_and_timeouts() {
+        let mut client = fast_client(FakeKernel::new());
+        match client.run("help") {
+            Err(Error::Status(status::UNKNOWN_COMMAND, _)) => {},
+            other => panic!("{:?}", other),
+        }
+
+        let mut kernel = FakeKernel::new();
+        kernel.lose_requests = 100;
+        let mut client = fast_client(kernel);
+        client.retries = 2;
+        match client.ping(b"") {
+            Err(Error::Timeout) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Consistent Overhead Byte Stuffing: rewrites a frame so that it contains
+//! no zero bytes, at the cost of one byte per 254, so that a zero can mark
+//! where each frame ends. A receiver that lost track, after line noise or
+//! joining halfway, is back in step at the next zero.
+
+/// The most bytes encoding `len` bytes can take, not counting the zero
+/// that ends the frame.
+pub fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encodes `input` into `output`, which must hold `max_encoded_len` bytes,
+/// and returns the length of the result.
+pub fn encode(input: &[u8], output: &mut [u8]) -> usize {
+    // each block starts with the distance to the next zero, which the block
+    // replaces; 0xFF says the block has 254 bytes and no zero after it
+    let mut code_index = 0;
+    let mut code = 1u8;
+    let mut written = 1;
+
+    for &b in input {
+        if b == 0 {
+            output[code_index] = code;
+            code_index = written;
+            written += 1;
+            code = 1;
+            continue;
+        }
+
+        output[written] = b;
+        written += 1;
+        code += 1;
+        if code == 0xFF {
+            output[code_index] = code;
+            code_index = written;
+            written += 1;
+            code = 1;
+        }
+    }
+
+    output[code_index] = code;
+    written
+}
+
+/// Decodes `input`, without the zero that ended it, into `output`. Fails if
+/// `input` isn't valid COBS or doesn't fit.
+pub fn decode(input: &[u8], output: &mut [u8]) -> Result<usize, ()> {
+    let mut read = 0;
+    let mut written = 0;
+
+    while read < input.len() {
+        let code = input[read] as usize;
+        if code == 0 || read + code > input.len() {
+            return Err(());
+        }
+        read += 1;
+
+        for _ in 1..code {
+            *output.get_mut(written).ok_or(())? = input[read];
+            read += 1;
+            written += 1;
+        }
+
+        // the zero the code replaced, unless the block was a full one or
+        // the last
+        if code != 0xFF && read < input.len() {
+            *output.get_mut(written).ok_or(())? = 0;
+            written += 1;
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) {
+        let mut encoded = [0u8; 600];
+        let mut decoded = [0u8; 600];
+        let len = encode(input, &mut encoded);
+        assert!(len <= max_encoded_len(input.len()));
+        assert!(!encoded[..len].contains(&0));
+        assert_eq!(decode(&encoded[..len], &mut decoded), Ok(input.len()));
+        assert_eq!(&decoded[..input.len()], input);
+    }
+
+    #[test]
+    fn known_encodings() {
+        let mut encoded = [0u8; 16];
+        let len = encode(&[0x11, 0x22, 0x00, 0x33], &mut encoded);
+        assert_eq!(&encoded[..len], &[0x03, 0x11, 0x22, 0x02, 0x33]);
+
+        let len = encode(&[0x00, 0x00], &mut encoded);
+        assert_eq!(&encoded[..len], &[0x01, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&[]);
+        round_trip(&[0]);
+        round_trip(&[1, 2, 0, 0, 3]);
+
+        let mut long = [0u8; 520];
+        for (i, b) in long.iter_mut().enumerate() {
+            *b = (i % 255) as u8 + 1;
+        }
+        round_trip(&long[..254]);
+        round_trip(&long[..255]);
+        round_trip(&long);
+        long[300] = 0;
+        round_trip(&long);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let mut decoded = [0u8; 16];
+        assert_eq!(decode(&[0x05, 1, 2], &mut decoded), Err(()));
+        assert_eq!(decode(&[0x02, 1, 0x00], &mut decoded), Err(()));
+        assert_eq!(decode(&[0x03, 1, 2], &mut decoded[..1]), Err(()));
+    }
+}
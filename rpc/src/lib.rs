@@ -0,0 +1,338 @@
+//! A framed request/response protocol for driving the kernel over a serial
+//! line, shared by the kernel and the host side library.
+//!
+//! Every frame is `kind, seq, code, payload..., crc16` (CRC little endian),
+//! COBS encoded and ended by a zero byte. The host sends a request and the
+//! kernel acknowledges it as soon as it arrives, then answers with a
+//! response carrying the same sequence number. A request that isn't
+//! acknowledged, or answered, in time is sent again unchanged; the kernel
+//! recognizes it by its sequence number, command and payload, and repeats
+//! its last response instead of running the request twice. Frames that fail the CRC are dropped, so the
+//! retransmission covers them too.
+
+#![no_std]
+
+use core::fmt;
+
+pub mod cobs;
+pub mod crc;
+
+/// Request codes.
+pub mod command {
+    /// Answered with the payload it carries.
+    pub const PING: u8 = 0;
+    /// Answered with ticks since boot (u64), then heap bytes used and heap
+    /// size (u32 each).
+    pub const STATUS: u8 = 1;
+    /// Starts uploading a wasm module of the given size (u32).
+    pub const UPLOAD_BEGIN: u8 = 2;
+    /// The next piece of the module: its offset (u32), then the bytes.
+    pub const UPLOAD_DATA: u8 = 3;
+    /// Calls an export of the uploaded module: the name's length (u8), the
+    /// name, then i32 arguments. Answered with a `value` tag and the value.
+    pub const INVOKE: u8 = 4;
+    /// Runs a shell command line; its output goes to the shell console.
+    pub const RUN: u8 = 5;
+}
+
+/// Response codes.
+pub mod status {
+    pub const OK: u8 = 0;
+    pub const UNKNOWN_COMMAND: u8 = 1;
+    /// The payload didn't make sense for the command.
+    pub const BAD_REQUEST: u8 = 2;
+    /// The command ran and failed; the payload says why, as text.
+    pub const FAILED: u8 = 3;
+}
+
+/// Tags of the values `command::INVOKE` returns, followed by the value
+/// itself, little endian.
+pub mod value {
+    pub const NONE: u8 = 0;
+    pub const I32: u8 = 1;
+    pub const I64: u8 = 2;
+}
+
+pub const MAX_PAYLOAD: usize = 249;
+
+const HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 2;
+
+/// The longest frame before encoding: a COBS block of 254 bytes, so that
+/// encoding adds just one byte.
+pub const MAX_FRAME: usize = HEADER_LEN + MAX_PAYLOAD + CRC_LEN;
+
+/// The longest frame on the wire, including the zero that ends it.
+pub const MAX_ENCODED: usize = MAX_FRAME + MAX_FRAME / 254 + 2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Request,
+    Response,
+    /// Says a request arrived; carries its sequence number and no payload.
+    Ack,
+}
+
+impl Kind {
+    fn from_u8(b: u8) -> Option<Kind> {
+        match b {
+            0 => Some(Kind::Request),
+            1 => Some(Kind::Response),
+            2 => Some(Kind::Ack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// More than `MAX_PAYLOAD` bytes of payload, or more than `MAX_ENCODED`
+    /// bytes before the end of a frame.
+    TooLong,
+    /// Not valid COBS, or too short or long to be a frame.
+    Malformed,
+    BadCrc,
+    UnknownKind(u8),
+}
+
+/// One request, response or acknowledgement.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub kind: Kind,
+    pub seq: u8,
+    /// A `command` for requests, a `status` for responses.
+    pub code: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Frame {
+    /// An empty frame; add the payload with `push`.
+    pub fn new(kind: Kind, seq: u8, code: u8) -> Frame {
+        Frame {
+            kind,
+            seq,
+            code,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    pub fn with_payload(kind: Kind, seq: u8, code: u8, payload: &[u8]) -> Result<Frame, Error> {
+        let mut frame = Frame::new(kind, seq, code);
+        frame.push(payload)?;
+        Ok(frame)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+
+    /// Appends to the payload, all of `bytes` or nothing.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.len + bytes.len() > MAX_PAYLOAD {
+            return Err(Error::TooLong);
+        }
+
+        self.payload[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Writes the frame as it goes on the wire, zero included, and returns
+    /// its length.
+    pub fn encode(&self, output: &mut [u8; MAX_ENCODED]) -> usize {
+        let mut raw = [0u8; MAX_FRAME];
+        raw[0] = self.kind as u8;
+        raw[1] = self.seq;
+        raw[2] = self.code;
+        let end = HEADER_LEN + self.len;
+        raw[HEADER_LEN..end].copy_from_slice(self.payload());
+        let crc = crc::crc16(&raw[..end]);
+        raw[end] = crc as u8;
+        raw[end + 1] = (crc >> 8) as u8;
+
+        let len = cobs::encode(&raw[..end + CRC_LEN], &mut output[..]);
+        output[len] = 0;
+        len + 1
+    }
+
+    /// Reads a frame from its encoded form, without the zero that ended it.
+    pub fn decode(input: &[u8]) -> Result<Frame, Error> {
+        let mut raw = [0u8; MAX_FRAME];
+        let len = cobs::decode(input, &mut raw).map_err(|_| Error::Malformed)?;
+        if len < HEADER_LEN + CRC_LEN {
+            return Err(Error::Malformed);
+        }
+
+        let end = len - CRC_LEN;
+        let crc = raw[end] as u16 | (raw[end + 1] as u16) << 8;
+        if crc::crc16(&raw[..end]) != crc {
+            return Err(Error::BadCrc);
+        }
+
+        let kind = Kind::from_u8(raw[0]).ok_or(Error::UnknownKind(raw[0]))?;
+        Frame::with_payload(kind, raw[1], raw[2], &raw[HEADER_LEN..end])
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} #{} code {} {:?}", self.kind, self.seq, self.code, self.payload())
+    }
+}
+
+/// Text in the payload, e.g. the reason a command failed; what doesn't fit
+/// is cut off.
+impl fmt::Write for Frame {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_PAYLOAD - self.len;
+        let bytes = &s.as_bytes()[..s.len().min(room)];
+        self.push(bytes).map_err(|_| fmt::Error)
+    }
+}
+
+/// Collects bytes from the line until a frame is complete.
+pub struct Decoder {
+    buffer: [u8; MAX_ENCODED],
+    len: usize,
+    /// Whether the frame being collected outgrew the buffer; the rest of it
+    /// is skipped.
+    overflow: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            buffer: [0; MAX_ENCODED],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Takes the next byte, and returns the frame or error it completes.
+    /// Zeros between frames are ignored.
+    pub fn push(&mut self, b: u8) -> Option<Result<Frame, Error>> {
+        if b != 0 {
+            if self.len < self.buffer.len() {
+                self.buffer[self.len] = b;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        let result = if self.overflow {
+            Some(Err(Error::TooLong))
+        } else if self.len == 0 {
+            None
+        } else {
+            Some(Frame::decode(&self.buffer[..self.len]))
+        };
+
+        self.len = 0;
+        self.overflow = false;
+        result
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+/// Reads a little endian u32 at `offset`, if there are enough bytes.
+pub fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+pub fn write_u32(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+pub fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let low = read_u32(bytes, offset)? as u64;
+    let high = read_u32(bytes, offset + 4)? as u64;
+    Some(low | high << 32)
+}
+
+pub fn write_u64(value: u64) -> [u8; 8] {
+    let (low, high) = (write_u32(value as u32), write_u32((value >> 32) as u32));
+    [low[0], low[1], low[2], low[3], high[0], high[1], high[2], high[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(decoder: &mut Decoder, bytes: &[u8]) -> Option<Result<Frame, Error>> {
+        let mut result = None;
+        for &b in bytes {
+            if let Some(r) = decoder.push(b) {
+                assert!(result.is_none());
+                result = Some(r);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frame = Frame::with_payload(Kind::Request, 7, command::PING, &[0, 1, 0, 2]).unwrap();
+        let mut encoded = [0u8; MAX_ENCODED];
+        let len = frame.encode(&mut encoded);
+        assert_eq!(encoded[len - 1], 0);
+        assert!(!encoded[..len - 1].contains(&0));
+
+        let decoded = feed(&mut Decoder::new(), &encoded[..len]).unwrap().unwrap();
+        assert_eq!(decoded.kind, Kind::Request);
+        assert_eq!(decoded.seq, 7);
+        assert_eq!(decoded.code, command::PING);
+        assert_eq!(decoded.payload(), &[0, 1, 0, 2]);
+
+        let full = Frame::with_payload(Kind::Response, 0, status::OK, &[0xAA; MAX_PAYLOAD]).unwrap();
+        let len = full.encode(&mut encoded);
+        assert!(len <= MAX_ENCODED);
+        let decoded = feed(&mut Decoder::new(), &encoded[..len]).unwrap().unwrap();
+        assert_eq!(decoded.payload(), full.payload());
+
+        assert_eq!(Frame::with_payload(Kind::Ack, 0, 0, &[0; MAX_PAYLOAD + 1]).err(), Some(Error::TooLong));
+    }
+
+    #[test]
+    fn corruption_is_caught() {
+        let frame = Frame::with_payload(Kind::Response, 1, status::OK, b"hello").unwrap();
+        let mut encoded = [0u8; MAX_ENCODED];
+        let len = frame.encode(&mut encoded);
+        encoded[4] ^= 0x10;
+
+        let mut decoder = Decoder::new();
+        assert_eq!(feed(&mut decoder, &encoded[..len]).unwrap().err(), Some(Error::BadCrc));
+
+        // the decoder is back in step for the next frame
+        encoded[4] ^= 0x10;
+        assert!(feed(&mut decoder, &encoded[..len]).unwrap().is_ok());
+    }
+
+    #[test]
+    fn overlong_frames_are_skipped() {
+        let mut decoder = Decoder::new();
+        assert!(feed(&mut decoder, &[0x55; MAX_ENCODED + 10]).is_none());
+        assert_eq!(decoder.push(0).unwrap().err(), Some(Error::TooLong));
+        assert!(decoder.push(0).is_none());
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(read_u32(&write_u32(0x12345678), 0), Some(0x12345678));
+        assert_eq!(read_u64(&write_u64(0x0123456789ABCDEF), 0), Some(0x0123456789ABCDEF));
+        assert_eq!(read_u32(&[1, 2, 3], 0), None);
+    }
+}
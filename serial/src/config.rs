@@ -0,0 +1,210 @@
+//! Line settings: speed, character size, parity and stop bits.
+
+use core::fmt;
+
+/// The UART's input clock divided by 16: the fastest rate there is, and
+/// the number every other rate must divide evenly.
+pub const MAX_BAUD_RATE: u32 = 115200;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// The parity bit is always 1.
+    Mark,
+    /// The parity bit is always 0.
+    Space,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopBits {
+    One,
+    /// Two stop bits, or one and a half with five data bits.
+    Two,
+}
+
+/// How the two ends keep each other from sending more than fits.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlowControl {
+    None,
+    /// RTS and CTS lines: each side drops its RTS to hold the other off.
+    RtsCts,
+    /// XOFF (Ctrl-S) and XON (Ctrl-Q) in the data itself, which then can't
+    /// carry those two bytes.
+    XonXoff,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigError {
+    /// The rate is zero, above `MAX_BAUD_RATE` or doesn't divide it evenly.
+    UnsupportedBaudRate(u32),
+}
+
+/// How a serial port sends and receives characters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl SerialConfig {
+    /// 8N1 at `baud_rate` without flow control, which is what everything
+    /// expects these days.
+    pub fn new(baud_rate: u32) -> SerialConfig {
+        SerialConfig {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+
+    /// The value for the divisor latch, if the UART can run at the rate.
+    pub fn divisor(&self) -> Result<u16, ConfigError> {
+        let baud_rate = self.baud_rate;
+        if baud_rate == 0 || baud_rate > MAX_BAUD_RATE || MAX_BAUD_RATE % baud_rate != 0 {
+            return Err(ConfigError::UnsupportedBaudRate(baud_rate));
+        }
+
+        // the latch is 16 bits, so 2 is the slowest rate: 1 divides evenly
+        // but its divisor doesn't fit
+        let divisor = MAX_BAUD_RATE / baud_rate;
+        if divisor > u16::max_value() as u32 {
+            return Err(ConfigError::UnsupportedBaudRate(baud_rate));
+        }
+        Ok(divisor as u16)
+    }
+
+    /// The line control register value, with the divisor latch and break
+    /// bits clear.
+    pub fn line_control(&self) -> u8 {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 0x00,
+            DataBits::Six => 0x01,
+            DataBits::Seven => 0x02,
+            DataBits::Eight => 0x03,
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0x00,
+            StopBits::Two => 0x04,
+        };
+
+        // enable, even select and stick parity
+        let parity = match self.parity {
+            Parity::None => 0x00,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18,
+            Parity::Mark => 0x28,
+            Parity::Space => 0x38,
+        };
+
+        data_bits | stop_bits | parity
+    }
+}
+
+/// 115200 baud, 8N1.
+impl Default for SerialConfig {
+    fn default() -> SerialConfig {
+        SerialConfig::new(MAX_BAUD_RATE)
+    }
+}
+
+/// The usual short form, e.g. "9600 7E1", plus the flow control if any.
+impl fmt::Display for SerialConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+            Parity::Mark => 'M',
+            Parity::Space => 'S',
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => "1",
+            StopBits::Two if self.data_bits == DataBits::Five => "1.5",
+            StopBits::Two => "2",
+        };
+
+        write!(f, "{} {}{}{}", self.baud_rate, data_bits, parity, stop_bits)?;
+        match self.flow_control {
+            FlowControl::None => Ok(()),
+            FlowControl::RtsCts => write!(f, " rts/cts"),
+            FlowControl::XonXoff => write!(f, " xon/xoff"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divisors() {
+        assert_eq!(SerialConfig::new(115200).divisor(), Ok(1));
+        assert_eq!(SerialConfig::new(9600).divisor(), Ok(12));
+        assert_eq!(SerialConfig::new(50).divisor(), Ok(2304));
+        assert_eq!(SerialConfig::new(0).divisor(), Err(ConfigError::UnsupportedBaudRate(0)));
+        assert_eq!(SerialConfig::new(2).divisor(), Ok(57600));
+        assert_eq!(SerialConfig::new(1).divisor(), Err(ConfigError::UnsupportedBaudRate(1)));
+        assert_eq!(SerialConfig::new(230400).divisor(), Err(ConfigError::UnsupportedBaudRate(230400)));
+        assert_eq!(SerialConfig::new(100000).divisor(), Err(ConfigError::UnsupportedBaudRate(100000)));
+    }
+
+    #[test]
+    fn line_control() {
+        assert_eq!(SerialConfig::default().line_control(), 0x03);
+
+        let config = SerialConfig {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::RtsCts,
+        };
+        assert_eq!(config.line_control(), 0x1E);
+    }
+
+    #[test]
+    fn display() {
+        use core::fmt::Write;
+
+        struct Buffer([u8; 32], usize);
+
+        impl Write for Buffer {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0[self.1..self.1 + s.len()].copy_from_slice(s.as_bytes());
+                self.1 += s.len();
+                Ok(())
+            }
+        }
+
+        let mut config = SerialConfig::new(9600);
+        config.stop_bits = StopBits::Two;
+        config.flow_control = FlowControl::XonXoff;
+        let mut buffer = Buffer([0; 32], 0);
+        write!(buffer, "{}", config).unwrap();
+        assert_eq!(&buffer.0[..buffer.1], b"9600 8N2 xon/xoff");
+    }
+}
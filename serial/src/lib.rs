@@ -11,29 +11,169 @@ extern crate x86;
 use x86::shared::io::{inb, outb};
 
 extern crate common;
-use common::{Queue,InterruptData};
+use common::{Event,Queue,InterruptData,Waiter};
 
-pub const COM1 : u16 = 0x3F8;
+mod config;
+mod ports;
+pub use config::{ConfigError, DataBits, FlowControl, Parity, SerialConfig, StopBits, MAX_BAUD_RATE};
+pub use ports::{irq, name, probe, AssignError, Role, SerialPorts, ROLES};
+pub use ports::{COM1, COM2, COM3, COM4, PORTS, PORT_COUNT};
+
+/// Counts of what went wrong on a port since boot.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SerialStats {
+    /// Bytes received while the input queue was full.
+    pub dropped: usize,
+    /// Bytes written without parking that the UART never took, as the
+    /// other end held us off.
+    pub unsent: usize,
+    /// Bytes the UART lost because its FIFO was full.
+    pub overrun: usize,
+    pub parity: usize,
+    pub framing: usize,
+    /// Times the line was held low for longer than a character.
+    pub breaks: usize,
+    /// Interrupts with a source the driver doesn't know.
+    pub unknown_interrupts: usize,
+}
 
 struct SerialPortRaw {
     base_address: u16,
-    in_queue: Queue<u8>,
-    out_queue: Queue<u8>,
+    config: SerialConfig,
+    in_queue: Queue<u8, [u8; QUEUE_SIZE]>,
+    out_queue: Queue<u8, [u8; QUEUE_SIZE]>,
+    stats: SerialStats,
+    /// The last value of the modem status register.
+    modem_status: u8,
+    /// Whether we asked the other end to stop sending.
+    throttled: bool,
+    /// Whether the other end sent XOFF.
+    stopped: bool,
+    /// XON or XOFF, to go out ahead of the queued bytes.
+    pending_control: Option<u8>,
 }
 
+/// Bytes each of a port's input and output queues holds.
+pub const QUEUE_SIZE: usize = 256;
+
+/// Fill levels of the input queue at which the other end is told to stop
+/// and to go on sending, with room to spare for what it sends meanwhile.
+pub const HIGH_WATERMARK: usize = QUEUE_SIZE * 3 / 4;
+pub const LOW_WATERMARK: usize = QUEUE_SIZE / 4;
+
+/// Bytes the transmit FIFO of a 16550 takes each time it runs empty.
+pub const TX_FIFO_SIZE: usize = 16;
+
+pub const XON: u8 = 0x11;
+pub const XOFF: u8 = 0x13;
+
 impl SerialPortRaw {
-    pub unsafe fn read_in_bytes(&mut self) {
+    /// Reads the LSR and counts the errors it reports. Reading it clears
+    /// the error bits, so everything that looks at it goes through here.
+    /// Returns whether there was an error, too.
+    pub unsafe fn line_status(&mut self) -> (u8, bool) {
+        let status = inb(self.base_address + SerialPort::LSR);
+        if status & SerialPort::LSR_OVERRUN != 0 {
+            self.stats.overrun += 1;
+        }
+        if status & SerialPort::LSR_PARITY != 0 {
+            self.stats.parity += 1;
+        }
+        if status & SerialPort::LSR_FRAMING != 0 {
+            self.stats.framing += 1;
+        }
+        if status & SerialPort::LSR_BREAK != 0 {
+            self.stats.breaks += 1;
+        }
+
+        (status, status & SerialPort::LSR_ERRORS != 0)
+    }
+
+    /// Moves received bytes to the input queue, dropping those that don't
+    /// fit. Returns whether anything was received and whether there were
+    /// line errors.
+    pub unsafe fn read_in_bytes(&mut self) -> (bool, bool) {
+        let (mut received, mut errors) = (false, false);
         loop {
-            let b = inb(self.base_address);
-            self.in_queue.enqueue(b).unwrap();
-            if inb(self.base_address + SerialPort::LSR) & 0x01 == 0x0 {
+            let (status, error) = self.line_status();
+            errors |= error;
+            if status & SerialPort::LSR_DATA_READY == 0 {
                 break;
             }
+
+            let b = inb(self.base_address);
+            if self.config.flow_control == FlowControl::XonXoff && (b == XON || b == XOFF) {
+                self.stopped = b == XOFF;
+                continue;
+            }
+
+            received = true;
+            if self.in_queue.enqueue(b).is_err() {
+                self.stats.dropped += 1;
+            }
+        }
+
+        if self.in_queue.count >= HIGH_WATERMARK {
+            self.throttle(true);
+        }
+        // XON may have arrived
+        self.write_out_bytes();
+
+        (received, errors)
+    }
+
+    /// Tells the other end to stop or to go on sending, with whichever
+    /// flow control is on.
+    pub unsafe fn throttle(&mut self, throttled: bool) {
+        if throttled == self.throttled {
+            return;
+        }
+        self.throttled = throttled;
+
+        match self.config.flow_control {
+            FlowControl::None => {},
+            FlowControl::RtsCts => self.set_rts(!throttled),
+            FlowControl::XonXoff => {
+                self.pending_control = Some(if throttled { XOFF } else { XON });
+                self.write_out_bytes();
+            },
+        }
+    }
+
+    pub unsafe fn set_rts(&mut self, on: bool) {
+        let mcr = inb(self.base_address + SerialPort::MCR);
+        let mcr = if on { mcr | SerialPort::MCR_RTS } else { mcr & !SerialPort::MCR_RTS };
+        outb(self.base_address + SerialPort::MCR, mcr);
+    }
+
+    /// Whether the other end lets us send.
+    pub fn can_transmit(&self) -> bool {
+        match self.config.flow_control {
+            FlowControl::None => true,
+            FlowControl::RtsCts => self.modem_status & SerialPort::MSR_CTS != 0,
+            FlowControl::XonXoff => !self.stopped,
         }
     }
 
+    /// Fills the transmit FIFO from the output queue if it is empty, and
+    /// returns without waiting if it isn't; the THR empty interrupt brings
+    /// us back once it is.
     pub unsafe fn write_out_bytes(&mut self) {
-        while inb(self.base_address + SerialPort::LSR) & 0x02 != 0x0 {
+        if self.line_status().0 & SerialPort::LSR_THR_EMPTY == 0 {
+            return;
+        }
+
+        for _ in 0..TX_FIFO_SIZE {
+            // XON and XOFF go out even while we are held off ourselves
+            if let Some(b) = self.pending_control.take() {
+                outb(self.base_address, b);
+                continue;
+            }
+
+            if !self.can_transmit() {
+                break;
+            }
+
             if let Some(b) = self.out_queue.try_dequeue() {
                 outb(self.base_address, b);
             } else {
@@ -42,21 +182,96 @@ impl SerialPortRaw {
         }
     }
 
-    pub fn set_baud_rate_internal<'a>(&self, baud_rate: u32) {
-        let divisor = 115200 / baud_rate;
-        unsafe {
-            let r = inb(self.base_address + SerialPort::LCR);
-            outb(self.base_address + SerialPort::LCR, 0x80 | r);
-            outb(self.base_address, (divisor & 0xFF) as u8);
-            outb(self.base_address + 1, (divisor >> 8) as u8);
-            outb(self.base_address + SerialPort::LCR, 0x7F | r);
+    /// Queues as much of `bytes` as fits and starts sending it. Returns how
+    /// many bytes were taken.
+    pub unsafe fn enqueue(&mut self, bytes: &[u8]) -> usize {
+        let mut taken = 0;
+        for &b in bytes {
+            if self.out_queue.enqueue(b).is_err() {
+                break;
+            }
+            taken += 1;
         }
+
+        self.write_out_bytes();
+        taken
     }
 
+    /// Polls until the UART takes some of a full output queue, for writers
+    /// that can't wait for the THR empty interrupt. Returns whether there
+    /// is room now: not if the other end holds us off, or if the UART
+    /// doesn't become ready within `PANIC_POLL_LIMIT` reads.
+    pub unsafe fn poll_for_room(&mut self) -> bool {
+        for _ in 0..PANIC_POLL_LIMIT {
+            if !self.can_transmit() {
+                return false;
+            }
+            if self.line_status().0 & SerialPort::LSR_THR_EMPTY != 0 {
+                self.write_out_bytes();
+                return !self.out_queue.is_full();
+            }
+        }
+        false
+    }
+
+    /// Takes a byte from the input queue, letting the other end go on once
+    /// enough room has been made.
+    pub unsafe fn receive(&mut self) -> Option<u8> {
+        let b = self.in_queue.try_dequeue();
+        if self.throttled && self.in_queue.count <= LOW_WATERMARK {
+            self.throttle(false);
+        }
+        b
+    }
+
+    /// Sends everything queued and waits for the last bit to leave the
+    /// shift register, so that nothing goes out half in the old settings.
+    /// Gives up if the other end holds us off, rather than wait for it.
+    pub unsafe fn drain(&mut self) {
+        loop {
+            self.write_out_bytes();
+            let empty = self.line_status().0 & SerialPort::LSR_TRANSMITTER_EMPTY != 0;
+            if (empty && self.out_queue.count == 0) || !self.can_transmit() {
+                break;
+            }
+        }
+    }
+
+    /// Programs the divisor latch and line control register. Interrupts
+    /// are off meanwhile, as the divisor latch hides the data and
+    /// interrupt enable registers.
+    pub unsafe fn write_config(&mut self, config: &SerialConfig) -> Result<(), ConfigError> {
+        let divisor = config.divisor()?;
+
+        let ier = inb(self.base_address + SerialPort::IER);
+        outb(self.base_address + SerialPort::IER, 0);
+
+        outb(self.base_address + SerialPort::LCR, SerialPort::LCR_DLAB);
+        outb(self.base_address + SerialPort::DLL, (divisor & 0xFF) as u8);
+        outb(self.base_address + SerialPort::DLM, (divisor >> 8) as u8);
+        // clearing DLAB again
+        outb(self.base_address + SerialPort::LCR, config.line_control());
+
+        outb(self.base_address + SerialPort::IER, ier);
+
+        // start over with the other end free to send, whatever the old flow
+        // control had told it
+        self.config = *config;
+        self.throttled = false;
+        self.stopped = false;
+        self.pending_control = None;
+        self.set_rts(true);
+        self.modem_status = inb(self.base_address + SerialPort::MSR);
+        Ok(())
+    }
 }
 
 pub struct SerialPort {
-    raw: InterruptData<Mutex<SerialPortRaw>>
+    raw: InterruptData<Mutex<SerialPortRaw>>,
+    ready: Event,
+    line_error: Event,
+    modem_status_changed: Event,
+    space: Event,
 }
 
 impl SerialPort {
@@ -65,9 +280,21 @@ impl SerialPort {
             raw: InterruptData::new(Mutex::new(
                 SerialPortRaw {
                     base_address: base_address,
+                    config: SerialConfig::default(),
+                    // room for a quick paste into a terminal
                     in_queue: Queue::new(),
+                    // a few lines of log, drained by the THR empty interrupt
                     out_queue: Queue::new(),
-                }))
+                    stats: SerialStats::default(),
+                    modem_status: 0,
+                    throttled: false,
+                    stopped: false,
+                    pending_control: None,
+                })),
+            ready: Event::new(),
+            line_error: Event::new(),
+            modem_status_changed: Event::new(),
+            space: Event::new(),
         }
     }
 
@@ -76,15 +303,42 @@ impl SerialPort {
     const IIR: u16 = 2;
     const FCR: u16 = 2;
     const LCR: u16 = 3;
+    const MCR: u16 = 4;
     const LSR: u16 = 5;
+    const MSR: u16 = 6;
+    // the divisor latch, in place of the data and IER while DLAB is set
+    const DLL: u16 = 0;
+    const DLM: u16 = 1;
+
+    const LCR_DLAB: u8 = 0x80;
+
+    const MCR_RTS: u8 = 0x02;
+    const MSR_CTS: u8 = 0x10;
 
-    pub fn init(&self) {
+    const LSR_DATA_READY: u8 = 0x01;
+    const LSR_OVERRUN: u8 = 0x02;
+    const LSR_PARITY: u8 = 0x04;
+    const LSR_FRAMING: u8 = 0x08;
+    const LSR_BREAK: u8 = 0x10;
+    const LSR_ERRORS: u8 = 0x1E;
+    const LSR_THR_EMPTY: u8 = 0x20;
+    const LSR_TRANSMITTER_EMPTY: u8 = 0x40;
+
+    // interrupt sources, from bits 1 to 3 of the IIR
+    const IIR_MODEM_STATUS: u8 = 0;
+    const IIR_THR_EMPTY: u8 = 1;
+    const IIR_DATA_AVAILABLE: u8 = 2;
+    const IIR_LINE_STATUS: u8 = 3;
+    const IIR_CHARACTER_TIMEOUT: u8 = 6;
+
+    pub fn init(&self, config: &SerialConfig) -> Result<(), ConfigError> {
         let port = self.raw.enter();
-        let port = port.lock();
+        let mut port = port.lock();
         unsafe {
             outb(port.base_address + SerialPort::IER, 0); // disable interrupts
-            outb(port.base_address + SerialPort::LCR, 3); // 8N1
-            port.set_baud_rate_internal(115200);
+            port.write_config(config)?;
+            // DTR, RTS, and OUT2, which connects the UART to its IRQ line
+            outb(port.base_address + SerialPort::MCR, 0x0B);
             // outb(port.base_address + SerialPort::FCR, 0);
             
             outb(port.base_address + SerialPort::FCR, 
@@ -94,42 +348,132 @@ impl SerialPort {
                 0x80   // 32-byte trigger
                 );
         }
+        Ok(())
     }
 
     pub fn enable_interrupts(&self) {
         let port = self.raw.enter();
         let port = port.lock();
         unsafe {
-            outb(port.base_address + SerialPort::IER, 
-                 0x3);  // received data, transmit empty
+            outb(port.base_address + SerialPort::IER,
+                 0xF);  // received data, transmit empty, line status, modem status
         }
     }       
 
-    pub fn set_baud_rate(&self, baud_rate: u32) -> () {
+    pub fn base_address(&self) -> u16 {
         let port = self.raw.enter();
         let port = port.lock();
-        port.set_baud_rate_internal(baud_rate);
+        port.base_address
+    }
+
+    /// Changes the line settings of a port that may be in use. Output
+    /// still queued is sent with the old settings first; on an error the
+    /// port is left as it was.
+    pub fn configure(&self, config: &SerialConfig) -> Result<(), ConfigError> {
+        config.divisor()?;
+
+        let port = self.raw.enter();
+        let mut port = port.lock();
+        unsafe {
+            port.drain();
+            port.write_config(config)
+        }
+    }
+
+    pub fn config(&self) -> SerialConfig {
+        let port = self.raw.enter();
+        let port = port.lock();
+        port.config
+    }
+
+    /// Changes just the speed, see `configure`.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<(), ConfigError> {
+        let config = SerialConfig { baud_rate, ..self.config() };
+        self.configure(&config)
     }
 
     pub fn try_receive(&self) -> Option<u8> {
         let port = self.raw.enter();
         let mut port = port.lock();
-        port.in_queue.try_dequeue()
+        unsafe { port.receive() }
+    }
+
+    /// The event signaled whenever bytes are received.
+    pub fn ready_event(&self) -> &Event {
+        &self.ready
+    }
+
+    /// The event signaled on overrun, parity and framing errors and breaks;
+    /// `stats` tells which.
+    pub fn line_error_event(&self) -> &Event {
+        &self.line_error
+    }
+
+    /// The event signaled when CTS, DSR, RI or DCD change.
+    pub fn modem_status_event(&self) -> &Event {
+        &self.modem_status_changed
+    }
+
+    /// The event signaled when bytes leave the output queue.
+    pub fn space_event(&self) -> &Event {
+        &self.space
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        let port = self.raw.enter();
+        let port = port.lock();
+        port.stats
+    }
+
+    /// The modem status register as of the last change.
+    pub fn modem_status(&self) -> u8 {
+        let port = self.raw.enter();
+        let port = port.lock();
+        port.modem_status
     }
 
     pub fn try_write(&self, b: u8) -> Result<(),()> {
+        if self.write_nonblocking(&[b]) == 1 { Ok(()) } else { Err(()) }
+    }
+
+    /// Queues as much of `bytes` as fits without waiting, and returns how
+    /// many bytes that was. Safe to call from interrupt handlers.
+    pub fn write_nonblocking(&self, bytes: &[u8]) -> usize {
         let port = self.raw.enter();
         let mut port = port.lock();
-        if port.out_queue.count > 0 {
-            return port.out_queue.enqueue(b);
-        }
+        unsafe { port.enqueue(bytes) }
+    }
 
+    /// Queues all of `bytes` without parking, feeding the UART by polling
+    /// whenever the output queue is full. Safe to call from interrupt
+    /// handlers, but it keeps interrupts off until done. Bytes are only
+    /// dropped, and counted in `SerialStats::unsent`, when the other end
+    /// holds us off or the UART stops taking them.
+    pub fn write_polling(&self, mut bytes: &[u8]) {
+        let port = self.raw.enter();
+        let mut port = port.lock();
         unsafe {
-            if inb(port.base_address + SerialPort::LSR) & 0x20 == 0x20 {
-            outb(port.base_address, b);
-                Ok(())
-            } else {
-                Err(())    
+            loop {
+                let taken = port.enqueue(bytes);
+                bytes = &bytes[taken..];
+                if bytes.is_empty() || !port.poll_for_room() {
+                    break;
+                }
+            }
+        }
+        port.stats.unsent += bytes.len();
+    }
+
+    /// Queues all of `bytes`, parking the calling thread with `waiter`
+    /// whenever the output queue is full. Only for threads: nothing else
+    /// can wait for the THR empty interrupt to make room.
+    pub fn write<W: Waiter>(&self, mut bytes: &[u8], waiter: &mut W) {
+        while !bytes.is_empty() {
+            self.space.reset();
+            let taken = self.write_nonblocking(bytes);
+            bytes = &bytes[taken..];
+            if !bytes.is_empty() {
+                waiter.wait(&[&self.space]);
             }
         }
     }
@@ -145,19 +489,46 @@ impl SerialPort {
                 }
 
                 match (flags >> 1) & 0x7 {
-                    1 => {
+                    SerialPort::IIR_THR_EMPTY => {
+                        port.write_out_bytes();
+                    },
+                    SerialPort::IIR_DATA_AVAILABLE | SerialPort::IIR_CHARACTER_TIMEOUT |
+                    SerialPort::IIR_LINE_STATUS => {
+                        // the same loop clears both, as it reads the LSR
+                        let (received, errors) = port.read_in_bytes();
+                        if received {
+                            self.ready.signal();
+                        }
+                        if errors {
+                            self.line_error.signal();
+                        }
+                    },
+                    SerialPort::IIR_MODEM_STATUS => {
+                        port.modem_status = inb(port.base_address + SerialPort::MSR);
+                        self.modem_status_changed.signal();
+                        // CTS may have come back
                         port.write_out_bytes();
                     },
-                    2 | 6  => {
-                        port.read_in_bytes();
+                    _ => {
+                        // nothing we know how to acknowledge; better to miss
+                        // it than to spin in an interrupt handler
+                        port.stats.unknown_interrupts += 1;
+                        break;
                     },
-                    i => panic!("Unknown interrupt source: {:x}", i),
                 }
             }
         }
+
+        // any of the above may have sent something
+        if !port.out_queue.is_full() {
+            self.space.signal();
+        }
     }
 }
 
+/// Writes to a port without parking, for `kprint!` outside threads and
+/// anything else that may run in an interrupt handler; see
+/// `SerialPort::write_polling`.
 pub struct SerialPortWriter<'a> {
     port: &'a SerialPort
 }
@@ -172,12 +543,107 @@ impl<'a> SerialPortWriter<'a> {
 
 impl<'a> Write for SerialPortWriter<'a> {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        for b in s.bytes() {
-            while Err(()) == self.port.try_write(b) { 
-                self.port.on_interrupt();
+        self.port.write_polling(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes to a port from a thread, parking it while the output queue is
+/// full, so that nothing is lost.
+pub struct BlockingWriter<'a, W: Waiter + 'a> {
+    port: &'a SerialPort,
+    waiter: &'a mut W,
+}
+
+impl<'a, W: Waiter> BlockingWriter<'a, W> {
+    pub fn new(port: &'a SerialPort, waiter: &'a mut W) -> BlockingWriter<'a, W> {
+        BlockingWriter { port, waiter }
+    }
+}
+
+impl<'a, W: Waiter> Write for BlockingWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        self.port.write(s.as_bytes(), self.waiter);
+        Ok(())
+    }
+}
+
+/// How many times `PanicWriter` reads the LSR before giving up on a byte:
+/// several character times even at 50 baud, as each read takes about a
+/// microsecond.
+const PANIC_POLL_LIMIT: usize = 1_000_000;
+
+/// Writes straight to the UART at `base_address`, polling the LSR, for
+/// fault handlers and panics. It takes no lock and needs no interrupts, so
+/// it works whatever state the kernel is in; bytes still queued by
+/// `SerialPort` may be overtaken, and flow control is ignored. A port that
+/// never becomes ready only costs a bounded delay per byte.
+pub struct PanicWriter {
+    base_address: u16,
+}
+
+impl PanicWriter {
+    pub fn new(base_address: u16) -> PanicWriter {
+        PanicWriter { base_address }
+    }
+
+    pub fn write_byte(&mut self, b: u8) {
+        unsafe {
+            for _ in 0..PANIC_POLL_LIMIT {
+                if inb(self.base_address + SerialPort::LSR) & SerialPort::LSR_THR_EMPTY != 0 {
+                    outb(self.base_address, b);
+                    return;
+                }
             }
         }
+    }
+}
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for b in s.bytes() {
+            self.write_byte(b);
+        }
 
         Ok(())
     }
+}
+
+/// Talks to the UART at `base_address` by polling the LSR, for the
+/// debugger, which runs with the machine stopped in an exception handler.
+/// Like `PanicWriter` it takes no lock and bypasses the queues, but it
+/// waits for as long as it takes.
+pub struct PolledPort {
+    base_address: u16,
+}
+
+impl PolledPort {
+    pub fn new(base_address: u16) -> PolledPort {
+        PolledPort { base_address }
+    }
+
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if inb(self.base_address + SerialPort::LSR) & SerialPort::LSR_DATA_READY != 0 {
+                Some(inb(self.base_address))
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(b) = self.try_read_byte() {
+                return b;
+            }
+        }
+    }
+
+    pub fn write_byte(&mut self, b: u8) {
+        unsafe {
+            while inb(self.base_address + SerialPort::LSR) & SerialPort::LSR_THR_EMPTY == 0 {}
+            outb(self.base_address, b);
+        }
+    }
 }
\ No newline at end of file
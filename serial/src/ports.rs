@@ -0,0 +1,241 @@
+//! The four standard PC serial ports and what each of them is used for.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::usize;
+
+use x86::shared::io::{inb, outb};
+
+use {ConfigError, SerialConfig, SerialPort};
+
+pub const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
+pub const COM3: u16 = 0x3E8;
+pub const COM4: u16 = 0x2E8;
+
+pub const PORT_COUNT: usize = 4;
+
+/// Base addresses of COM1 to COM4, in that order.
+pub const PORTS: [u16; PORT_COUNT] = [COM1, COM2, COM3, COM4];
+
+/// The IRQ of port `index`: COM1 and COM3 share IRQ 4, COM2 and COM4 IRQ 3.
+pub fn irq(index: usize) -> u8 {
+    if index % 2 == 0 { 4 } else { 3 }
+}
+
+pub fn name(index: usize) -> &'static str {
+    ["COM1", "COM2", "COM3", "COM4"][index]
+}
+
+const SCRATCH: u16 = 7;
+
+/// Whether a UART answers at `base_address`, found by writing patterns to
+/// its scratch register and reading them back. Nothing there reads 0xFF.
+pub fn probe(base_address: u16) -> bool {
+    unsafe {
+        let old = inb(base_address + SCRATCH);
+        for &pattern in &[0x55, 0xAA] {
+            outb(base_address + SCRATCH, pattern);
+            if inb(base_address + SCRATCH) != pattern {
+                return false;
+            }
+        }
+        outb(base_address + SCRATCH, old);
+    }
+
+    true
+}
+
+/// What a port is used for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    /// The interactive session: shell input and output.
+    Console,
+    /// Kernel log messages.
+    Log,
+    /// The framed protocol host tools drive the kernel with; never shared
+    /// with the others, as it isn't text.
+    Rpc,
+    /// The line to gdb, see the `gdb` crate; never shared either.
+    Debug,
+}
+
+const ROLE_COUNT: usize = 4;
+
+pub const ROLES: [Role; ROLE_COUNT] = [Role::Console, Role::Log, Role::Rpc, Role::Debug];
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Console => "console",
+            Role::Log => "log",
+            Role::Rpc => "rpc",
+            Role::Debug => "debug",
+        }
+    }
+
+    /// Whether the role needs its port to itself: each reads everything
+    /// that comes in, so a port with two of them loses input to one.
+    fn is_exclusive(self) -> bool {
+        self == Role::Rpc || self == Role::Debug
+    }
+}
+
+/// Why `SerialPorts::assign` refused.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AssignError {
+    NoSuchPort,
+    /// The port fills this other role, and one of the two can't share.
+    InUse(Role),
+}
+
+const NO_PORT: usize = usize::MAX;
+
+/// All serial ports found at boot, and which of them fills each `Role`.
+pub struct SerialPorts {
+    ports: [SerialPort; PORT_COUNT],
+    present: [AtomicBool; PORT_COUNT],
+    roles: [AtomicUsize; ROLE_COUNT],
+}
+
+impl SerialPorts {
+    pub fn new() -> SerialPorts {
+        SerialPorts {
+            ports: [SerialPort::create(COM1), SerialPort::create(COM2),
+                    SerialPort::create(COM3), SerialPort::create(COM4)],
+            present: [AtomicBool::new(false), AtomicBool::new(false),
+                      AtomicBool::new(false), AtomicBool::new(false)],
+            roles: [AtomicUsize::new(NO_PORT), AtomicUsize::new(NO_PORT),
+                    AtomicUsize::new(NO_PORT), AtomicUsize::new(NO_PORT)],
+        }
+    }
+
+    /// Probes for each port and sets up the ones that are there with
+    /// `config`. The first port found becomes the console, the second one,
+    /// if there is one, takes the log, a third one the RPC protocol and a
+    /// fourth one the debugger.
+    pub fn init(&self, config: &SerialConfig) -> Result<(), ConfigError> {
+        for (index, port) in self.ports.iter().enumerate() {
+            if probe(PORTS[index]) {
+                port.init(config)?;
+                self.present[index].store(true, Ordering::SeqCst);
+            }
+        }
+
+        let mut found = (0..PORT_COUNT).filter(|&index| self.is_present(index));
+        if let Some(first) = found.next() {
+            let second = found.next().unwrap_or(first);
+            self.roles[Role::Console as usize].store(first, Ordering::SeqCst);
+            self.roles[Role::Log as usize].store(second, Ordering::SeqCst);
+            for &role in &[Role::Rpc, Role::Debug] {
+                if let Some(index) = found.next() {
+                    self.roles[role as usize].store(index, Ordering::SeqCst);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_present(&self, index: usize) -> bool {
+        self.present[index].load(Ordering::SeqCst)
+    }
+
+    /// Port `index`, counting from 0 for COM1, if it was found.
+    pub fn get(&self, index: usize) -> Option<&SerialPort> {
+        if index < PORT_COUNT && self.is_present(index) {
+            Some(&self.ports[index])
+        } else {
+            None
+        }
+    }
+
+    /// Makes port `index` fill `role`. Fails if the port wasn't found, or
+    /// if it would share the RPC protocol or the debugger with anything.
+    pub fn assign(&self, role: Role, index: usize) -> Result<(), AssignError> {
+        if self.get(index).is_none() {
+            return Err(AssignError::NoSuchPort);
+        }
+
+        for &other in ROLES.iter() {
+            let shared = self.role_index(other) == Some(index) && other != role;
+            if shared && (role.is_exclusive() || other.is_exclusive()) {
+                return Err(AssignError::InUse(other));
+            }
+        }
+
+        self.roles[role as usize].store(index, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// The index of the port filling `role`, if any.
+    pub fn role_index(&self, role: Role) -> Option<usize> {
+        match self.roles[role as usize].load(Ordering::SeqCst) {
+            NO_PORT => None,
+            index => Some(index),
+        }
+    }
+
+    pub fn for_role(&self, role: Role) -> Option<&SerialPort> {
+        self.role_index(role).and_then(|index| self.get(index))
+    }
+
+    /// The base address of the port filling `role`, without taking any
+    /// lock, for a `PanicWriter`.
+    pub fn base_address(&self, role: Role) -> Option<u16> {
+        // roles only ever go to ports that were found
+        self.role_index(role).map(|index| PORTS[index])
+    }
+
+    pub fn enable_interrupts(&self) {
+        for index in 0..PORT_COUNT {
+            if let Some(port) = self.get(index) {
+                port.enable_interrupts();
+            }
+        }
+    }
+
+    /// Handles `irq` for every port that shares it; each port checks its
+    /// own IIR and does nothing if it wasn't the one interrupting.
+    pub fn on_interrupt(&self, irq_number: u8) {
+        for index in 0..PORT_COUNT {
+            if irq(index) != irq_number {
+                continue;
+            }
+
+            if let Some(port) = self.get(index) {
+                port.on_interrupt();
+            }
+        }
+    }
+}
+
+impl Default for SerialPorts {
+    fn default() -> SerialPorts {
+        SerialPorts::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_roles() {
+        let ports = SerialPorts::new();
+        for index in 0..3 {
+            ports.present[index].store(true, Ordering::SeqCst);
+        }
+        ports.assign(Role::Console, 0).unwrap();
+        ports.assign(Role::Log, 0).unwrap();
+
+        assert_eq!(ports.assign(Role::Rpc, 0), Err(AssignError::InUse(Role::Console)));
+        assert_eq!(ports.assign(Role::Rpc, 3), Err(AssignError::NoSuchPort));
+        ports.assign(Role::Rpc, 1).unwrap();
+        assert_eq!(ports.assign(Role::Debug, 1), Err(AssignError::InUse(Role::Rpc)));
+        assert_eq!(ports.assign(Role::Log, 1), Err(AssignError::InUse(Role::Rpc)));
+        // moving a role to where it already is
+        ports.assign(Role::Rpc, 1).unwrap();
+        ports.assign(Role::Debug, 2).unwrap();
+        assert_eq!(ports.role_index(Role::Debug), Some(2));
+    }
+}
@@ -0,0 +1,101 @@
+//! Backtraces for panics and faults, from the chain of frame pointers: a
+//! function keeps its caller's `rbp` at `[rbp]` and the address to return
+//! to at `[rbp + 8]`, since `intermezzos.json` doesn't let the compiler
+//! leave frame pointers out.
+//!
+//! Addresses are named from the symbol table `embed-symbols`, in the
+//! `symbols-host` crate, writes into `KERNEL_SYMBOLS` once the kernel is
+//! linked; a kernel that didn't go through it prints addresses only.
+
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ::CONTEXT;
+use symbols::Table;
+
+/// Room for the symbol table; `embed-symbols` says so if it needs more.
+pub const SYMBOLS_SIZE: usize = 256 * 1024;
+
+/// Zeros until `embed-symbols` finds the `.symbols` section and writes the
+/// table there; mutable so that the compiler can't assume it stays zeros.
+#[no_mangle]
+#[link_section = ".symbols"]
+pub static mut KERNEL_SYMBOLS: [u8; SYMBOLS_SIZE] = [0; SYMBOLS_SIZE];
+
+/// Frames followed at most, in case the chain loops.
+const MAX_FRAMES: usize = 32;
+
+fn symbols() -> Option<Table<'static>> {
+    Table::new(unsafe { &KERNEL_SYMBOLS })
+}
+
+/// A code address, with the function it's in when the table says.
+pub struct Location {
+    pub address: usize,
+    /// Return addresses are just past their call, which can be the last
+    /// instruction of the function: they're looked up a byte earlier.
+    pub returns_to: bool,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.address)?;
+        let address = self.address as u64;
+        let lookup = if self.returns_to { address.saturating_sub(1) } else { address };
+        if let Some((symbol, _)) = symbols().and_then(|table| table.lookup(lookup)) {
+            write!(f, " {}+{:#x}", symbol.name, address - symbol.address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls `f` with the return addresses of the frames from `rbp` up.
+pub fn walk<F: FnMut(usize)>(mut rbp: usize, mut f: F) {
+    for _ in 0..MAX_FRAMES {
+        // threads start with rbp zero; anything unaligned isn't a frame
+        if rbp == 0 || rbp % 8 != 0 {
+            break;
+        }
+
+        let (caller_rbp, return_address) = unsafe {
+            (ptr::read_volatile(rbp as *const usize), ptr::read_volatile((rbp + 8) as *const usize))
+        };
+        if return_address == 0 {
+            break;
+        }
+        f(return_address);
+
+        // the stack grows down, so callers' frames are above
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+}
+
+/// The frame pointer of the function calling this.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let rbp: usize;
+    unsafe {
+        asm!("mov $0, rbp" : "=r"(rbp) ::: "intel");
+    }
+    rbp
+}
+
+static PRINTING: AtomicBool = AtomicBool::new(false);
+
+/// Prints a backtrace from `rip`, in the frame at `rbp`, for a fault or a
+/// panic. A broken frame chain can fault in turn; the backtrace of that
+/// fault is skipped rather than started over.
+pub fn print_on_fault(rip: usize, rbp: usize) {
+    if PRINTING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    kprintln_fault!(CONTEXT, "Backtrace:");
+    kprintln_fault!(CONTEXT, "  {}", Location { address: rip, returns_to: false });
+    walk(rbp, |address| kprintln_fault!(CONTEXT, "  {}", Location { address, returns_to: true }));
+    PRINTING.store(false, Ordering::SeqCst);
+}
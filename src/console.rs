@@ -0,0 +1,117 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use common::Waiter;
+use serial::Role;
+use spin::Mutex;
+use tty::{Input, Line, LineEditor};
+
+use ::{CONTEXT, SHELL_CONSOLE};
+
+/// Returned by `Console::read_line` when Ctrl-C was pressed.
+#[derive(Debug)]
+pub struct Interrupted;
+
+/// Writes line editing feedback to the shell console and the console's
+/// serial port.
+struct Echo;
+
+impl fmt::Write for Echo {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        kprint_console!(CONTEXT, SHELL_CONSOLE, "{}", s);
+        Ok(())
+    }
+}
+
+struct ConsoleData {
+    editor: LineEditor,
+    /// A line, kept by the editor, was completed while `poll_interrupt`
+    /// was draining input.
+    pending: bool,
+}
+
+/// The kernel's line discipline, merging keyboard and serial console input.
+pub struct Console {
+    data: Mutex<ConsoleData>,
+    interrupted: AtomicBool,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            data: Mutex::new(ConsoleData {
+                editor: LineEditor::new(),
+                pending: false,
+            }),
+            interrupted: AtomicBool::new(false),
+        }
+    }
+
+    fn next_char() -> Option<char> {
+        CONTEXT.keyboard.try_dequeue()
+            .or_else(|| {
+                CONTEXT.serial.for_role(Role::Console)
+                    .and_then(|port| port.try_receive())
+                    .map(|b| b as char)
+            })
+    }
+
+    /// Feeds all available input to the editor, stopping at the first
+    /// completed line or Ctrl-C.
+    fn pump(&self) -> Option<Input> {
+        let mut data = self.data.lock();
+        if data.pending {
+            data.pending = false;
+            return Some(Input::Line);
+        }
+
+        while let Some(c) = Console::next_char() {
+            match data.editor.feed(c, &mut Echo) {
+                Ok(Some(Input::Interrupt)) => {
+                    self.interrupted.store(true, Ordering::SeqCst);
+                    return Some(Input::Interrupt);
+                },
+                Ok(Some(input)) => return Some(input),
+                Ok(None) | Err(_) => {},
+            }
+        }
+
+        None
+    }
+
+    /// Returns the next line typed on either the keyboard or the serial
+    /// console, parking the calling thread until Enter is pressed.
+    pub fn read_line<W: Waiter>(&self, waiter: &mut W) -> Result<Line, Interrupted> {
+        loop {
+            // looked up every time around, the console may move to another port
+            let serial = CONTEXT.serial.for_role(Role::Console).map(|port| port.ready_event());
+            CONTEXT.keyboard.ready_event().reset();
+            if let Some(event) = serial {
+                event.reset();
+            }
+
+            match self.pump() {
+                Some(Input::Line) => return Ok(self.data.lock().editor.line()),
+                Some(Input::Interrupt) => {
+                    self.interrupted.store(false, Ordering::SeqCst);
+                    return Err(Interrupted);
+                },
+                None => {},
+            }
+
+            match serial {
+                Some(event) => waiter.wait(&[CONTEXT.keyboard.ready_event(), event]),
+                None => waiter.wait(&[CONTEXT.keyboard.ready_event()]),
+            }
+        }
+    }
+
+    /// Lets long-running work check whether Ctrl-C was pressed. Anything
+    /// else typed in the meantime keeps being edited as usual.
+    pub fn poll_interrupt(&self) -> bool {
+        if let Some(Input::Line) = self.pump() {
+            self.data.lock().pending = true;
+        }
+
+        self.interrupted.swap(false, Ordering::SeqCst)
+    }
+}
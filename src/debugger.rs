@@ -0,0 +1,218 @@
+//! Debugging the kernel with gdb, through the `gdb` stub, on the serial
+//! port with `Role::Debug`: the fourth one found, or any other picked with
+//! `serial debug <1-4>` in the shell. With QEMU, for instance,
+//!
+//! ```text
+//! qemu-system-x86_64 ... -serial stdio -serial null -serial null -serial tcp::1234,server,nowait
+//! ```
+//!
+//! and `target remote localhost:1234` in gdb. Typing anything in gdb, or
+//! Ctrl-C, stops the kernel wherever it is; breakpoints and single steps
+//! stop it in their exception handlers.
+
+use core::ptr;
+use spin::Mutex;
+
+use ::CONTEXT;
+use debugregs::{self, Condition, DebugRegisters, FLAGS_RF};
+use gdb::{register, Connection, Registers, Stub, Target, SIGINT};
+use interrupts::InterruptState;
+use serial::{PolledPort, Role, QUEUE_SIZE};
+
+lazy_static! {
+    static ref STUB: Mutex<Stub> = Mutex::new(Stub::new());
+}
+
+const CTRL_C: u8 = 0x03;
+
+/// The line to gdb, with what the serial driver received before the
+/// kernel stopped still to be read first.
+struct DebugLine {
+    port: PolledPort,
+    received: [u8; QUEUE_SIZE],
+    len: usize,
+    read: usize,
+}
+
+impl DebugLine {
+    fn new() -> Option<DebugLine> {
+        CONTEXT.serial.base_address(Role::Debug).map(|base_address| DebugLine {
+            port: PolledPort::new(base_address),
+            received: [0; QUEUE_SIZE],
+            len: 0,
+            read: 0,
+        })
+    }
+}
+
+impl Connection for DebugLine {
+    fn read_byte(&mut self) -> u8 {
+        if self.read < self.len {
+            self.read += 1;
+            return self.received[self.read - 1];
+        }
+
+        self.port.read_byte()
+    }
+
+    fn write_byte(&mut self, b: u8) {
+        self.port.write_byte(b);
+    }
+}
+
+/// The code an exception stopped, as its handler sees it.
+struct Stopped<'a> {
+    state: &'a mut InterruptState,
+}
+
+impl<'a> Target for Stopped<'a> {
+    fn registers(&self) -> Registers {
+        let state = &*self.state;
+        let regs = &state.regs;
+        let mut registers = Registers::default();
+        {
+            let values = &mut registers.values;
+            values[register::RAX] = regs.rax as u64;
+            values[register::RBX] = regs.rbx as u64;
+            values[register::RCX] = regs.rcx as u64;
+            values[register::RDX] = regs.rdx as u64;
+            values[register::RSI] = regs.rsi as u64;
+            values[register::RDI] = regs.rdi as u64;
+            values[register::RBP] = regs.rbp as u64;
+            values[register::RSP] = state.rsp as u64;
+            let high = [regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15];
+            for (value, &reg) in values[register::R8..register::R15 + 1].iter_mut().zip(high.iter()) {
+                *value = reg as u64;
+            }
+            values[register::RIP] = state.rip as u64;
+            values[register::EFLAGS] = state.flags as u64;
+            values[register::CS] = state.cs as u64;
+            values[register::SS] = state.ss as u64;
+            // the data segment registers aren't saved, and unused in long mode
+        }
+        registers
+    }
+
+    fn set_registers(&mut self, registers: &Registers) {
+        let values = &registers.values;
+        let state = &mut *self.state;
+        state.regs.rax = values[register::RAX] as *mut usize;
+        state.regs.rbx = values[register::RBX] as *mut usize;
+        state.regs.rcx = values[register::RCX] as *mut usize;
+        state.regs.rdx = values[register::RDX] as *mut usize;
+        state.regs.rsi = values[register::RSI] as *mut usize;
+        state.regs.rdi = values[register::RDI] as *mut usize;
+        state.regs.rbp = values[register::RBP] as *mut usize;
+        state.rsp = values[register::RSP] as *mut usize;
+        state.regs.r8 = values[register::R8] as *mut usize;
+        state.regs.r9 = values[register::R8 + 1] as *mut usize;
+        state.regs.r10 = values[register::R8 + 2] as *mut usize;
+        state.regs.r11 = values[register::R8 + 3] as *mut usize;
+        state.regs.r12 = values[register::R8 + 4] as *mut usize;
+        state.regs.r13 = values[register::R8 + 5] as *mut usize;
+        state.regs.r14 = values[register::R8 + 6] as *mut usize;
+        state.regs.r15 = values[register::R15] as *mut usize;
+        state.rip = values[register::RIP] as *mut usize;
+        state.flags = values[register::EFLAGS] as *mut usize;
+        // changing segments under the kernel's feet would only crash it
+    }
+
+    // Memory that isn't mapped page faults, and the kernel hangs there: gdb
+    // is trusted to ask for addresses that make sense, as it would be with
+    // any kernel debugger.
+
+    fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), ()> {
+        if address == 0 {
+            return Err(());
+        }
+
+        for (offset, b) in bytes.iter_mut().enumerate() {
+            *b = unsafe { ptr::read_volatile((address as usize + offset) as *const u8) };
+        }
+        Ok(())
+    }
+
+    fn write_memory(&mut self, address: u64, bytes: &[u8]) -> Result<(), ()> {
+        if address == 0 {
+            return Err(());
+        }
+
+        // code is writable, see disable_write_protect_bit
+        for (offset, &b) in bytes.iter().enumerate() {
+            unsafe { ptr::write_volatile((address as usize + offset) as *mut u8, b) };
+        }
+        Ok(())
+    }
+}
+
+/// Called by the breakpoint handler. Returns false if the breakpoint isn't
+/// one of gdb's, for the handler to deal with.
+pub fn on_breakpoint(state: &mut InterruptState) -> bool {
+    match DebugLine::new() {
+        Some(mut line) => STUB.lock().on_breakpoint(&mut line, &mut Stopped { state }),
+        None => false,
+    }
+}
+
+/// Called by the debug exception handler for single steps. Returns false if
+/// gdb didn't ask for the step.
+pub fn on_step(state: &mut InterruptState) -> bool {
+    match DebugLine::new() {
+        Some(mut line) => STUB.lock().on_step(&mut line, &mut Stopped { state }),
+        None => false,
+    }
+}
+
+/// Called by the debug exception handler with DR6, taken already. Reports
+/// the hardware breakpoints and watchpoints that fired, and returns whether
+/// any did.
+pub fn on_watchpoint(state: &mut InterruptState, status: debugregs::Status) -> bool {
+    let mut hit = false;
+    for (slot, watchpoint) in DebugRegisters::current().iter() {
+        if !status.triggered(slot) {
+            continue;
+        }
+
+        // the watched code may hold the screen lock
+        kprintln_fault!(CONTEXT, "Watchpoint {} ({}): {:?}", slot, watchpoint, state);
+        if watchpoint.condition == Condition::Execute {
+            // carry on past it instead of faulting on it again
+            state.flags = (state.flags as usize | FLAGS_RF) as *mut usize;
+        }
+        hit = true;
+    }
+    hit
+}
+
+/// Called by the serial interrupt handlers, after the driver: input on the
+/// debug port while the kernel runs means gdb wants to stop it, to attach
+/// or because Ctrl-C was pressed.
+pub fn on_serial_interrupt(state: &mut InterruptState) {
+    let port = match CONTEXT.serial.for_role(Role::Debug) {
+        Some(port) => port,
+        None => return,
+    };
+    let mut line = match DebugLine::new() {
+        Some(line) => line,
+        None => return,
+    };
+
+    let mut interrupted = false;
+    while let Some(b) = port.try_receive() {
+        if b == CTRL_C {
+            interrupted = true;
+        } else if line.len < line.received.len() {
+            line.received[line.len] = b;
+            line.len += 1;
+        }
+    }
+
+    // stray acknowledgements don't stop anything; a packet does, and gdb
+    // waits for the answer to it rather than for a stop reply
+    let packet = line.received[..line.len].contains(&b'$');
+    if interrupted {
+        STUB.lock().run(&mut line, &mut Stopped { state }, Some(SIGINT));
+    } else if packet {
+        STUB.lock().run(&mut line, &mut Stopped { state }, None);
+    }
+}
@@ -9,34 +9,80 @@ macro_rules! kprintln {
     ($ctx:ident, $fmt:expr, $($arg:tt)*) => (kprint!($ctx, concat!($fmt, "\n"), $($arg)*));
 }
 
+/// Prints to the log console (0) and to the serial port.
 #[macro_export]
 macro_rules! kprint {
-    ($ctx:ident, $($arg:tt)*) => ({
+    ($ctx:ident, $($arg:tt)*) => (kprint_console!($ctx, 0, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! kprintln_console {
+    ($ctx:ident, $console:expr, $fmt:expr) => (kprint_console!($ctx, $console, concat!($fmt, "\n")));
+    ($ctx:ident, $console:expr, $fmt:expr, $($arg:tt)*) => (kprint_console!($ctx, $console, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Prints to the given virtual console and to a serial port: the log port
+/// for the log console (0), the console port for the others. Nothing is
+/// lost when the port's queue fills up: a thread is parked until there is
+/// room, and anything that can't park polls the UART instead.
+#[macro_export]
+macro_rules! kprint_console {
+    ($ctx:ident, $console:expr, $($arg:tt)*) => ({
         use core::fmt::Write;
-        use serial::SerialPortWriter;
+        use serial::{BlockingWriter, Role, SerialPortWriter};
+        use thread::CurrentThread;
+        let console = $console;
         {
             let mut vga = $ctx.vga.lock();
-            vga.write_fmt(format_args!($($arg)*)).unwrap();
+            vga.console(console).write_fmt(format_args!($($arg)*)).unwrap();
             vga.flush();
         }
-        {
-            let mut port_writer = SerialPortWriter::from_port(&($ctx).com1);
-            port_writer.write_fmt(format_args!($($arg)*)).unwrap();
+        let role = if console == 0 { Role::Log } else { Role::Console };
+        if let Some(port) = ($ctx).serial.for_role(role) {
+            if let Some(mut thread) = CurrentThread::get() {
+                BlockingWriter::new(port, &mut thread).write_fmt(format_args!($($arg)*)).unwrap();
+            } else {
+                SerialPortWriter::from_port(port).write_fmt(format_args!($($arg)*)).unwrap();
+            }
+        }
+    });
+}
+
+#[macro_export]
+macro_rules! kprintln_fault {
+    ($ctx:ident, $fmt:expr) => (kprint_fault!($ctx, concat!($fmt, "\n")));
+    ($ctx:ident, $fmt:expr, $($arg:tt)*) => (kprint_fault!($ctx, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Like `kprint!`, for fault handlers and panics: it never waits for a
+/// lock, skipping the screen if it is taken, and polls the log port
+/// directly instead of going through its queue.
+#[macro_export]
+macro_rules! kprint_fault {
+    ($ctx:ident, $($arg:tt)*) => ({
+        use core::fmt::Write;
+        use serial::{PanicWriter, Role};
+        if let Some(mut vga) = $ctx.vga.try_lock() {
+            let _ = vga.console(0).write_fmt(format_args!($($arg)*));
+            vga.flush();
+        }
+        if let Some(base_address) = ($ctx).serial.base_address(Role::Log) {
+            let _ = PanicWriter::new(base_address).write_fmt(format_args!($($arg)*));
         }
     });
 }
 
+/// Replaces the text of the header row at the top of the log console.
 #[macro_export]
 macro_rules! kprint_header {
     ($ctx:ident, $($arg:tt)*) => ({
         use core::fmt::Write;
         let mut vga = $ctx.vga.lock();
-        let old_position = vga.position;
-        vga.invert();
-        vga.position = 0;
-        vga.write_fmt(format_args!($($arg)*)).unwrap();
-        vga.invert();
-        vga.position = old_position;
+        {
+            let mut header = vga.console(0).region($ctx.header);
+            header.clear();
+            header.write_fmt(format_args!($($arg)*)).unwrap();
+        }
         vga.flush();
     });
 }
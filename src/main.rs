@@ -7,11 +7,17 @@
 #![no_main]
 
 extern crate common;
+extern crate debugregs;
+extern crate gdb;
 extern crate keyboard;
+extern crate mouse;
 #[macro_use]
 extern crate interrupts;
 extern crate pic;
+extern crate rpc;
 extern crate serial;
+extern crate symbols;
+extern crate tty;
 extern crate vga;
 
 #[macro_use]
@@ -28,38 +34,75 @@ extern crate wasmi;
 
 #[cfg(not(test))]
 pub mod panic;
+mod backtrace;
+mod console;
+mod debugger;
+pub mod remote;
+pub mod shell;
 mod thread;
+mod trace;
 
+use console::Console;
 use core::intrinsics;
 use core::sync::atomic::{AtomicUsize,Ordering};
 use interrupts::{Idt, IdtRef};
-use keyboard::Keyboard;
+use keyboard::{Hotkey, Keyboard};
+use mouse::Mouse;
 use spin::Mutex;
-use serial::{SerialPort,COM1};
+use serial::{SerialConfig,SerialPorts};
 use thread::*;
-use vga::Vga;
+use vga::{Edge, RegionId, Vga, MAIN_REGION};
 use wasmi::{ImportsBuilder, Module, ModuleInstance, NopExternals, RuntimeValue};
 use x86::bits64::irq::IdtEntry;
 
+/// Real port I/O for the VGA registers.
+pub struct VgaIo;
+
+impl vga::Io for VgaIo {
+    fn inb(&mut self, port: u16) -> u8 {
+        unsafe { x86::shared::io::inb(port) }
+    }
+
+    fn outb(&mut self, port: u16, value: u8) {
+        unsafe { x86::shared::io::outb(port, value) }
+    }
+}
+
 pub struct Context {
-    pub vga: Mutex<Vga<&'static mut [u8]>>,
+    pub vga: Mutex<Vga<&'static mut [u8], VgaIo>>,
     pub idt: IdtRef<'static>,
-    pub com1: SerialPort,
+    pub serial: SerialPorts,
     pub keyboard: Keyboard,
+    pub mouse: Mouse,
+    pub console: Console,
+    /// The pinned top row of the log console, see `kprint_header!`.
+    pub header: RegionId,
     time: AtomicUsize,
 }
 
 impl Context {
     pub fn new(idt: &'static Idt) -> Context {
+        // all of the text mode window, so that the font plane fits through it
         let slice = unsafe {
-            core::slice::from_raw_parts_mut(0xb8000 as *mut u8, 4000)
+            core::slice::from_raw_parts_mut(0xb8000 as *mut u8, 0x8000)
+        };
+
+        let mut vga = Vga::with_io(slice, VgaIo);
+        let header = {
+            let console = vga.console(0);
+            let header = console.split(MAIN_REGION, Edge::Top, 1).unwrap();
+            console.region(header).invert();
+            header
         };
 
         Context {
-            vga: Mutex::new(Vga::new(slice)),
+            vga: Mutex::new(vga),
             idt: IdtRef::from_idt(idt),
             keyboard: Keyboard::new(),
-            com1: SerialPort::create(COM1),
+            mouse: Mouse::new(),
+            console: Console::new(),
+            header,
+            serial: SerialPorts::new(),
             time: AtomicUsize::new(0)
         }
     }
@@ -98,6 +141,21 @@ struct MyAllocator
 
 unsafe impl core::marker::Sync for MyAllocator {}
 
+impl MyAllocator {
+    /// Bytes handed out so far; nothing is ever freed.
+    pub fn used(&self) -> usize {
+        if self.cur == null_mut() {
+            0
+        } else {
+            self.cur as usize - &self.bytes[0] as *const u8 as usize
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
 unsafe impl GlobalAlloc for MyAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut new = self.cur;
@@ -133,12 +191,17 @@ fn foo(layout: core::alloc::Layout) -> ! {
 }
 
 #[global_allocator]
-static A: MyAllocator = MyAllocator {
+pub static A: MyAllocator = MyAllocator {
     bytes: [0u8; 1024*1024],
     cur: null_mut(),
     // max: null_mut(),
 };
 
+/// Virtual console the shell runs on; `kprint!` logs to console 0.
+pub const SHELL_CONSOLE: usize = 1;
+
+pub const WASM_SAMPLE_APP: &[u8] = include_bytes!("../wasm-sample-app/target/wasm32-unknown-unknown/release/wasm_sample_app.wasm");
+
 fn dump_last_instruction(state: &interrupts::InterruptState) {
     let mut instruction_size = 16;
     unsafe {
@@ -162,77 +225,129 @@ fn dump_last_instruction(state: &interrupts::InterruptState) {
     }
 }
 
+/// What else is worth knowing after a fault, once its registers are out:
+/// how the code got to `rip`, in the frame at `rbp`.
+pub fn report_fault(rip: usize, rbp: usize) {
+    trace::dump_on_fault();
+    backtrace::print_on_fault(rip, rbp);
+}
+
+/// `report_fault` for the exceptions that push an error code, which
+/// `make_idt_entry!` doesn't skip: the code is in the `rip` slot, and each
+/// slot after it holds the one before, so the faulting rip is in `cs`.
+fn report_fault_with_error_code(state: &interrupts::InterruptState) {
+    report_fault(state.cs as usize, state.regs.rbp as usize);
+}
+
 #[no_mangle]
 pub fn _start() -> ! {
     kprintln!(CONTEXT, "Initializing APIC...");
 
     pic::remap();
 
-    kprintln!(CONTEXT, "Initializing COM1...");
-    CONTEXT.com1.init();
+    kprintln!(CONTEXT, "Initializing serial ports...");
+    CONTEXT.serial.init(&SerialConfig::default()).unwrap();
+    for index in 0..serial::PORT_COUNT {
+        if CONTEXT.serial.is_present(index) {
+            kprintln!(CONTEXT, "{} found", serial::name(index));
+        }
+    }
+
+    kprintln!(CONTEXT, "Initializing PS/2 controller...");
+    match CONTEXT.keyboard.init() {
+        Ok(info) => kprintln!(CONTEXT, "PS/2 controller: {:?}", info),
+        Err(e) => kprintln!(CONTEXT, "PS/2 controller failed: {:?}", e),
+    }
+
+    kprintln!(CONTEXT, "Initializing PS/2 mouse...");
+    match CONTEXT.mouse.init() {
+        Ok(has_wheel) => kprintln!(CONTEXT, "PS/2 mouse wheel: {}", has_wheel),
+        Err(e) => kprintln!(CONTEXT, "PS/2 mouse failed: {:?}", e),
+    }
 
     kprintln!(CONTEXT, "Configuring interrupts...");
 
-    CONTEXT.idt.set_handler(0, make_idt_entry!(isr0, |state| {
-        kprintln!(CONTEXT, "Divide by zero: {:?}", state);
+    CONTEXT.idt.set_handler(0, make_idt_entry!(isr0, 0, |state: &mut interrupts::InterruptState| {
+        kprintln_fault!(CONTEXT, "Divide by zero: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(1, make_idt_entry!(isr1, |state: &mut interrupts::InterruptState| {
-        kprintln!(CONTEXT, "Trap: {:?}", state);
-        dump_last_instruction(state);
+    CONTEXT.idt.set_handler(1, make_idt_entry!(isr1, 1, |state: &mut interrupts::InterruptState| {
+        let status = debugregs::Status::take();
+        let hit = debugger::on_watchpoint(state, status);
+        let stepped = (status.single_step() || !hit) && debugger::on_step(state);
+        let traced = status.single_step() && !stepped && trace::on_step(state);
+        if !hit && !stepped && !traced {
+            kprintln!(CONTEXT, "Trap: {:?}", state);
+            dump_last_instruction(state);
+        }
 
         pic::eoi_for(1);
     }));
-    CONTEXT.idt.set_handler(2, make_idt_entry!(isr2, |state| {
-        kprint!(CONTEXT, "NMI: {:?}", state);
+    CONTEXT.idt.set_handler(2, make_idt_entry!(isr2, 2, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "NMI: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(3, make_idt_entry!(isr3, |state| {
-        kprintln!(CONTEXT, "Breakpoint: {:?}", state);
+    CONTEXT.idt.set_handler(3, make_idt_entry!(isr3, 3, |state: &mut interrupts::InterruptState| {
+        if !debugger::on_breakpoint(state) {
+            kprintln!(CONTEXT, "Breakpoint: {:?}", state);
+        }
         pic::eoi_for(3);
     }));
-    CONTEXT.idt.set_handler(4, make_idt_entry!(isr4, |state| {
-        kprint!(CONTEXT, "Overflow: {:?}", state);
+    CONTEXT.idt.set_handler(4, make_idt_entry!(isr4, 4, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Overflow: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(5, make_idt_entry!(isr5, |state| {
-        kprint!(CONTEXT, "Bounds: {:?}", state);
+    CONTEXT.idt.set_handler(5, make_idt_entry!(isr5, 5, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Bounds: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(6, make_idt_entry!(isr6, |state| {
-        kprint!(CONTEXT, "Invalid opcode: {:?}", state);
+    CONTEXT.idt.set_handler(6, make_idt_entry!(isr6, 6, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Invalid opcode: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(7, make_idt_entry!(isr7, |state| {
-        kprint!(CONTEXT, "Device not available: {:?}", state);
+    CONTEXT.idt.set_handler(7, make_idt_entry!(isr7, 7, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Device not available: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(8, make_idt_entry!(isr8, |state| {
-        kprint!(CONTEXT, "Double fault: {:?}", state);
+    CONTEXT.idt.set_handler(8, make_idt_entry!(isr8, 8, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Double fault: {:?}", state);
+        report_fault_with_error_code(state);
         loop {}
     }));
-    CONTEXT.idt.set_handler(9, make_idt_entry!(isr9, |state| {
-        kprint!(CONTEXT, "Coprocessor segment overrun: {:?}", state);
+    CONTEXT.idt.set_handler(9, make_idt_entry!(isr9, 9, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Coprocessor segment overrun: {:?}", state);
+        report_fault(state.rip as usize, state.regs.rbp as usize);
         loop {}
     }));
-    CONTEXT.idt.set_handler(10, make_idt_entry!(isr10, |state| {
-        kprint!(CONTEXT, "Invalid TSS: {:?}", state);
+    CONTEXT.idt.set_handler(10, make_idt_entry!(isr10, 10, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Invalid TSS: {:?}", state);
+        report_fault_with_error_code(state);
         loop {}
     }));
-    CONTEXT.idt.set_handler(11, make_idt_entry!(isr11, |state| {
-        kprint!(CONTEXT, "Segment not present: {:?}", state);
+    CONTEXT.idt.set_handler(11, make_idt_entry!(isr11, 11, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Segment not present: {:?}", state);
+        report_fault_with_error_code(state);
         loop {}
     }));
-    CONTEXT.idt.set_handler(12, make_idt_entry!(isr12, |state| {
-        kprint!(CONTEXT, "Stack segment fault: {:?}", state);
+    CONTEXT.idt.set_handler(12, make_idt_entry!(isr12, 12, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Stack segment fault: {:?}", state);
+        report_fault_with_error_code(state);
         loop {}
     }));
-    CONTEXT.idt.set_handler(13, make_idt_entry!(isr13, |state| {
-        kprint!(CONTEXT, "General protection fault: {:?}", state);
+    CONTEXT.idt.set_handler(13, make_idt_entry!(isr13, 13, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "General protection fault: {:?}", state);
+        report_fault_with_error_code(state);
         loop {}
     }));
-    CONTEXT.idt.set_handler(14, make_idt_entry!(isr14, |state| {
-        kprint!(CONTEXT, "Page fault: {:?}", state);
+    CONTEXT.idt.set_handler(14, make_idt_entry!(isr14, 14, |state: &mut interrupts::InterruptState| {
+        kprint_fault!(CONTEXT, "Page fault: {:?}", state);
+        report_fault_with_error_code(state);
         //dump_last_instruction(state);
         loop { unsafe { x86::shared::halt(); } }
     }));
@@ -240,44 +355,59 @@ pub fn _start() -> ! {
     // IRQ0 (0) on PIC1 (32), so IDT index is 32
     // Keyboard uses IRQ1 and PIC1 has been remapped to 0x20 (32); therefore
     // the index in the IDT for IRQ1 will be 32 + 1 = 33
-    CONTEXT.idt.set_handler(32, make_idt_entry!(isr32, |_state| {
+    CONTEXT.idt.set_handler(32, make_idt_entry!(isr32, 32, |_state| {
         CONTEXT.on_tick();
         pic::eoi_for(32);
     }));
-    CONTEXT.idt.set_handler(33, make_idt_entry!(isr33, |_state| {
+    CONTEXT.idt.set_handler(33, make_idt_entry!(isr33, 33, |_state| {
         CONTEXT.keyboard.isr();
         pic::eoi_for(33);
     }));
-    CONTEXT.idt.set_handler(35, make_idt_entry!(isr35, |_state| {
-        kprint!(CONTEXT, "COM2/4 ISR");
+    // COM2 and COM4 share IRQ3, COM1 and COM3 IRQ4
+    CONTEXT.idt.set_handler(35, make_idt_entry!(isr35, 35, |state: &mut interrupts::InterruptState| {
+        CONTEXT.serial.on_interrupt(3);
         pic::eoi_for(35);
+        debugger::on_serial_interrupt(state);
+    }));
+    // IRQ12 is on PIC2 (40), so IDT index is 40 + 4 = 44
+    CONTEXT.idt.set_handler(44, make_idt_entry!(isr44, 44, |_state| {
+        CONTEXT.mouse.isr();
+        pic::eoi_for(44);
     }));
-    CONTEXT.idt.set_handler(36, make_idt_entry!(isr36, |_state| {
-        // kprintln!(CONTEXT, "COM1/3 ISR enter");
-        CONTEXT.com1.on_interrupt();
-        // kprintln!(CONTEXT, "COM1/3 ISR exit");
+    CONTEXT.idt.set_handler(36, make_idt_entry!(isr36, 36, |state: &mut interrupts::InterruptState| {
+        CONTEXT.serial.on_interrupt(4);
         pic::eoi_for(36);
+        debugger::on_serial_interrupt(state);
     }));
 
-    kprintln!(CONTEXT, "Configuring COM1...");
-    CONTEXT.com1.enable_interrupts();
+    kprintln!(CONTEXT, "Configuring serial ports...");
+    CONTEXT.serial.enable_interrupts();
+    pic::enable_irq(3);
     pic::enable_irq(4);
 
+    kprintln!(CONTEXT, "Configuring keyboard...");
+    pic::enable_irq(1);
+
+    kprintln!(CONTEXT, "Configuring mouse...");
+    pic::enable_irq(2); // cascade from PIC2
+    pic::enable_irq(12);
+
     kprintln!(CONTEXT, "Kernel initialized.");
     kprintln!(CONTEXT, "Pic mask: {:x}", pic::get_mask());
     kprintln!(CONTEXT, "Enabling interrupts.");
     CONTEXT.idt.enable_interrupts();
 
     let mut main_thread = Scheduler::new();
-    main_thread.create_thread("echo", echo, 0);
     main_thread.create_thread("clock", clock, 0);
-    main_thread.create_thread("keyboard", keyboard, 0);
-
+    main_thread.create_thread("shell", shell::shell, 0);
+    main_thread.create_thread("hotkeys", hotkeys, 0);
+    main_thread.create_thread("remote", remote::server, 0);
 
-    let bytes = include_bytes!("../wasm-sample-app/target/wasm32-unknown-unknown/release/wasm_sample_app.wasm");
+    shell::init();
+    remote::init();
 
     disable_write_protect_bit();
-    let module = Module::from_buffer(&bytes[..]).unwrap();
+    let module = Module::from_buffer(WASM_SAMPLE_APP).unwrap();
     assert!(module.deny_floating_point().is_ok());
 
     let main = ModuleInstance::new(&module, &ImportsBuilder::default())
@@ -291,7 +421,7 @@ pub fn _start() -> ! {
     let result = main.invoke_export("wasm_add", &[RuntimeValue::I32(1), RuntimeValue::I32(2)], &mut NopExternals);
     kprintln!(CONTEXT, "Result: {:?} =? {}", result, a + b);
 
-    kprintln!(CONTEXT, "Beginning main loop.");
+    kprintln!(CONTEXT, "Beginning main loop. Press Alt+F{} for the shell.", SHELL_CONSOLE + 1);
     
     main_thread.run();
 }
@@ -382,7 +512,7 @@ fn toggle_single_step() {
     }
 }
 
-fn shutdown() {
+pub fn shutdown() {
     unsafe {
         // https://wiki.osdev.org/Shutdown
         // In newer versions of QEMU, you can pass -device isa-debug-exit,iobase=0xf4,iosize=0x04 on the command-line, and do: 
@@ -390,26 +520,12 @@ fn shutdown() {
     }
 }
 
-pub fn echo(ctxt: &mut ThreadContext, _arg: usize) {
-    loop { 
-        while let Some(b) = CONTEXT.com1.try_receive() {
-            match b as char {
-                'Q' => { shutdown(); },
-                _ => {},
-            };
-            
-            kprint!(CONTEXT, "{}", b as char);
-        }
-        ctxt.yield_to();
-    }
-}
-
 pub fn clock(ctxt: &mut ThreadContext, _arg: usize) {
     let mut last_displayed = 0;
     loop { 
         let ticks = CONTEXT.ticks();
         if ticks - last_displayed > 00 {
-            kprint_header!(CONTEXT, "ticks: {}\n", ticks);
+            kprint_header!(CONTEXT, "ticks: {}", ticks);
             last_displayed = ticks;
         }
         
@@ -417,19 +533,18 @@ pub fn clock(ctxt: &mut ThreadContext, _arg: usize) {
     }
 }
 
-pub fn keyboard(ctxt: &mut ThreadContext, _arg: usize) {
-    loop { 
-        while let Some(b) = CONTEXT.keyboard.try_dequeue() {
-            while CONTEXT.com1.try_write(b as u8) != Ok(()) { }
-            match b {
-                'Q' => {
-                    shutdown();
-                },
-                _ => {
-                    kprint!(CONTEXT, "{}", b);
-                }
-            };
+/// Handles the key combinations the keyboard driver keeps for the kernel.
+pub fn hotkeys(ctxt: &mut ThreadContext, _arg: usize) {
+    loop {
+        let hotkey = CONTEXT.keyboard.read_hotkey(ctxt);
+        let mut vga = CONTEXT.vga.lock();
+        let active = vga.active_console();
+
+        match hotkey {
+            Hotkey::SwitchConsole(index) => vga.switch_to(index),
+            Hotkey::ScrollBack => vga.console(active).page_up(),
+            Hotkey::ScrollForward => vga.console(active).page_down(),
         }
-        ctxt.yield_to();
+        vga.flush();
     }
-}
\ No newline at end of file
+}
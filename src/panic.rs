@@ -1,9 +1,11 @@
 use ::CONTEXT;
+use backtrace;
 use core::panic::PanicInfo;
 
 #[panic_handler]
 #[no_mangle]
 pub fn panic(info: &PanicInfo) -> ! {
-    kprintln!(CONTEXT, "KERNEL PANIC: {:?}", info);
+    kprintln_fault!(CONTEXT, "KERNEL PANIC: {:?}", info);
+    ::report_fault(panic as usize, backtrace::frame_pointer());
     loop {}
 }
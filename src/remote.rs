@@ -0,0 +1,301 @@
+//! The kernel end of the `rpc` protocol: a thread that answers requests
+//! from host tools on the serial port with `Role::Rpc`.
+
+use core::fmt::Write;
+use core::str;
+use common::Waiter;
+use spin::Mutex;
+use wasmi::RuntimeValue;
+
+use ::CONTEXT;
+use rpc::{self, command, crc, status, value, Decoder, Frame, Kind, MAX_ENCODED};
+use serial::{Role, SerialPort};
+use shell;
+use thread::ThreadContext;
+
+/// Handles a request: gets its payload, adds to the response's and returns
+/// a `status`.
+pub type HandlerFunc = fn(&mut ThreadContext, &[u8], &mut Frame) -> u8;
+
+#[derive(Clone, Copy)]
+pub struct Handler {
+    /// The `command` it handles.
+    pub code: u8,
+    pub func: HandlerFunc,
+}
+
+const MAX_HANDLERS: usize = 16;
+
+lazy_static! {
+    static ref HANDLERS: Mutex<[Option<Handler>; MAX_HANDLERS]> = {
+        Mutex::new([None; MAX_HANDLERS])
+    };
+}
+
+/// Makes the kernel answer requests with `handler.code`. Fails if the table
+/// is full or the code is already taken.
+pub fn register(handler: Handler) -> Result<(), Handler> {
+    let mut handlers = HANDLERS.lock();
+    if handlers.iter().any(|h| h.map(|h| h.code) == Some(handler.code)) {
+        return Err(handler);
+    }
+
+    match handlers.iter_mut().find(|h| h.is_none()) {
+        Some(slot) => {
+            *slot = Some(handler);
+            Ok(())
+        },
+        None => Err(handler),
+    }
+}
+
+fn find(code: u8) -> Option<Handler> {
+    HANDLERS.lock().iter().filter_map(|h| *h).find(|h| h.code == code)
+}
+
+/// The largest wasm module that can be uploaded.
+pub const MAX_UPLOAD: usize = 64 * 1024;
+
+struct Upload {
+    bytes: [u8; MAX_UPLOAD],
+    /// How much has arrived so far.
+    len: usize,
+    /// The size announced by `command::UPLOAD_BEGIN`.
+    expected: usize,
+}
+
+impl Upload {
+    fn module(&self) -> Option<&[u8]> {
+        if self.expected > 0 && self.len == self.expected {
+            Some(&self.bytes[..self.len])
+        } else {
+            None
+        }
+    }
+}
+
+// too big for a thread's stack; only the remote thread touches it
+static mut UPLOAD: Upload = Upload {
+    bytes: [0; MAX_UPLOAD],
+    len: 0,
+    expected: 0,
+};
+
+fn upload() -> &'static mut Upload {
+    unsafe { &mut UPLOAD }
+}
+
+fn ping(_ctxt: &mut ThreadContext, payload: &[u8], response: &mut Frame) -> u8 {
+    match response.push(payload) {
+        Ok(()) => status::OK,
+        Err(_) => status::BAD_REQUEST,
+    }
+}
+
+fn kernel_status(_ctxt: &mut ThreadContext, _payload: &[u8], response: &mut Frame) -> u8 {
+    let fields = response.push(&rpc::write_u64(CONTEXT.ticks() as u64))
+        .and_then(|_| response.push(&rpc::write_u32(::A.used() as u32)))
+        .and_then(|_| response.push(&rpc::write_u32(::A.capacity() as u32)));
+    match fields {
+        Ok(()) => status::OK,
+        Err(_) => status::FAILED,
+    }
+}
+
+fn upload_begin(_ctxt: &mut ThreadContext, payload: &[u8], response: &mut Frame) -> u8 {
+    let size = match rpc::read_u32(payload, 0) {
+        Some(size) => size as usize,
+        None => return status::BAD_REQUEST,
+    };
+    if size == 0 || size > MAX_UPLOAD {
+        let _ = write!(response, "{} bytes won't fit, the limit is {}", size, MAX_UPLOAD);
+        return status::FAILED;
+    }
+
+    let upload = upload();
+    upload.len = 0;
+    upload.expected = size;
+    status::OK
+}
+
+/// Answers with the number of bytes received so far.
+fn upload_data(_ctxt: &mut ThreadContext, payload: &[u8], response: &mut Frame) -> u8 {
+    let upload = upload();
+    let offset = match rpc::read_u32(payload, 0) {
+        Some(offset) => offset as usize,
+        None => return status::BAD_REQUEST,
+    };
+    let data = &payload[4..];
+    // pieces come in order, retransmissions are taken care of already
+    if offset != upload.len || upload.len + data.len() > upload.expected {
+        return status::BAD_REQUEST;
+    }
+
+    upload.bytes[offset..offset + data.len()].copy_from_slice(data);
+    upload.len += data.len();
+    match response.push(&rpc::write_u32(upload.len as u32)) {
+        Ok(()) => status::OK,
+        Err(_) => status::FAILED,
+    }
+}
+
+fn invoke(_ctxt: &mut ThreadContext, payload: &[u8], response: &mut Frame) -> u8 {
+    let name_len = match payload.first() {
+        Some(&len) => len as usize,
+        None => return status::BAD_REQUEST,
+    };
+    let name = match payload.get(1..1 + name_len).and_then(|name| str::from_utf8(name).ok()) {
+        Some(name) => name,
+        None => return status::BAD_REQUEST,
+    };
+
+    let args = &payload[1 + name_len..];
+    let mut values = [RuntimeValue::I32(0); 8];
+    if args.len() % 4 != 0 || args.len() / 4 > values.len() {
+        return status::BAD_REQUEST;
+    }
+    let count = args.len() / 4;
+    for (index, value) in values[..count].iter_mut().enumerate() {
+        let arg = rpc::read_u32(args, index * 4).unwrap();
+        *value = RuntimeValue::I32(arg as i32);
+    }
+
+    let module = match upload().module() {
+        Some(module) => module,
+        None => {
+            let _ = write!(response, "no module uploaded");
+            return status::FAILED;
+        },
+    };
+
+    let pushed = match shell::invoke_wasm(module, name, &values[..count]) {
+        Ok(None) => response.push(&[value::NONE]),
+        Ok(Some(RuntimeValue::I32(v))) => response.push(&[value::I32])
+            .and_then(|_| response.push(&rpc::write_u32(v as u32))),
+        Ok(Some(RuntimeValue::I64(v))) => response.push(&[value::I64])
+            .and_then(|_| response.push(&rpc::write_u64(v as u64))),
+        Ok(Some(other)) => {
+            let _ = write!(response, "unsupported result: {:?}", other);
+            return status::FAILED;
+        },
+        Err(e) => {
+            let _ = write!(response, "{:?}", e);
+            return status::FAILED;
+        },
+    };
+
+    match pushed {
+        Ok(()) => status::OK,
+        Err(_) => status::FAILED,
+    }
+}
+
+fn run(ctxt: &mut ThreadContext, payload: &[u8], _response: &mut Frame) -> u8 {
+    match str::from_utf8(payload).map(|line| shell::run_line(ctxt, line)) {
+        Ok(true) => status::OK,
+        Ok(false) => status::UNKNOWN_COMMAND,
+        Err(_) => status::BAD_REQUEST,
+    }
+}
+
+/// Registers the built-in requests.
+pub fn init() {
+    let builtins = [
+        Handler { code: command::PING, func: ping },
+        Handler { code: command::STATUS, func: kernel_status },
+        Handler { code: command::UPLOAD_BEGIN, func: upload_begin },
+        Handler { code: command::UPLOAD_DATA, func: upload_data },
+        Handler { code: command::INVOKE, func: invoke },
+        Handler { code: command::RUN, func: run },
+    ];
+
+    for handler in builtins.iter() {
+        register(*handler).ok().expect("duplicate rpc handler");
+    }
+}
+
+fn send(ctxt: &mut ThreadContext, port: &SerialPort, frame: &Frame) {
+    let mut encoded = [0u8; MAX_ENCODED];
+    let len = frame.encode(&mut encoded);
+    port.write(&encoded[..len], ctxt);
+}
+
+/// The last request answered, to tell a retransmission from a new request
+/// that happens to reuse its `seq`, like the first one of another client.
+struct Answered {
+    seq: u8,
+    code: u8,
+    /// `crc::crc16` of the payload.
+    crc: u16,
+    response: Frame,
+}
+
+impl Answered {
+    fn answers(&self, request: &Frame) -> bool {
+        self.seq == request.seq && self.code == request.code &&
+            self.crc == crc::crc16(request.payload())
+    }
+}
+
+/// Acknowledges `request` and answers it, or repeats the last response if
+/// it is that request sent again.
+fn handle(ctxt: &mut ThreadContext, port: &SerialPort, request: &Frame, last: &mut Option<Answered>) {
+    if request.kind != Kind::Request {
+        return;
+    }
+
+    send(ctxt, port, &Frame::new(Kind::Ack, request.seq, status::OK));
+
+    if let Some(ref answered) = *last {
+        if answered.answers(request) {
+            send(ctxt, port, &answered.response);
+            return;
+        }
+    }
+
+    let mut response = Frame::new(Kind::Response, request.seq, status::OK);
+    let code = match find(request.code) {
+        Some(handler) => (handler.func)(ctxt, request.payload(), &mut response),
+        None => status::UNKNOWN_COMMAND,
+    };
+    response.code = code;
+    send(ctxt, port, &response);
+    *last = Some(Answered {
+        seq: request.seq,
+        code: request.code,
+        crc: crc::crc16(request.payload()),
+        response,
+    });
+}
+
+/// The remote thread: answers requests for as long as there is a port
+/// with the RPC role.
+pub fn server(ctxt: &mut ThreadContext, _arg: usize) {
+    let mut decoder = Decoder::new();
+    let mut last = None;
+
+    loop {
+        let port = match CONTEXT.serial.for_role(Role::Rpc) {
+            Some(port) => port,
+            None => {
+                // it may be assigned one from the shell
+                ctxt.yield_to();
+                continue;
+            },
+        };
+
+        port.ready_event().reset();
+        let mut received = false;
+        while let Some(b) = port.try_receive() {
+            received = true;
+            // broken frames are dropped, the host sends them again
+            if let Some(Ok(frame)) = decoder.push(b) {
+                handle(ctxt, port, &frame, &mut last);
+            }
+        }
+
+        if !received {
+            ctxt.wait(&[port.ready_event()]);
+        }
+    }
+}
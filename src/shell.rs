@@ -0,0 +1,559 @@
+use core::str::SplitWhitespace;
+use spin::Mutex;
+use wasmi::{ImportsBuilder, Module, ModuleInstance, NopExternals, RuntimeValue};
+
+use ::{CONTEXT, SHELL_CONSOLE};
+use debugregs::{Condition, Watchpoint};
+use serial::{self, AssignError, DataBits, FlowControl, Parity, Role, SerialConfig, StopBits};
+use thread::{self, ThreadContext};
+use trace::{self, Selection};
+use vga::{mode, Graphics, Mode, RegionError, Vga, FONT_PLANE_SIZE};
+
+/// A shell command: receives the words typed after its name.
+pub type CommandFunc = fn(&mut ThreadContext, &mut SplitWhitespace);
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub func: CommandFunc,
+}
+
+const MAX_COMMANDS: usize = 32;
+
+lazy_static! {
+    static ref COMMANDS: Mutex<[Option<Command>; MAX_COMMANDS]> = {
+        Mutex::new([None; MAX_COMMANDS])
+    };
+}
+
+/// Makes `command` available in the shell. Fails if the table is full or
+/// the name is already taken.
+pub fn register(command: Command) -> Result<(), Command> {
+    let mut commands = COMMANDS.lock();
+    if commands.iter().any(|c| c.map(|c| c.name) == Some(command.name)) {
+        return Err(command);
+    }
+
+    match commands.iter_mut().find(|c| c.is_none()) {
+        Some(slot) => {
+            *slot = Some(command);
+            Ok(())
+        },
+        None => Err(command),
+    }
+}
+
+fn find(name: &str) -> Option<Command> {
+    COMMANDS.lock().iter().filter_map(|c| *c).find(|c| c.name == name)
+}
+
+/// The wasm modules built into the kernel, by name.
+const WASM_MODULES: &[(&str, &[u8])] = &[
+    ("sample", ::WASM_SAMPLE_APP),
+];
+
+fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        usize::from_str_radix(s, 10).ok()
+    }
+}
+
+/// An i32 argument for a wasm export, with an optional minus sign.
+fn parse_i32(s: &str) -> Option<i32> {
+    let (negative, digits) = if s.starts_with('-') { (true, &s[1..]) } else { (false, s) };
+    let n = parse_number(digits)?;
+    if negative && n <= 1 << 31 {
+        Some((n as i64).wrapping_neg() as i32)
+    } else if !negative && n <= i32::max_value() as usize {
+        Some(n as i32)
+    } else {
+        None
+    }
+}
+
+fn help(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    for command in COMMANDS.lock().iter().filter_map(|c| *c) {
+        kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:8} {}", command.name, command.help);
+    }
+}
+
+fn ps(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:>4} {:12} {}", "ID", "NAME", "STATE");
+    thread::for_each_thread(|t| {
+        let state = if t.is_runnable() { "runnable" } else { "waiting" };
+        kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:>4} {:12} {}", t.id, t.name, state);
+    });
+}
+
+fn mem(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    let used = ::A.used();
+    let capacity = ::A.capacity();
+    kprintln_console!(CONTEXT, SHELL_CONSOLE, "heap: {} of {} bytes used, {} free", used, capacity, capacity - used);
+}
+
+fn ticks(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    kprintln_console!(CONTEXT, SHELL_CONSOLE, "{}", CONTEXT.ticks());
+}
+
+fn irq(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    for vector in 0..256 {
+        let count = interrupts::interrupt_count(vector);
+        if count > 0 {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:>3}: {}", vector, count);
+        }
+    }
+}
+
+fn run(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let (name, export) = match (args.next(), args.next()) {
+        (Some(name), Some(export)) => (name, export),
+        _ => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: run <module> <export> [i32 args...]");
+            for &(name, _) in WASM_MODULES {
+                kprintln_console!(CONTEXT, SHELL_CONSOLE, "  {}", name);
+            }
+            return;
+        },
+    };
+
+    let bytes = match WASM_MODULES.iter().find(|m| m.0 == name) {
+        Some(&(_, bytes)) => bytes,
+        None => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "no such module: {}", name);
+            return;
+        },
+    };
+
+    let mut values = [RuntimeValue::I32(0); 8];
+    let mut count = 0;
+    for arg in args {
+        match (parse_i32(arg), values.get_mut(count)) {
+            (Some(n), Some(value)) => *value = RuntimeValue::I32(n),
+            _ => {
+                kprintln_console!(CONTEXT, SHELL_CONSOLE, "bad argument: {}", arg);
+                return;
+            },
+        }
+        count += 1;
+    }
+
+    match invoke_wasm(bytes, export, &values[..count]) {
+        Ok(result) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:?}", result),
+        Err(e) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "error: {:?}", e),
+    }
+}
+
+/// Instantiates the module in `bytes` and calls `export` in it.
+pub fn invoke_wasm(bytes: &[u8], export: &str, args: &[RuntimeValue]) -> Result<Option<RuntimeValue>, wasmi::Error> {
+    let module = Module::from_buffer(bytes)?;
+    module.deny_floating_point()?;
+
+    let instance = ModuleInstance::new(&module, &ImportsBuilder::default())?
+        .run_start(&mut NopExternals)?;
+
+    instance.invoke_export(export, args, &mut NopExternals)
+}
+
+fn peek(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let address = match args.next().and_then(parse_number) {
+        Some(address) => address,
+        None => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: peek <address> [count]");
+            return;
+        },
+    };
+    let count = args.next().and_then(parse_number).unwrap_or(16);
+
+    // wraps around the top of the address space rather than overflowing
+    for row in (0..count).step_by(16) {
+        kprint_console!(CONTEXT, SHELL_CONSOLE, "{:016x}:", address.wrapping_add(row));
+        for i in row..core::cmp::min(row.saturating_add(16), count) {
+            let b = unsafe { core::ptr::read_volatile(address.wrapping_add(i) as *const u8) };
+            kprint_console!(CONTEXT, SHELL_CONSOLE, " {:02x}", b);
+        }
+        kprintln_console!(CONTEXT, SHELL_CONSOLE, "");
+    }
+}
+
+fn poke(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    match (args.next().and_then(parse_number), args.next().and_then(parse_number)) {
+        (Some(address), Some(value)) if value <= 0xff => unsafe {
+            core::ptr::write_volatile(address as *mut u8, value as u8);
+        },
+        _ => kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: poke <address> <byte>"),
+    }
+}
+
+fn list_watchpoints() {
+    thread::for_each_thread(|t| {
+        for (slot, watchpoint) in t.watchpoints().iter() {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:>4} {:12} {}: {}", t.id, t.name, slot, watchpoint);
+        }
+    });
+}
+
+/// Reads what `watch` should set: `None` to clear the slot.
+fn parse_watchpoint(args: &mut SplitWhitespace) -> Option<Option<Watchpoint>> {
+    let condition = match args.next()? {
+        "off" => return Some(None),
+        "x" => Condition::Execute,
+        "w" => Condition::Write,
+        "rw" => Condition::ReadWrite,
+        _ => return None,
+    };
+    let address = args.next().and_then(parse_number)?;
+    let len = args.next().map_or(Some(1), parse_number)?;
+
+    match Watchpoint::new(address, condition, len) {
+        Ok(watchpoint) => Some(Some(watchpoint)),
+        Err(e) => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "error: {:?}", e);
+            None
+        },
+    }
+}
+
+fn watch(ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let id = match args.next() {
+        Some(id) => parse_number(id),
+        None => {
+            list_watchpoints();
+            return;
+        },
+    };
+    let slot = args.next().and_then(parse_number);
+    let (id, slot, watchpoint) = match (id, slot, parse_watchpoint(args)) {
+        (Some(id), Some(slot), Some(watchpoint)) => (id, slot, watchpoint),
+        _ => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: watch <thread> <slot> x|w|rw <address> [1|2|4|8], or off");
+            return;
+        },
+    };
+
+    // the shell's own thread has its registers loaded already
+    let result = if id == ctxt.id() {
+        Some(ctxt.set_watchpoint(slot, watchpoint))
+    } else {
+        thread::set_watchpoint(id, slot, watchpoint)
+    };
+    match result {
+        Some(Ok(())) => {},
+        Some(Err(e)) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "error: {:?}", e),
+        None => kprintln_console!(CONTEXT, SHELL_CONSOLE, "no thread {}", id),
+    }
+}
+
+fn trace_command(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let selection = match args.next() {
+        None => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{:?}", trace::selection());
+            return;
+        },
+        Some("dump") => {
+            trace::for_each_step(|step| kprintln_console!(CONTEXT, SHELL_CONSOLE, "{}", step));
+            return;
+        },
+        Some("off") => Some(Selection::Off),
+        Some("thread") => args.next().and_then(parse_number).map(Selection::Thread),
+        Some("range") => match (args.next().and_then(parse_number), args.next().and_then(parse_number)) {
+            (Some(start), Some(end)) if start < end => Some(Selection::Range(start, end)),
+            _ => None,
+        },
+        Some(_) => None,
+    };
+
+    match selection {
+        Some(selection) => trace::select(selection),
+        None => kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: trace [thread <id> | range <start> <end> | off | dump]"),
+    }
+}
+
+/// The 8x16 font the BIOS left in video memory, saved by `init` to put
+/// back after modes that overwrite it.
+static BOOT_FONT: Mutex<[u8; FONT_PLANE_SIZE]> = Mutex::new([0; FONT_PLANE_SIZE]);
+
+type ScreenVga = Vga<&'static mut [u8], ::VgaIo>;
+
+/// The text mode the screen is in, by its size.
+fn current_text_mode(vga: &ScreenVga) -> &'static Mode {
+    mode::TEXT_MODES.iter()
+        .find(|m| m.width == vga.cols() && m.height == vga.rows())
+        .expect("unknown text mode")
+}
+
+fn set_text_mode(vga: &mut ScreenVga, mode: &Mode) -> Result<(), RegionError> {
+    vga.set_mode(mode)?;
+    if mode.font_height != 8 {
+        vga.restore_font(&*BOOT_FONT.lock());
+    }
+    vga.flush();
+    Ok(())
+}
+
+fn text_mode(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let size = match args.next() {
+        Some(size) => size,
+        None => {
+            // printing locks the screen too
+            let (cols, rows) = {
+                let vga = CONTEXT.vga.lock();
+                (vga.cols(), vga.rows())
+            };
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{}x{}", cols, rows);
+            return;
+        },
+    };
+
+    let found = mode::TEXT_MODES.iter().find(|m| {
+        let mut dimensions = size.split('x').map(parse_number);
+        dimensions.next() == Some(Some(m.width)) && dimensions.next() == Some(Some(m.height))
+    });
+
+    let result = match found {
+        Some(found) => set_text_mode(&mut CONTEXT.vga.lock(), found),
+        None => {
+            kprint_console!(CONTEXT, SHELL_CONSOLE, "usage: mode [");
+            for m in mode::TEXT_MODES.iter() {
+                kprint_console!(CONTEXT, SHELL_CONSOLE, " {}x{}", m.width, m.height);
+            }
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, " ]");
+            return;
+        },
+    };
+
+    if let Err(e) = result {
+        kprintln_console!(CONTEXT, SHELL_CONSOLE, "error: {:?}", e);
+    }
+}
+
+fn gfx(ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    let previous = {
+        let mut vga = CONTEXT.vga.lock();
+        let previous = current_text_mode(&vga);
+        vga.set_mode(&mode::GRAPHICS_320X200).unwrap();
+        // a grey ramp after the 16 default colors
+        for i in 0..32 {
+            vga.set_palette(16 + i, i * 2, i * 2, i * 2);
+        }
+        previous
+    };
+
+    let framebuffer = unsafe {
+        core::slice::from_raw_parts_mut(0xa0000 as *mut u8, 320 * 200)
+    };
+    let mut graphics = Graphics::new(framebuffer);
+    graphics.clear(1);
+    for x in 0..256 {
+        graphics.line(32 + x as isize, 40, 32 + x as isize, 120, 16 + (x / 8) as u8);
+    }
+    graphics.rect(20, 20, 280, 160, 15);
+    graphics.line(20, 179, 299, 20, 14);
+    graphics.fill_rect(40, 140, 24, 24, 4);
+    graphics.text(28, 28, "intermezzOS: mode 13h", 15, None);
+    graphics.text(28, 168, "press enter to return", 7, Some(1));
+
+    // nothing reaches the screen in the meantime, the text is redrawn on return
+    let _ = CONTEXT.console.read_line(ctxt);
+
+    // the same size as before, so the consoles fit
+    set_text_mode(&mut CONTEXT.vga.lock(), previous).unwrap();
+}
+
+/// Parses the short form of a line format, e.g. "8N1" or "7E2".
+fn parse_line_format(format: &str, config: &mut SerialConfig) -> Option<()> {
+    let bytes = format.as_bytes();
+    if bytes.len() != 3 {
+        return None;
+    }
+
+    config.data_bits = match bytes[0] {
+        b'5' => DataBits::Five,
+        b'6' => DataBits::Six,
+        b'7' => DataBits::Seven,
+        b'8' => DataBits::Eight,
+        _ => return None,
+    };
+    config.parity = match bytes[1].to_ascii_uppercase() {
+        b'N' => Parity::None,
+        b'O' => Parity::Odd,
+        b'E' => Parity::Even,
+        b'M' => Parity::Mark,
+        b'S' => Parity::Space,
+        _ => return None,
+    };
+    config.stop_bits = match bytes[2] {
+        b'1' => StopBits::One,
+        b'2' => StopBits::Two,
+        _ => return None,
+    };
+    Some(())
+}
+
+fn stty(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let port = match CONTEXT.serial.for_role(Role::Console) {
+        Some(port) => port,
+        None => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "no serial console");
+            return;
+        },
+    };
+
+    let mut config = port.config();
+    let baud_rate = match args.next() {
+        Some(baud_rate) => baud_rate,
+        None => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{}", config);
+            return;
+        },
+    };
+
+    let format = args.next().unwrap_or("8N1");
+    let flow_control = match args.next() {
+        None => Some(FlowControl::None),
+        Some("rtscts") => Some(FlowControl::RtsCts),
+        Some("xonxoff") => Some(FlowControl::XonXoff),
+        Some(_) => None,
+    };
+    match (baud_rate.parse(), parse_line_format(format, &mut config), flow_control) {
+        (Ok(baud_rate), Some(()), Some(flow_control)) => {
+            config.baud_rate = baud_rate;
+            config.flow_control = flow_control;
+        },
+        _ => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: stty [baud [8N1 [rtscts|xonxoff]]]");
+            return;
+        },
+    }
+
+    match port.configure(&config) {
+        Ok(()) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "{}", config),
+        Err(e) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "error: {:?}", e),
+    }
+}
+
+fn serial_ports(_ctxt: &mut ThreadContext, args: &mut SplitWhitespace) {
+    let role = match args.next() {
+        Some("console") => Role::Console,
+        Some("log") => Role::Log,
+        Some("rpc") => Role::Rpc,
+        Some("debug") => Role::Debug,
+        Some(_) => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "usage: serial [console|log|rpc|debug <1-4>]");
+            return;
+        },
+        None => {
+            for index in 0..serial::PORT_COUNT {
+                if let Some(port) = CONTEXT.serial.get(index) {
+                    kprint_console!(CONTEXT, SHELL_CONSOLE, "{} {:#x} IRQ{} {}", serial::name(index),
+                        port.base_address(), serial::irq(index), port.config());
+                    for &role in serial::ROLES.iter() {
+                        if CONTEXT.serial.role_index(role) == Some(index) {
+                            kprint_console!(CONTEXT, SHELL_CONSOLE, " {}", role.name());
+                        }
+                    }
+                    kprintln_console!(CONTEXT, SHELL_CONSOLE, "");
+
+                    let stats = port.stats();
+                    kprintln_console!(CONTEXT, SHELL_CONSOLE, "  dropped {} unsent {} overrun {} parity {} framing {} break {}",
+                        stats.dropped, stats.unsent, stats.overrun, stats.parity, stats.framing, stats.breaks);
+                }
+            }
+            return;
+        },
+    };
+
+    let assigned = args.next()
+        .and_then(parse_number)
+        .and_then(|number| number.checked_sub(1))
+        .ok_or(AssignError::NoSuchPort)
+        .and_then(|index| CONTEXT.serial.assign(role, index));
+    match assigned {
+        Ok(()) => {},
+        Err(AssignError::NoSuchPort) => kprintln_console!(CONTEXT, SHELL_CONSOLE, "no such port"),
+        Err(AssignError::InUse(other)) => {
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "{} is on that port already; rpc and debug need one to themselves",
+                other.name());
+        },
+    }
+}
+
+fn reboot(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    kprintln_console!(CONTEXT, SHELL_CONSOLE, "Rebooting...");
+    keyboard::controller::reset_cpu();
+}
+
+fn halt(_ctxt: &mut ThreadContext, _args: &mut SplitWhitespace) {
+    kprintln_console!(CONTEXT, SHELL_CONSOLE, "Halting.");
+    ::shutdown();
+    unsafe {
+        x86::shared::irq::disable();
+        loop {
+            x86::shared::halt();
+        }
+    }
+}
+
+/// Registers the built-in commands.
+pub fn init() {
+    CONTEXT.vga.lock().save_font(&mut *BOOT_FONT.lock());
+
+    let builtins = [
+        Command { name: "help", help: "list commands", func: help },
+        Command { name: "ps", help: "list threads", func: ps },
+        Command { name: "mem", help: "show heap usage", func: mem },
+        Command { name: "ticks", help: "show timer ticks since boot", func: ticks },
+        Command { name: "irq", help: "show interrupt counts per vector", func: irq },
+        Command { name: "run", help: "run <module> <export> [args] - call into a wasm module", func: run },
+        Command { name: "peek", help: "peek <address> [count] - dump memory", func: peek },
+        Command { name: "poke", help: "poke <address> <byte> - write memory", func: poke },
+        Command { name: "watch", help: "watch [thread slot x|w|rw address [len] | off] - list or set hardware watchpoints", func: watch },
+        Command { name: "trace", help: "trace [thread <id> | range <start> <end> | off | dump] - single step and log instructions", func: trace_command },
+        Command { name: "mode", help: "mode [COLSxROWS] - show or change the text mode", func: text_mode },
+        Command { name: "gfx", help: "show a mode 13h graphics demo", func: gfx },
+        Command { name: "stty", help: "stty [baud [8N1 [flow]]] - show or change the serial console settings", func: stty },
+        Command { name: "serial", help: "serial [console|log|rpc|debug <1-4>] - list serial ports or move a role", func: serial_ports },
+        Command { name: "reboot", help: "reset the machine", func: reboot },
+        Command { name: "halt", help: "stop the machine", func: halt },
+    ];
+
+    for command in builtins.iter() {
+        register(*command).ok().expect("duplicate shell command");
+    }
+}
+
+/// Runs a command line as if typed into the shell. Returns false if there
+/// is no such command; an empty line does nothing and succeeds.
+pub fn run_line(ctxt: &mut ThreadContext, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return true,
+    };
+
+    match find(name) {
+        Some(command) => {
+            (command.func)(ctxt, &mut words);
+            true
+        },
+        None => false,
+    }
+}
+
+/// The shell thread: reads commands from the console and runs them.
+pub fn shell(ctxt: &mut ThreadContext, _arg: usize) {
+    loop {
+        kprint_console!(CONTEXT, SHELL_CONSOLE, "> ");
+        let line = match CONTEXT.console.read_line(ctxt) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+
+        if !run_line(ctxt, line.as_str()) {
+            let name = line.as_str().split_whitespace().next().unwrap_or("");
+            kprintln_console!(CONTEXT, SHELL_CONSOLE, "unknown command: {} (try 'help')", name);
+        }
+    }
+}
@@ -3,6 +3,10 @@ extern crate interrupts;
 extern crate x86;
 
 use ::CONTEXT;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use common::{Event, Waiter};
+use debugregs::{self, DebugRegisters, Watchpoint};
+use trace;
 
 #[repr(C)]
 #[repr(align(16))]
@@ -13,6 +17,7 @@ pub struct ThreadState {
 }
 
 const STACK_SIZE : usize = 32 * 1024;
+const MAX_WAIT_EVENTS : usize = 4;
 
 #[repr(C)]
 #[repr(align(16))]
@@ -21,6 +26,10 @@ pub struct Thread {
     pub id: usize,
     stack: [u8; STACK_SIZE],
     pub name: &'static str,
+    waiting_on: [*const Event; MAX_WAIT_EVENTS],
+    waiting_count: usize,
+    /// Its hardware breakpoints and watchpoints, loaded while it runs.
+    debug: DebugRegisters,
 }
 
 pub struct ThreadContext<'a> {
@@ -30,7 +39,37 @@ pub struct ThreadContext<'a> {
 
 impl<'a> ThreadContext<'a> {
     pub fn yield_to(&mut self) -> () {
-        self.this_thread.switch_to(self.prev_thread);
+        self.this_thread.switch_with_debug_registers(self.prev_thread);
+    }
+
+    pub fn id(&self) -> usize {
+        self.this_thread.id
+    }
+
+    /// Sets what hardware breakpoint `slot` watches in this thread, or
+    /// clears it with `None`.
+    pub fn set_watchpoint(&mut self, slot: usize, watchpoint: Option<Watchpoint>) -> Result<(), debugregs::Error> {
+        self.this_thread.debug.set(slot, watchpoint)?;
+        unsafe { self.this_thread.debug.load() };
+        Ok(())
+    }
+}
+
+impl<'a> Waiter for ThreadContext<'a> {
+    /// Parks this thread; the scheduler skips it until one of `events` is
+    /// signaled.
+    fn wait(&mut self, events: &[&Event]) {
+        assert!(events.len() <= MAX_WAIT_EVENTS);
+        for (slot, event) in self.this_thread.waiting_on.iter_mut().zip(events.iter()) {
+            *slot = *event as *const Event;
+        }
+        self.this_thread.waiting_count = events.len();
+
+        while !events.iter().any(|e| e.is_signaled()) {
+            self.yield_to();
+        }
+
+        self.this_thread.waiting_count = 0;
     }
 }
 
@@ -54,9 +93,36 @@ impl Thread {
             id: id,
             stack: [0x0u8; STACK_SIZE],
             name: name,
+            waiting_on: [core::ptr::null(); MAX_WAIT_EVENTS],
+            waiting_count: 0,
+            debug: DebugRegisters::new(),
         }    
     }
 
+    pub fn watchpoints(&self) -> &DebugRegisters {
+        &self.debug
+    }
+
+    /// `switch_to`, keeping the debug registers and the trap flag of each
+    /// thread its own.
+    fn switch_with_debug_registers(&mut self, next: &Thread) {
+        self.debug = DebugRegisters::current();
+        unsafe { next.debug.load() };
+        CURRENT.store(next.id, Ordering::SeqCst);
+        trace::set_trap_flag(trace::wants(next.id));
+        SWITCHING.store(true, Ordering::SeqCst);
+        self.switch_to(next);
+        // back in this thread
+        SWITCHING.store(false, Ordering::SeqCst);
+    }
+
+    /// A thread is runnable unless it is parked waiting for events, none of
+    /// which have been signaled yet.
+    pub fn is_runnable(&self) -> bool {
+        self.waiting_count == 0 ||
+            self.waiting_on[..self.waiting_count].iter().any(|e| unsafe { (**e).is_signaled() })
+    }
+
     fn state_mut(&mut self) -> &mut ThreadState {
         unsafe {
             &mut *self.state_ptr
@@ -90,6 +156,7 @@ impl Thread {
     unsafe extern "sysv64" fn thread_start(f_ptr: FnPtr, prev_thread: &mut Thread, this_thread: &mut Thread, arg: usize) {
         // let ip = f_ptr.f as *const u8;
         kprintln!(CONTEXT, "thread_start arg:{:x} prev:{:?} current:{:?}", arg, prev_thread, this_thread);
+        SWITCHING.store(false, Ordering::SeqCst);
         let mut context = ThreadContext
         {
             prev_thread: prev_thread,
@@ -178,6 +245,87 @@ impl Thread {
     }
 }
 
+/// The scheduler currently running threads, for `for_each_thread`.
+static SCHEDULER: AtomicUsize = AtomicUsize::new(0);
+
+/// The id of the thread running; the scheduler's own is 1.
+static CURRENT: AtomicUsize = AtomicUsize::new(1);
+
+/// Set while `switch_with_debug_registers` is between two threads, when
+/// neither may park.
+static SWITCHING: AtomicBool = AtomicBool::new(false);
+
+pub fn current_id() -> usize {
+    CURRENT.load(Ordering::SeqCst)
+}
+
+/// A `Waiter` for whichever thread is running, for code with no
+/// `ThreadContext` at hand, like `kprint!`.
+pub struct CurrentThread {
+    scheduler: *mut Scheduler,
+}
+
+impl CurrentThread {
+    /// `None` where nothing may park: before the scheduler runs, in the
+    /// scheduler itself, halfway through a switch, and in interrupt
+    /// handlers, which run with interrupts off.
+    pub fn get() -> Option<CurrentThread> {
+        let scheduler = SCHEDULER.load(Ordering::SeqCst) as *mut Scheduler;
+        let interrupts_on = x86::shared::flags::flags() & x86::shared::flags::FLAGS_IF == x86::shared::flags::FLAGS_IF;
+        if scheduler.is_null() || current_id() == 1 || SWITCHING.load(Ordering::SeqCst) || !interrupts_on {
+            return None;
+        }
+        Some(CurrentThread { scheduler })
+    }
+}
+
+impl Waiter for CurrentThread {
+    /// Parks the running thread, going back to the scheduler as its own
+    /// `ThreadContext` would.
+    fn wait(&mut self, events: &[&Event]) {
+        let scheduler = unsafe { &mut *self.scheduler };
+        let id = current_id();
+        let this_thread = scheduler.threads.iter_mut()
+            .filter_map(|t| t.as_mut())
+            .find(|t| t.id == id)
+            .expect("the running thread isn't the scheduler's");
+        let mut context = ThreadContext {
+            prev_thread: &mut scheduler.scheduler_thread,
+            this_thread,
+        };
+        context.wait(events);
+    }
+}
+
+/// Calls `f` with every thread of the running scheduler.
+pub fn for_each_thread<F: FnMut(&Thread)>(mut f: F) {
+    let scheduler = SCHEDULER.load(Ordering::SeqCst) as *const Scheduler;
+    if scheduler.is_null() {
+        return;
+    }
+
+    for t in unsafe { (*scheduler).threads.iter() } {
+        if let Some(t) = t {
+            f(t);
+        }
+    }
+}
+
+/// Sets a watchpoint in another thread of the running scheduler, to be
+/// loaded when it next runs; `None` if there's no thread `id`. Use
+/// `ThreadContext::set_watchpoint` for the calling thread.
+pub fn set_watchpoint(id: usize, slot: usize, watchpoint: Option<Watchpoint>) -> Option<Result<(), debugregs::Error>> {
+    let scheduler = SCHEDULER.load(Ordering::SeqCst) as *mut Scheduler;
+    if scheduler.is_null() {
+        return None;
+    }
+
+    let threads = unsafe { (*scheduler).threads.iter_mut() };
+    threads.filter_map(|t| t.as_mut())
+        .find(|t| t.id == id)
+        .map(|t| t.debug.set(slot, watchpoint))
+}
+
 pub struct Scheduler {
     free_index: usize,
     scheduler_thread: Thread,
@@ -204,12 +352,16 @@ impl Scheduler {
     }
 
     pub fn run(&mut self) -> ! {
+        SCHEDULER.store(self as *const Scheduler as usize, Ordering::SeqCst);
         loop {
             for t in self.threads.into_iter() {
                 if let Some(t) = t {
+                    if !t.is_runnable() {
+                        continue;
+                    }
                     kprintln!(CONTEXT, "Switching to thread {}: {}", t.id, t.name);
                     //::toggle_single_step();
-                    self.scheduler_thread.switch_to(&t);
+                    self.scheduler_thread.switch_with_debug_registers(&t);
                     // ::toggle_single_step();
                 } else {
                     break;
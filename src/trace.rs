@@ -0,0 +1,295 @@
+//! An instruction tracer: single steps one thread, or whatever runs inside
+//! an address range, and keeps the last `TRACE_LEN` instructions executed
+//! with the registers each changed. `trace` in the shell picks what to
+//! trace and dumps it; faults dump it too.
+//!
+//! Stepping uses the trap flag, which `thread` sets and clears on every
+//! switch so that only the traced thread pays for it.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use ::CONTEXT;
+use interrupts::InterruptState;
+use thread;
+
+/// How many instructions are kept; older ones are overwritten.
+pub const TRACE_LEN: usize = 256;
+
+/// The longest x86 instruction.
+const MAX_INSTRUCTION_LEN: usize = 15;
+/// Registers changed by one instruction that are recorded; `push` and
+/// `call` change two, string instructions up to four.
+const MAX_DELTAS: usize = 4;
+
+const REGISTER_COUNT: usize = 17;
+const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15", "flags",
+];
+
+const FLAGS_TF: usize = 0x100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Selection {
+    Off,
+    Thread(usize),
+    /// Instructions from the start address up to, not including, the end,
+    /// in any thread.
+    Range(usize, usize),
+}
+
+// Read on every thread switch and every step, so kept out of the lock.
+// No thread has id 0, and an empty range traces nothing.
+static THREAD: AtomicUsize = AtomicUsize::new(0);
+static RANGE_START: AtomicUsize = AtomicUsize::new(0);
+static RANGE_END: AtomicUsize = AtomicUsize::new(0);
+
+/// What is being traced.
+pub fn selection() -> Selection {
+    let (start, end) = (RANGE_START.load(Ordering::SeqCst), RANGE_END.load(Ordering::SeqCst));
+    match THREAD.load(Ordering::SeqCst) {
+        0 if start < end => Selection::Range(start, end),
+        0 => Selection::Off,
+        id => Selection::Thread(id),
+    }
+}
+
+/// Whether thread `id` runs with the trap flag set.
+pub fn wants(id: usize) -> bool {
+    match selection() {
+        Selection::Off => false,
+        Selection::Thread(traced) => traced == id,
+        Selection::Range(_, _) => true,
+    }
+}
+
+fn selected(thread: usize, address: usize) -> bool {
+    match selection() {
+        Selection::Off => false,
+        Selection::Thread(traced) => traced == thread,
+        Selection::Range(start, end) => start <= address && address < end,
+    }
+}
+
+/// One instruction executed.
+#[derive(Clone, Copy)]
+pub struct Step {
+    pub thread: usize,
+    pub address: usize,
+    len: usize,
+    bytes: [u8; MAX_INSTRUCTION_LEN],
+    /// The registers it changed, as indexes in `REGISTER_NAMES`, and their
+    /// new values.
+    deltas: [(u8, usize); MAX_DELTAS],
+    delta_count: usize,
+}
+
+impl Step {
+    /// The instruction's bytes; empty if `lde` couldn't decode it.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>2} {:016x}:", self.thread, self.address)?;
+        for b in self.bytes() {
+            write!(f, " {:02x}", b)?;
+        }
+        // pad to the longest common instructions, so that registers line up
+        for _ in self.len..8 {
+            write!(f, "   ")?;
+        }
+        for &(register, value) in &self.deltas[..self.delta_count] {
+            write!(f, " {}={:x}", REGISTER_NAMES[register as usize], value)?;
+        }
+        Ok(())
+    }
+}
+
+const EMPTY_STEP: Step = Step {
+    thread: 0,
+    address: 0,
+    len: 0,
+    bytes: [0; MAX_INSTRUCTION_LEN],
+    deltas: [(0, 0); MAX_DELTAS],
+    delta_count: 0,
+};
+
+/// The instruction about to run when the last step trapped.
+#[derive(Clone, Copy)]
+struct Pending {
+    thread: usize,
+    address: usize,
+    registers: [usize; REGISTER_COUNT],
+}
+
+struct Trace {
+    steps: [Step; TRACE_LEN],
+    /// Steps recorded since the selection was made; the latest is at
+    /// `(count - 1) % TRACE_LEN`.
+    count: usize,
+    pending: Option<Pending>,
+}
+
+static TRACE: Mutex<Trace> = Mutex::new(Trace {
+    steps: [EMPTY_STEP; TRACE_LEN],
+    count: 0,
+    pending: None,
+});
+
+fn registers(state: &InterruptState) -> [usize; REGISTER_COUNT] {
+    let regs = &state.regs;
+    [
+        regs.rax as usize, regs.rbx as usize, regs.rcx as usize, regs.rdx as usize,
+        regs.rsi as usize, regs.rdi as usize, regs.rbp as usize, state.rsp as usize,
+        regs.r8 as usize, regs.r9 as usize, regs.r10 as usize, regs.r11 as usize,
+        regs.r12 as usize, regs.r13 as usize, regs.r14 as usize, regs.r15 as usize,
+        // the trap flag is ours, not the traced code's
+        state.flags as usize & !FLAGS_TF,
+    ]
+}
+
+/// The instruction at `address`, as `dump_last_instruction` decodes it.
+fn instruction(address: usize) -> ([u8; MAX_INSTRUCTION_LEN], usize) {
+    let mut bytes = [0u8; MAX_INSTRUCTION_LEN];
+    let code = unsafe { core::slice::from_raw_parts(address as *const u8, MAX_INSTRUCTION_LEN) };
+    let len = match lde::X64.iter(code, address as u64).next() {
+        Some((opcode, _va)) => opcode.len(),
+        None => 0,
+    };
+    bytes[..len].copy_from_slice(&code[..len]);
+    (bytes, len)
+}
+
+impl Trace {
+    fn record(&mut self, before: &Pending, after: &[usize; REGISTER_COUNT]) {
+        let (bytes, len) = instruction(before.address);
+        let mut step = Step {
+            thread: before.thread,
+            address: before.address,
+            len,
+            bytes,
+            ..EMPTY_STEP
+        };
+
+        let changed = before.registers.iter().zip(after.iter()).enumerate()
+            .filter(|&(_, (old, new))| old != new);
+        for (register, (_, &new)) in changed.take(MAX_DELTAS) {
+            step.deltas[step.delta_count] = (register as u8, new);
+            step.delta_count += 1;
+        }
+
+        self.steps[self.count % TRACE_LEN] = step;
+        self.count += 1;
+    }
+
+    /// The steps kept, oldest first.
+    fn steps<'a>(&'a self) -> impl Iterator<Item = &'a Step> + 'a {
+        let kept = self.count.min(TRACE_LEN);
+        (self.count - kept..self.count).map(move |n| &self.steps[n % TRACE_LEN])
+    }
+}
+
+/// Called by the debug exception handler for single steps gdb didn't ask
+/// for. Records the instruction that just ran if it was selected, and
+/// returns whether tracing is on, in which case the step was the tracer's.
+pub fn on_step(state: &InterruptState) -> bool {
+    if selection() == Selection::Off {
+        return false;
+    }
+
+    // taken by the traced code itself, dumping the trace: skip the step
+    let mut trace = match TRACE.try_lock() {
+        Some(trace) => trace,
+        None => return true,
+    };
+
+    let now = registers(state);
+    let pending = trace.pending;
+    if let Some(before) = pending {
+        if selected(before.thread, before.address) {
+            trace.record(&before, &now);
+        }
+    }
+    trace.pending = Some(Pending {
+        thread: thread::current_id(),
+        address: state.rip as usize,
+        registers: now,
+    });
+    true
+}
+
+/// Sets or clears the trap flag of the code running.
+pub fn set_trap_flag(on: bool) {
+    unsafe {
+        if on {
+            asm!("
+                pushf
+                bts qword ptr [rsp], 8
+                popf
+                "
+                : // no outputs
+                : // no inputs
+                : // no clobbers
+                : "volatile", "intel");
+        } else {
+            asm!("
+                pushf
+                btr qword ptr [rsp], 8
+                popf
+                "
+                : // no outputs
+                : // no inputs
+                : // no clobbers
+                : "volatile", "intel");
+        }
+    }
+}
+
+/// Starts tracing `selection`, from an empty trace, or stops with
+/// `Selection::Off`. The calling thread's trap flag is set accordingly;
+/// the others' when they next run.
+pub fn select(selection: Selection) {
+    let (thread, start, end) = match selection {
+        Selection::Off => (0, 0, 0),
+        Selection::Thread(id) => (id, 0, 0),
+        Selection::Range(start, end) => (0, start, end),
+    };
+
+    set_trap_flag(false);
+    {
+        let mut trace = TRACE.lock();
+        trace.count = 0;
+        trace.pending = None;
+        THREAD.store(thread, Ordering::SeqCst);
+        RANGE_START.store(start, Ordering::SeqCst);
+        RANGE_END.store(end, Ordering::SeqCst);
+    }
+    set_trap_flag(wants(thread::current_id()));
+}
+
+/// Calls `f` with the steps kept, oldest first.
+pub fn for_each_step<F: FnMut(&Step)>(mut f: F) {
+    for step in TRACE.lock().steps() {
+        f(step);
+    }
+}
+
+/// Prints the steps kept, after a fault or a panic: only if the trace
+/// isn't being changed or dumped, as the fault may have happened there.
+pub fn dump_on_fault() {
+    if let Some(trace) = TRACE.try_lock() {
+        if trace.count == 0 {
+            return;
+        }
+
+        kprintln_fault!(CONTEXT, "Last {} instructions traced:", trace.count.min(TRACE_LEN));
+        for step in trace.steps() {
+            kprintln_fault!(CONTEXT, "{}", step);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Embeds the kernel's symbol table in the kernel itself, for backtraces
+//! to show function names: run the `embed-symbols` binary on the linked
+//! kernel, before making a boot image of it.
+//!
+//! ```text
+//! cargo run --manifest-path symbols-host/Cargo.toml -- target/intermezzos/debug/intermezzos
+//! ```
+//!
+//! The function symbols are read from the ELF `.symtab`, demangled, and
+//! written in the `symbols` format over the zeroed `.symbols` section the
+//! kernel reserves for them. Addresses don't move, so this is done in place.
+
+extern crate symbols;
+
+use std::error;
+use std::fmt;
+use std::str;
+
+use symbols::Symbol;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Not a 64-bit little endian ELF file, or a broken one.
+    NotElf,
+    NoSection(&'static str),
+    /// The table would need this many bytes.
+    TooBig(usize),
+    Table(symbols::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotElf => write!(f, "not a 64-bit little endian ELF file"),
+            Error::NoSection(name) => write!(f, "no {} section", name),
+            Error::TooBig(needed) => write!(f, "the symbol table needs {} bytes, more than .symbols has", needed),
+            Error::Table(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SECTION_HEADER_LEN: usize = 64;
+const SYMBOL_LEN: usize = 24;
+
+fn read(bytes: &[u8], offset: usize, len: usize) -> Result<u64> {
+    let b = bytes.get(offset..offset + len).ok_or(Error::NotElf)?;
+    Ok(b.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+fn sections(elf: &[u8]) -> Result<Vec<Section>> {
+    // 64 bits, little endian
+    if elf.get(..6) != Some(b"\x7fELF\x02\x01") {
+        return Err(Error::NotElf);
+    }
+
+    let start = read(elf, 0x28, 8)? as usize;
+    let count = read(elf, 0x3c, 2)? as usize;
+    (0..count).map(|index| {
+        let header = start + index * SECTION_HEADER_LEN;
+        Ok(Section {
+            name: read(elf, header, 4)? as u32,
+            kind: read(elf, header + 4, 4)? as u32,
+            offset: read(elf, header + 24, 8)? as usize,
+            size: read(elf, header + 32, 8)? as usize,
+            link: read(elf, header + 40, 4)? as usize,
+        })
+    }).collect()
+}
+
+/// The NUL terminated string at `offset` in a string table section.
+fn string<'a>(elf: &'a [u8], table: &Section, offset: usize) -> Result<&'a str> {
+    let strings = elf.get(table.offset..table.offset + table.size).ok_or(Error::NotElf)?;
+    let bytes = strings.get(offset..).ok_or(Error::NotElf)?;
+    let end = bytes.iter().position(|&b| b == 0).ok_or(Error::NotElf)?;
+    str::from_utf8(&bytes[..end]).map_err(|_| Error::NotElf)
+}
+
+fn find_section<'a>(elf: &[u8], sections: &'a [Section], name: &'static str) -> Result<&'a Section> {
+    let names = sections.get(read(elf, 0x3e, 2)? as usize).ok_or(Error::NotElf)?;
+    for section in sections {
+        if string(elf, names, section.name as usize)? == name {
+            return Ok(section);
+        }
+    }
+    Err(Error::NoSection(name))
+}
+
+/// The function symbols of `elf`, demangled and sorted by address.
+pub fn functions(elf: &[u8]) -> Result<Vec<(u64, u32, String)>> {
+    let sections = sections(elf)?;
+    let symtab = sections.iter().find(|s| s.kind == SHT_SYMTAB).ok_or(Error::NoSection(".symtab"))?;
+    let strtab = sections.get(symtab.link).ok_or(Error::NotElf)?;
+
+    let mut functions = Vec::new();
+    for index in 0..symtab.size / SYMBOL_LEN {
+        let symbol = symtab.offset + index * SYMBOL_LEN;
+        let info = read(elf, symbol + 4, 1)? as u8;
+        let address = read(elf, symbol + 8, 8)?;
+        if info & 0xf != STT_FUNC || address == 0 {
+            continue;
+        }
+
+        let name = string(elf, strtab, read(elf, symbol, 4)? as usize)?;
+        let size = read(elf, symbol + 16, 8)? as u32;
+        functions.push((address, size, demangle(name)));
+    }
+
+    functions.sort();
+    functions.dedup_by_key(|f| f.0);
+    Ok(functions)
+}
+
+/// Writes the symbol table into the `.symbols` section of `elf`, and
+/// returns how many symbols are in it.
+pub fn embed(elf: &mut [u8]) -> Result<usize> {
+    let functions = functions(elf)?;
+    let (offset, size) = {
+        let sections = sections(elf)?;
+        let section = find_section(elf, &sections, ".symbols")?;
+        (section.offset, section.size)
+    };
+
+    let symbols: Vec<Symbol> = functions.iter()
+        .map(|&(address, size, ref name)| Symbol { address, size, name })
+        .collect();
+    let output = elf.get_mut(offset..offset + size).ok_or(Error::NotElf)?;
+    match symbols::write(&symbols, output) {
+        Ok(_) => Ok(symbols.len()),
+        Err(symbols::Error::TooBig) => Err(Error::TooBig(symbols::table_len(&symbols))),
+        Err(e) => Err(Error::Table(e)),
+    }
+}
+
+const ESCAPES: [(&str, &str); 14] = [
+    ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+    ("$LP$", "("), ("$RP$", ")"), ("$C$", ","), ("$u20$", " "), ("$u27$", "'"),
+    ("$u5b$", "["), ("$u5d$", "]"), ("$u7b$", "{"), ("$u7d$", "}"),
+];
+
+/// Turns a Rust symbol, `_ZN` then length prefixed path segments then `E`,
+/// into its path, without the hash rustc adds at the end. Other names are
+/// returned as they are.
+pub fn demangle(name: &str) -> String {
+    if !name.starts_with("_ZN") || !name.ends_with('E') {
+        return name.to_string();
+    }
+    let mut rest = &name[3..name.len() - 1];
+
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len = match rest[..digits].parse::<usize>() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+        segments.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    let is_hash = |s: &&str| s.len() == 17 && s.starts_with('h') && s[1..].bytes().all(|b| b.is_ascii_hexdigit());
+    if segments.last().is_some_and(is_hash) {
+        segments.pop();
+    }
+
+    let path = segments.iter().map(|segment| {
+        // segments can't start with `$`, so escapes there get a `_` first
+        let segment = if segment.starts_with("_$") { &segment[1..] } else { segment };
+        let mut segment = segment.replace("..", "::");
+        for &(escape, replacement) in ESCAPES.iter() {
+            segment = segment.replace(escape, replacement);
+        }
+        segment
+    }).collect::<Vec<_>>();
+    path.join("::")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangling() {
+        assert_eq!(demangle("_ZN11intermezzos5panic5panic17h3c1e4e7b0a1f2d9cE"), "intermezzos::panic::panic");
+        assert_eq!(demangle("_ZN4core3fmt5write17h0123456789abcdefE"), "core::fmt::write");
+        assert_eq!(
+            demangle("_ZN64_$LT$intermezzos..thread..Thread$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE"),
+            "<intermezzos::thread::Thread as core::fmt::Debug>::fmt");
+        assert_eq!(demangle("_start"), "_start");
+        assert_eq!(demangle("_ZN99tooshortE"), "_ZN99tooshortE");
+    }
+
+    #[test]
+    fn not_elf() {
+        assert!(functions(b"#!/bin/sh\n").is_err());
+    }
+}
@@ -0,0 +1,28 @@
+extern crate symbols_host;
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: embed-symbols <kernel ELF>");
+            process::exit(2);
+        },
+    };
+
+    let result = fs::read(&path).map_err(|e| e.to_string()).and_then(|mut elf| {
+        let count = symbols_host::embed(&mut elf).map_err(|e| e.to_string())?;
+        fs::write(&path, &elf).map_err(|e| e.to_string())?;
+        Ok(count)
+    });
+    match result {
+        Ok(count) => println!("{}: {} symbols embedded", path, count),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,222 @@
+//! The symbol table the kernel carries in itself, to put function names on
+//! the addresses in its backtraces. `symbols-host` writes it, from the
+//! kernel's ELF symbols, into the space the kernel sets aside for it once
+//! the kernel is linked.
+//!
+//! Everything is little endian: the magic, the number of symbols (u32),
+//! then for each symbol, sorted by address, its address (u64), size (u32)
+//! and the offset of its name (u32) in the names that follow, each stored
+//! as its length (u16) and bytes.
+
+#![no_std]
+
+use core::str;
+
+pub const MAGIC: [u8; 4] = *b"SYMS";
+
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Symbol<'a> {
+    pub address: u64,
+    /// In bytes; 0 when unknown, the symbol then covers everything up to
+    /// the next one.
+    pub size: u32,
+    pub name: &'a str,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The table needs more room than there is.
+    TooBig,
+    NameTooLong,
+    Unsorted,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let b = bytes.get(offset..offset + 2)?;
+    Some(b[0] as u16 | (b[1] as u16) << 8)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(read_u32(bytes, offset)? as u64 | (read_u32(bytes, offset + 4)? as u64) << 32)
+}
+
+fn write_le(output: &mut [u8], value: u64) {
+    for (i, b) in output.iter_mut().enumerate() {
+        *b = (value >> (i * 8)) as u8;
+    }
+}
+
+/// How many bytes `write` needs for `symbols`.
+pub fn table_len(symbols: &[Symbol]) -> usize {
+    let names: usize = symbols.iter().map(|symbol| 2 + symbol.name.len()).sum();
+    HEADER_LEN + symbols.len() * ENTRY_LEN + names
+}
+
+/// Writes the table of `symbols`, sorted by address, to `output`, and
+/// returns its length.
+pub fn write(symbols: &[Symbol], output: &mut [u8]) -> Result<usize, Error> {
+    let names_start = HEADER_LEN + symbols.len() * ENTRY_LEN;
+    if names_start > output.len() {
+        return Err(Error::TooBig);
+    }
+
+    output[..4].copy_from_slice(&MAGIC);
+    write_le(&mut output[4..8], symbols.len() as u64);
+
+    let mut names_len = 0;
+    let mut previous = 0;
+    for (index, symbol) in symbols.iter().enumerate() {
+        if symbol.address < previous {
+            return Err(Error::Unsorted);
+        }
+        previous = symbol.address;
+
+        let name = symbol.name.as_bytes();
+        if name.len() > u16::MAX as usize {
+            return Err(Error::NameTooLong);
+        }
+        let start = names_start + names_len;
+        if start + 2 + name.len() > output.len() {
+            return Err(Error::TooBig);
+        }
+        write_le(&mut output[start..start + 2], name.len() as u64);
+        output[start + 2..start + 2 + name.len()].copy_from_slice(name);
+
+        let entry = HEADER_LEN + index * ENTRY_LEN;
+        write_le(&mut output[entry..entry + 8], symbol.address);
+        write_le(&mut output[entry + 8..entry + 12], symbol.size as u64);
+        write_le(&mut output[entry + 12..entry + 16], names_len as u64);
+        names_len += 2 + name.len();
+    }
+
+    Ok(names_start + names_len)
+}
+
+/// A table written by `write`.
+#[derive(Clone, Copy)]
+pub struct Table<'a> {
+    bytes: &'a [u8],
+    count: usize,
+}
+
+impl<'a> Table<'a> {
+    /// Reads the table at the start of `bytes`; `None` if none was written
+    /// there.
+    pub fn new(bytes: &'a [u8]) -> Option<Table<'a>> {
+        if bytes.get(..4)? != MAGIC {
+            return None;
+        }
+
+        let count = read_u32(bytes, 4)? as usize;
+        if HEADER_LEN + count * ENTRY_LEN > bytes.len() {
+            return None;
+        }
+        Some(Table { bytes, count })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn address(&self, index: usize) -> u64 {
+        read_u64(self.bytes, HEADER_LEN + index * ENTRY_LEN).unwrap()
+    }
+
+    /// The symbol at `index`, if its name is intact.
+    pub fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        if index >= self.count {
+            return None;
+        }
+
+        let entry = HEADER_LEN + index * ENTRY_LEN;
+        let name_offset = HEADER_LEN + self.count * ENTRY_LEN + read_u32(self.bytes, entry + 12)? as usize;
+        let name_len = read_u16(self.bytes, name_offset)? as usize;
+        let name = self.bytes.get(name_offset + 2..name_offset + 2 + name_len)?;
+        Some(Symbol {
+            address: self.address(index),
+            size: read_u32(self.bytes, entry + 8)?,
+            name: str::from_utf8(name).ok()?,
+        })
+    }
+
+    /// The symbol `address` is in, and how far into it.
+    pub fn lookup(&self, address: u64) -> Option<(Symbol<'a>, u64)> {
+        // the last symbol starting at or before the address
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.address(middle) <= address {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        if low == 0 {
+            return None;
+        }
+
+        let symbol = self.get(low - 1)?;
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size as u64 {
+            return None;
+        }
+        Some((symbol, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: [Symbol<'static>; 3] = [
+        Symbol { address: 0x1000, size: 0x20, name: "_start" },
+        Symbol { address: 0x1040, size: 0, name: "intermezzos::panic::panic" },
+        Symbol { address: 0x2000, size: 0x10, name: "core::fmt::write" },
+    ];
+
+    #[test]
+    fn tables_round_trip() {
+        let mut bytes = [0u8; 256];
+        let len = write(&SYMBOLS, &mut bytes).unwrap();
+        assert_eq!(len, table_len(&SYMBOLS));
+        let table = Table::new(&bytes[..len]).unwrap();
+        assert_eq!(table.len(), 3);
+        for (index, symbol) in SYMBOLS.iter().enumerate() {
+            assert_eq!(table.get(index), Some(*symbol));
+        }
+        assert_eq!(table.get(3), None);
+
+        assert!(Table::new(&[0u8; 256]).is_none());
+        assert_eq!(write(&SYMBOLS, &mut bytes[..60]), Err(Error::TooBig));
+        assert_eq!(write(&[SYMBOLS[1], SYMBOLS[0]], &mut bytes), Err(Error::Unsorted));
+    }
+
+    #[test]
+    fn lookups() {
+        let mut bytes = [0u8; 256];
+        write(&SYMBOLS, &mut bytes).unwrap();
+        let table = Table::new(&bytes).unwrap();
+
+        assert_eq!(table.lookup(0xfff), None);
+        assert_eq!(table.lookup(0x1000), Some((SYMBOLS[0], 0)));
+        assert_eq!(table.lookup(0x101f), Some((SYMBOLS[0], 0x1f)));
+        // between symbols
+        assert_eq!(table.lookup(0x1020), None);
+        // no size: runs up to the next symbol
+        assert_eq!(table.lookup(0x1fff), Some((SYMBOLS[1], 0xfbf)));
+        assert_eq!(table.lookup(0x2008), Some((SYMBOLS[2], 8)));
+        assert_eq!(table.lookup(0x2010), None);
+    }
+}
@@ -0,0 +1,460 @@
+//! Line discipline: turns a stream of typed characters into edited lines.
+//!
+//! Input is expected in the form a VT100-style terminal sends it, so both
+//! the serial port and the keyboard driver (which translates its special
+//! keys into the same escape sequences) can feed the same editor. Changes
+//! to the line are echoed back as text and VT100 cursor movements.
+
+#![no_std]
+
+use core::fmt;
+use core::fmt::Write;
+
+/// Maximum number of characters in a line.
+pub const LINE_MAX: usize = 128;
+/// Number of lines remembered for Up/Down recall.
+pub const HISTORY_SIZE: usize = 16;
+
+const CTRL_A: char = '\x01';
+const CTRL_C: char = '\x03';
+const CTRL_E: char = '\x05';
+const BACKSPACE: char = '\x08';
+const CTRL_U: char = '\x15';
+const CTRL_W: char = '\x17';
+const ESCAPE: char = '\x1b';
+const DELETE: char = '\x7f';
+
+/// A completed line.
+#[derive(Clone, Copy)]
+pub struct Line {
+    bytes: [u8; LINE_MAX * 4],
+    len: usize,
+}
+
+impl Line {
+    fn new(chars: &[char]) -> Line {
+        let mut line = Line {
+            bytes: [0; LINE_MAX * 4],
+            len: 0,
+        };
+        for c in chars {
+            let len = c.encode_utf8(&mut line.bytes[line.len..]).len();
+            line.len += len;
+        }
+        line
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever filled from whole chars
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl fmt::Debug for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// What a character fed to the editor resulted in.
+#[derive(Debug)]
+pub enum Input {
+    /// Enter was pressed; `LineEditor::line` has the line.
+    Line,
+    /// Ctrl-C was pressed; the line being edited was discarded.
+    Interrupt,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    /// ESC was typed, the next character says what kind of sequence it is.
+    Started,
+    /// Inside `ESC [`: the first parameter, and whether it is complete.
+    /// Only that one is used; the others, as in xterm's `ESC [ 1 ; 5 C`
+    /// for Ctrl-Right, are read and ignored.
+    Csi(u8, bool),
+}
+
+pub struct LineEditor {
+    line: [char; LINE_MAX],
+    len: usize,
+    cursor: usize,
+    history: [[char; LINE_MAX]; HISTORY_SIZE],
+    history_len: [usize; HISTORY_SIZE],
+    history_count: usize,
+    /// How many entries back from the newest one Up/Down are showing,
+    /// or 0 while editing a fresh line.
+    history_offset: usize,
+    escape: Escape,
+    last_was_cr: bool,
+    /// The last line entered.
+    entered: Line,
+}
+
+impl Default for LineEditor {
+    fn default() -> LineEditor {
+        LineEditor::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor {
+            line: ['\0'; LINE_MAX],
+            len: 0,
+            cursor: 0,
+            history: [['\0'; LINE_MAX]; HISTORY_SIZE],
+            history_len: [0; HISTORY_SIZE],
+            history_count: 0,
+            history_offset: 0,
+            escape: Escape::None,
+            last_was_cr: false,
+            entered: Line::new(&[]),
+        }
+    }
+
+    /// The last line entered, once `feed` returned `Input::Line`.
+    pub fn line(&self) -> Line {
+        self.entered
+    }
+
+    /// The line as edited so far.
+    pub fn current(&self) -> Line {
+        Line::new(&self.line[..self.len])
+    }
+
+    /// Processes one typed character, writing whatever is needed to update
+    /// the terminal to `echo`.
+    pub fn feed<W: Write>(&mut self, c: char, echo: &mut W) -> Result<Option<Input>, fmt::Error> {
+        let last_was_cr = self.last_was_cr;
+        self.last_was_cr = c == '\r';
+
+        match self.escape {
+            Escape::Started => {
+                self.escape = if c == '[' || c == 'O' { Escape::Csi(0, false) } else { Escape::None };
+                return Ok(None);
+            },
+            Escape::Csi(param, complete) => {
+                match c {
+                    '0'..='9' if !complete => {
+                        let digit = c as u8 - b'0';
+                        self.escape = Escape::Csi(param.saturating_mul(10).saturating_add(digit), false);
+                    },
+                    // more parameters, and intermediate bytes
+                    '\u{20}'..='\u{3f}' => self.escape = Escape::Csi(param, true),
+                    '\u{40}'..='\u{7e}' => {
+                        self.escape = Escape::None;
+                        self.control_sequence(param, c, echo)?;
+                    },
+                    _ => self.escape = Escape::None,
+                }
+                return Ok(None);
+            },
+            Escape::None => {},
+        }
+
+        match c {
+            // terminals send CR, CR LF or LF for Enter
+            '\n' if last_was_cr => {},
+            '\r' | '\n' => {
+                echo.write_char('\n')?;
+                self.entered = self.current();
+                self.remember();
+                self.clear();
+                return Ok(Some(Input::Line));
+            },
+            CTRL_C => {
+                echo.write_str("^C\n")?;
+                self.clear();
+                return Ok(Some(Input::Interrupt));
+            },
+            BACKSPACE | DELETE => {
+                if self.cursor > 0 {
+                    self.move_left(1, echo)?;
+                    self.delete(1, echo)?;
+                }
+            },
+            CTRL_U => {
+                let cursor = self.cursor;
+                self.move_left(cursor, echo)?;
+                self.delete(cursor, echo)?;
+            },
+            CTRL_W => {
+                let mut start = self.cursor;
+                while start > 0 && self.line[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != ' ' {
+                    start -= 1;
+                }
+                let count = self.cursor - start;
+                self.move_left(count, echo)?;
+                self.delete(count, echo)?;
+            },
+            CTRL_A => {
+                let cursor = self.cursor;
+                self.move_left(cursor, echo)?;
+            },
+            CTRL_E => {
+                let count = self.len - self.cursor;
+                self.move_right(count, echo)?;
+            },
+            ESCAPE => self.escape = Escape::Started,
+            c if c < ' ' => {},
+            c => self.insert(c, echo)?,
+        }
+
+        Ok(None)
+    }
+
+    fn control_sequence<W: Write>(&mut self, param: u8, c: char, echo: &mut W) -> fmt::Result {
+        match (c, param) {
+            ('A', _) => self.recall(self.history_offset + 1, echo),
+            ('B', _) => {
+                let offset = self.history_offset.saturating_sub(1);
+                self.recall(offset, echo)
+            },
+            ('C', _) => self.move_right(1, echo),
+            ('D', _) => self.move_left(1, echo),
+            ('H', _) | ('~', 1) => {
+                let cursor = self.cursor;
+                self.move_left(cursor, echo)
+            },
+            ('F', _) | ('~', 4) => {
+                let count = self.len - self.cursor;
+                self.move_right(count, echo)
+            },
+            ('~', 3) => self.delete(1, echo),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.cursor = 0;
+        self.history_offset = 0;
+    }
+
+    fn insert<W: Write>(&mut self, c: char, echo: &mut W) -> fmt::Result {
+        if self.len == LINE_MAX {
+            return Ok(());
+        }
+
+        let mut i = self.len;
+        while i > self.cursor {
+            self.line[i] = self.line[i - 1];
+            i -= 1;
+        }
+        self.line[self.cursor] = c;
+        self.len += 1;
+        self.cursor += 1;
+
+        echo.write_char(c)?;
+        self.redraw_tail(0, echo)
+    }
+
+    /// Removes `count` characters at the cursor.
+    fn delete<W: Write>(&mut self, count: usize, echo: &mut W) -> fmt::Result {
+        let count = core::cmp::min(count, self.len - self.cursor);
+        if count == 0 {
+            return Ok(());
+        }
+
+        for i in self.cursor..self.len - count {
+            self.line[i] = self.line[i + count];
+        }
+        self.len -= count;
+
+        self.redraw_tail(count, echo)
+    }
+
+    /// Reprints everything after the cursor, blanks out `erased` characters
+    /// that used to follow it, and moves the terminal cursor back.
+    fn redraw_tail<W: Write>(&self, erased: usize, echo: &mut W) -> fmt::Result {
+        for c in &self.line[self.cursor..self.len] {
+            echo.write_char(*c)?;
+        }
+        for _ in 0..erased {
+            echo.write_char(' ')?;
+        }
+
+        let back = self.len - self.cursor + erased;
+        if back > 0 {
+            write!(echo, "\x1b[{}D", back)?;
+        }
+        Ok(())
+    }
+
+    fn move_left<W: Write>(&mut self, count: usize, echo: &mut W) -> fmt::Result {
+        let count = core::cmp::min(count, self.cursor);
+        self.cursor -= count;
+        if count > 0 {
+            write!(echo, "\x1b[{}D", count)?;
+        }
+        Ok(())
+    }
+
+    fn move_right<W: Write>(&mut self, count: usize, echo: &mut W) -> fmt::Result {
+        let count = core::cmp::min(count, self.len - self.cursor);
+        self.cursor += count;
+        if count > 0 {
+            write!(echo, "\x1b[{}C", count)?;
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+
+        // don't store the same command twice in a row
+        if self.history_count > 0 {
+            let newest = (self.history_count - 1) % HISTORY_SIZE;
+            let len = self.history_len[newest];
+            if self.history[newest][..len] == self.line[..self.len] {
+                return;
+            }
+        }
+
+        let slot = self.history_count % HISTORY_SIZE;
+        self.history[slot] = self.line;
+        self.history_len[slot] = self.len;
+        self.history_count += 1;
+    }
+
+    /// Replaces the line with the history entry `offset` steps back, where
+    /// offset 0 is an empty line.
+    fn recall<W: Write>(&mut self, offset: usize, echo: &mut W) -> fmt::Result {
+        let available = core::cmp::min(self.history_count, HISTORY_SIZE);
+        if offset > available || offset == self.history_offset {
+            return Ok(());
+        }
+
+        let cursor = self.cursor;
+        self.move_left(cursor, echo)?;
+        echo.write_str("\x1b[K")?;
+
+        if offset == 0 {
+            self.len = 0;
+        } else {
+            let slot = (self.history_count - offset) % HISTORY_SIZE;
+            self.line = self.history[slot];
+            self.len = self.history_len[slot];
+        }
+        self.cursor = self.len;
+        self.history_offset = offset;
+
+        for c in &self.line[..self.len] {
+            echo.write_char(*c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt;
+    use super::*;
+
+    struct Echo {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl Echo {
+        fn new() -> Echo {
+            Echo { bytes: [0; 256], len: 0 }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.bytes[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Echo {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    fn type_str(editor: &mut LineEditor, s: &str) -> Option<Input> {
+        let mut echo = Echo::new();
+        let mut result = None;
+        for c in s.chars() {
+            if let Some(input) = editor.feed(c, &mut echo).unwrap() {
+                result = Some(input);
+            }
+        }
+        result
+    }
+
+    fn enter(editor: &mut LineEditor, s: &str) -> Line {
+        match type_str(editor, s) {
+            Some(Input::Line) => editor.line(),
+            other => panic!("expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plain_line() {
+        let mut editor = LineEditor::new();
+        assert_eq!(enter(&mut editor, "hello\r").as_str(), "hello");
+        assert_eq!(enter(&mut editor, "world\r\n").as_str(), "world");
+        assert_eq!(enter(&mut editor, "\n").as_str(), "");
+    }
+
+    #[test]
+    fn backspace_and_cursor_movement() {
+        let mut editor = LineEditor::new();
+        assert_eq!(enter(&mut editor, "helo\x08lo\n").as_str(), "hello");
+        assert_eq!(enter(&mut editor, "hllo\x1b[D\x1b[D\x1b[De\n").as_str(), "hello");
+        assert_eq!(enter(&mut editor, "xhello\x1b[H\x1b[3~\n").as_str(), "hello");
+        // Ctrl-Right and Ctrl-Left, with a modifier parameter
+        assert_eq!(enter(&mut editor, "hllo\x1b[1;5D\x1b[1;5D\x1b[1;5D\x1b[1;5D\x1b[1;5Ce\n").as_str(), "hello");
+    }
+
+    #[test]
+    fn kill_line_and_word() {
+        let mut editor = LineEditor::new();
+        assert_eq!(enter(&mut editor, "junk\x15ok\n").as_str(), "ok");
+        assert_eq!(enter(&mut editor, "one two \x17three\n").as_str(), "one three");
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut editor = LineEditor::new();
+        match type_str(&mut editor, "abc\x03") {
+            Some(Input::Interrupt) => {},
+            other => panic!("expected an interrupt, got {:?}", other),
+        }
+        assert_eq!(editor.current().as_str(), "");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::new();
+        type_str(&mut editor, "first\n");
+        type_str(&mut editor, "second\n");
+        type_str(&mut editor, "second\n");
+
+        assert_eq!(enter(&mut editor, "\x1b[A\n").as_str(), "second");
+        assert_eq!(enter(&mut editor, "\x1b[A\x1b[A\n").as_str(), "first");
+        // recalling "first" made it the newest entry again
+        assert_eq!(enter(&mut editor, "\x1b[A\x1b[A\x1b[B!\n").as_str(), "first!");
+        assert_eq!(enter(&mut editor, "\x1b[A\x1b[B\n").as_str(), "");
+    }
+
+    #[test]
+    fn echo_insert_in_middle() {
+        let mut editor = LineEditor::new();
+        let mut echo = Echo::new();
+        for c in "ac\x1b[Db".chars() {
+            editor.feed(c, &mut echo).unwrap();
+        }
+        assert_eq!(echo.as_str(), "ac\x1b[1Dbc\x1b[1D");
+    }
+}
@@ -0,0 +1,142 @@
+//! A parser for the subset of ANSI/VT100 escape sequences we render.
+//!
+//! See https://vt100.net/emu/dec_ansi_parser for the full state machine;
+//! this one only knows about plain characters, C0 controls and CSI
+//! sequences, and silently drops anything else that starts with ESC,
+//! intermediate bytes and final byte included.
+
+const ESC: u8 = 0x1b;
+
+/// Most CSI sequences we handle take one or two parameters; SGR can take
+/// more, and extra ones are ignored.
+pub const MAX_PARAMS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    /// Nothing to do yet, the byte was part of a sequence.
+    None,
+    /// Draw the byte.
+    Print(u8),
+    /// Execute a C0 control such as `\n`, `\r`, `\t` or backspace.
+    Execute(u8),
+    /// A complete control sequence `ESC [ params final`.
+    Csi(Params, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Params {
+    values: [u16; MAX_PARAMS],
+    count: usize,
+}
+
+impl Params {
+    fn new() -> Params {
+        Params { values: [0; MAX_PARAMS], count: 0 }
+    }
+
+    /// The parameters as given; missing ones read as 0.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.values[..self.count]
+    }
+
+    /// Parameter `index`, or `default` if it was left out or 0.
+    pub fn get(&self, index: usize, default: u16) -> u16 {
+        match self.as_slice().get(index) {
+            Some(&0) | None => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// `ESC` then intermediate bytes, as in `ESC ( B`: waiting for the
+    /// final byte.
+    EscapeIntermediate,
+    Csi,
+}
+
+#[derive(Clone, Copy)]
+pub struct Parser {
+    state: State,
+    params: Params,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: Params::new(),
+        }
+    }
+
+    /// Abandons any escape sequence that was being read.
+    pub fn reset(&mut self) {
+        self.state = State::Ground;
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Action {
+        match self.state {
+            State::Ground => match byte {
+                ESC => {
+                    self.state = State::Escape;
+                    Action::None
+                },
+                0x00..=0x1f | 0x7f => Action::Execute(byte),
+                _ => Action::Print(byte),
+            },
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => {
+                        self.params = Params::new();
+                        State::Csi
+                    },
+                    0x20..=0x2f => State::EscapeIntermediate,
+                    _ => State::Ground,
+                };
+                Action::None
+            },
+            State::EscapeIntermediate => {
+                // the final byte, 0x30 to 0x7e, ends the sequence unprinted
+                match byte {
+                    0x20..=0x2f => {},
+                    _ => self.state = State::Ground,
+                }
+                Action::None
+            },
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.params.count == 0 {
+                        self.params.count = 1;
+                    }
+                    if self.params.count <= MAX_PARAMS {
+                        let value = &mut self.params.values[self.params.count - 1];
+                        *value = value.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    }
+                    Action::None
+                },
+                b';' => {
+                    if self.params.count == 0 {
+                        self.params.count = 1;
+                    }
+                    if self.params.count < MAX_PARAMS {
+                        self.params.count += 1;
+                    }
+                    Action::None
+                },
+                // intermediate and private marker bytes
+                0x20..=0x2f | b'<'..=b'?' => Action::None,
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    Action::Csi(self.params, byte)
+                },
+                _ => {
+                    self.state = State::Ground;
+                    Action::None
+                },
+            },
+        }
+    }
+}
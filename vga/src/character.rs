@@ -24,6 +24,47 @@ pub enum Color {
     White = 0xF,
 }
 
+impl Color {
+    fn from_u8(value: u8) -> Color {
+        match value & 0xF {
+            0x0 => Color::Black,
+            0x1 => Color::Blue,
+            0x2 => Color::Green,
+            0x3 => Color::Cyan,
+            0x4 => Color::Red,
+            0x5 => Color::Magenta,
+            0x6 => Color::Brown,
+            0x7 => Color::Gray,
+            0x8 => Color::DarkGray,
+            0x9 => Color::BrightBlue,
+            0xA => Color::BrightGreen,
+            0xB => Color::BrightCyan,
+            0xC => Color::BrightRed,
+            0xD => Color::BrightMagenta,
+            0xE => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+
+    /// Maps an ANSI color number (0 = black .. 7 = white) onto the VGA
+    /// palette, whose red and blue bits are the other way round.
+    pub fn from_ansi(index: u8, bright: bool) -> Color {
+        let vga = match index & 0x7 {
+            1 => 0x4,
+            3 => 0x6,
+            4 => 0x1,
+            6 => 0x3,
+            i => i,
+        };
+        Color::from_u8(if bright { vga | 0x8 } else { vga })
+    }
+
+    /// The high-intensity variant of this color.
+    pub fn bright(self) -> Color {
+        Color::from_u8(self as u8 | 0x8)
+    }
+}
+
 impl Character {
     pub fn new(character: u8, foreground: Color, background: Color) -> Character {
         let attribute = ((background as u8) << 4) + (foreground as u8);
@@ -62,4 +103,14 @@ mod tests {
         assert_eq!(character.attribute, 0xF8);
     }
 
+    #[test]
+    fn ansi_colors() {
+        assert_eq!(Color::from_ansi(1, false), Color::Red);
+        assert_eq!(Color::from_ansi(3, false), Color::Brown);
+        assert_eq!(Color::from_ansi(3, true), Color::Yellow);
+        assert_eq!(Color::from_ansi(4, true), Color::BrightBlue);
+        assert_eq!(Color::from_ansi(7, false), Color::Gray);
+        assert_eq!(Color::Green.bright(), Color::BrightGreen);
+    }
+
 }
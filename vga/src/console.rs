@@ -0,0 +1,712 @@
+use core::cmp;
+use core::fmt;
+use core::fmt::Write;
+use core::ops::Range;
+
+use ansi::{Action, Params, Parser};
+use character::Character;
+use character::Color;
+use cp437;
+use {DEFAULT_COLS, DEFAULT_ROWS};
+
+/// The largest text mode a console can hold.
+pub const MAX_ROWS: usize = 50;
+pub const MAX_COLS: usize = 90;
+
+/// Most rows of history a console can keep.
+pub const SCROLLBACK_MAX_ROWS: usize = 64;
+
+/// Most regions a console can be split into.
+pub const MAX_REGIONS: usize = 4;
+
+/// Identifies a region of a console, see `Console::split`.
+pub type RegionId = usize;
+
+/// The region covering the whole console until it is split. It is the one
+/// the console's own `Write` goes to, the one that keeps the scrollback and
+/// the one that shows the hardware cursor.
+pub const MAIN_REGION: RegionId = 0;
+
+/// Which side of a region `Console::split` takes the new region from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge {
+    Top,
+    Bottom,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RegionError {
+    /// The console already has `MAX_REGIONS` regions.
+    TooManyRegions,
+    /// The region to split has fewer rows than asked for, plus one to keep,
+    /// or a resize would leave the main region without any.
+    NoRoom,
+}
+
+/// A band of rows with its own cursor and colors, scrolling on its own.
+#[derive(Clone, Copy)]
+struct Region {
+    top: usize,
+    rows: usize,
+    /// The console's width, kept here for the cursor arithmetic.
+    cols: usize,
+    /// Relative to the region's first cell.
+    position: usize,
+    foreground_color: Color,
+    background_color: Color,
+    default_foreground_color: Color,
+    default_background_color: Color,
+    bold: bool,
+    reverse: bool,
+    saved_position: usize,
+    parser: Parser,
+}
+
+impl Region {
+    fn new(top: usize, rows: usize, cols: usize, foreground_color: Color, background_color: Color) -> Region {
+        Region {
+            top,
+            rows,
+            cols,
+            position: 0,
+            foreground_color,
+            background_color,
+            default_foreground_color: foreground_color,
+            default_background_color: background_color,
+            bold: false,
+            reverse: false,
+            saved_position: 0,
+            parser: Parser::new(),
+        }
+    }
+
+    /// The screen cells the region covers.
+    fn cells(&self) -> Range<usize> {
+        self.top * self.cols..(self.top + self.rows) * self.cols
+    }
+
+    /// The colors to draw with, after applying bold and reverse video.
+    fn colors(&self) -> (Color, Color) {
+        let foreground = if self.bold {
+            self.foreground_color.bright()
+        } else {
+            self.foreground_color
+        };
+
+        if self.reverse {
+            (self.background_color, foreground)
+        } else {
+            (foreground, self.background_color)
+        }
+    }
+
+    fn blank(&self) -> Character {
+        let (foreground, background) = self.colors();
+        Character::new(b' ', foreground, background)
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        let row = cmp::min(row, self.rows - 1);
+        let column = cmp::min(column, self.cols - 1);
+        self.position = row * self.cols + column;
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        if params.as_slice().is_empty() {
+            self.reset_attributes();
+        }
+
+        for &param in params.as_slice() {
+            match param {
+                0 => self.reset_attributes(),
+                1 => self.bold = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                27 => self.reverse = false,
+                30..=37 => self.foreground_color = Color::from_ansi((param - 30) as u8, false),
+                39 => self.foreground_color = self.default_foreground_color,
+                40..=47 => self.background_color = Color::from_ansi((param - 40) as u8, false),
+                49 => self.background_color = self.default_background_color,
+                90..=97 => self.foreground_color = Color::from_ansi((param - 90) as u8, true),
+                100..=107 => self.background_color = Color::from_ansi((param - 100) as u8, true),
+                _ => {},
+            }
+        }
+    }
+
+    fn reset_attributes(&mut self) {
+        self.foreground_color = self.default_foreground_color;
+        self.background_color = self.default_background_color;
+        self.bold = false;
+        self.reverse = false;
+    }
+}
+
+/// The top and rows of region `id` once the main region, `main`, has
+/// `main_rows` rows: the regions below it move by the difference.
+fn new_extent(id: RegionId, region: &Region, main: &Region, main_rows: usize) -> (usize, usize) {
+    if id == MAIN_REGION {
+        (region.top, main_rows)
+    } else if region.top > main.top {
+        (region.top + main_rows - main.rows, region.rows)
+    } else {
+        (region.top, region.rows)
+    }
+}
+
+/// The text of one virtual console, divided into regions.
+pub struct Console {
+    rows: usize,
+    cols: usize,
+    /// The screen, `cols` cells per row; the cells past `rows * cols` are
+    /// unused.
+    pub(crate) buffer: [Character; MAX_ROWS * MAX_COLS],
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
+    /// Rows that scrolled off the top of the main region, as a ring buffer.
+    /// Only the first `cols` cells of each are shown.
+    history: [[Character; MAX_COLS]; SCROLLBACK_MAX_ROWS],
+    /// Index of the oldest row in `history`.
+    history_start: usize,
+    history_len: usize,
+    history_limit: usize,
+    /// How many rows back from the live view the main region shows.
+    view_offset: usize,
+    /// Screen cells that changed since the last flush; empty if none did.
+    dirty: Range<usize>,
+}
+
+impl Console {
+    /// A console the size of the 80x25 text mode we boot in.
+    pub fn new() -> Console {
+        let foreground_color = Color::Green;
+        let background_color = Color::Black;
+
+        let main = Region::new(0, DEFAULT_ROWS, DEFAULT_COLS, foreground_color, background_color);
+        let blank = main.blank();
+
+        Console {
+            rows: DEFAULT_ROWS,
+            cols: DEFAULT_COLS,
+            buffer: [blank; MAX_ROWS * MAX_COLS],
+            regions: [main; MAX_REGIONS],
+            region_count: 1,
+            history: [[blank; MAX_COLS]; SCROLLBACK_MAX_ROWS],
+            history_start: 0,
+            history_len: 0,
+            history_limit: SCROLLBACK_MAX_ROWS,
+            view_offset: 0,
+            dirty: 0..DEFAULT_ROWS * DEFAULT_COLS,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Whether `resize` to `rows` rows leaves the main region at least one.
+    pub(crate) fn can_resize(&self, rows: usize) -> bool {
+        self.regions[MAIN_REGION].rows + rows > self.rows
+    }
+
+    /// Changes the console to `rows` by `cols` cells, e.g. after switching
+    /// text modes. The main region grows or shrinks by the difference in
+    /// rows, the other regions keep their size and stay next to it. Rows
+    /// are cut off or padded on the right; if the main region shrinks
+    /// below its cursor, its top rows go to the history first.
+    pub fn resize(&mut self, rows: usize, cols: usize) -> Result<(), RegionError> {
+        assert!(rows <= MAX_ROWS && cols <= MAX_COLS && cols > 0);
+
+        if !self.can_resize(rows) {
+            return Err(RegionError::NoRoom);
+        }
+        let main = self.regions[MAIN_REGION];
+        let main_rows = main.rows + rows - self.rows;
+
+        self.set_view_offset(0);
+        let cursor_row = main.position / self.cols;
+        if cursor_row >= main_rows {
+            let column = main.position % self.cols;
+            self.update_region(MAIN_REGION, |console, region| {
+                for _ in main_rows - 1..cursor_row {
+                    console.scroll(region);
+                }
+                region.position = (main_rows - 1) * region.cols + column;
+            });
+        }
+
+        // the row each row of the new layout comes from, if any; regions
+        // below the main region move by however many rows it grew or shrank
+        let mut sources = [None; MAX_ROWS];
+        for (id, region) in self.regions[..self.region_count].iter().enumerate() {
+            let (top, new_rows) = new_extent(id, region, &main, main_rows);
+            for row in 0..cmp::min(region.rows, new_rows) {
+                sources[top + row] = Some(region.top + row);
+            }
+        }
+
+        // lay the rows out again with the new width, in place as there's
+        // no room for a second screen on a thread's stack: rows that move
+        // towards the start go first, from the top, then those that move
+        // towards the end, from the bottom, so none is overwritten before
+        // it has moved
+        let old_cols = self.cols;
+        let width = cmp::min(old_cols, cols);
+        for (row, source) in sources[..rows].iter().enumerate() {
+            if let Some(source) = *source {
+                let (from, to) = (source * old_cols, row * cols);
+                if to <= from {
+                    for i in 0..width {
+                        self.buffer[to + i] = self.buffer[from + i];
+                    }
+                }
+            }
+        }
+        for (row, source) in sources[..rows].iter().enumerate().rev() {
+            if let Some(source) = *source {
+                let (from, to) = (source * old_cols, row * cols);
+                if to > from {
+                    for i in (0..width).rev() {
+                        self.buffer[to + i] = self.buffer[from + i];
+                    }
+                }
+            }
+        }
+
+        let blank = self.regions[MAIN_REGION].blank();
+        for (row, source) in sources[..rows].iter().enumerate() {
+            let start = row * cols + if source.is_some() { width } else { 0 };
+            for cell in self.buffer[start..(row + 1) * cols].iter_mut() {
+                *cell = blank;
+            }
+        }
+
+        for (id, region) in self.regions[..self.region_count].iter_mut().enumerate() {
+            let (top, new_rows) = new_extent(id, region, &main, main_rows);
+            let row = cmp::min(region.position / self.cols, new_rows - 1);
+            let column = cmp::min(region.position % self.cols, cols - 1);
+            region.top = top;
+            region.rows = new_rows;
+            region.cols = cols;
+            region.position = row * cols + column;
+            region.saved_position = 0;
+        }
+
+        self.rows = rows;
+        self.cols = cols;
+        self.dirty = 0..rows * cols;
+        Ok(())
+    }
+
+    /// Takes `rows` rows from the `edge` of region `id` for a new, blank
+    /// region and returns its id. The new region starts out with the colors
+    /// the old one currently uses.
+    ///
+    /// Splitting a single row off the top of the main region gives a status
+    /// bar that the main region's output can't scroll away.
+    pub fn split(&mut self, id: RegionId, edge: Edge, rows: usize) -> Result<RegionId, RegionError> {
+        assert!(id < self.region_count);
+        if self.region_count == MAX_REGIONS {
+            return Err(RegionError::TooManyRegions);
+        }
+
+        let old = self.regions[id];
+        if rows == 0 || rows >= old.rows {
+            return Err(RegionError::NoRoom);
+        }
+
+        let (top, old_top) = match edge {
+            Edge::Top => (old.top, old.top + rows),
+            Edge::Bottom => (old.top + old.rows - rows, old.top),
+        };
+
+        let mut new = Region::new(top, rows, self.cols, old.foreground_color, old.background_color);
+        new.bold = old.bold;
+        new.reverse = old.reverse;
+
+        // the text stays where it is on screen, so the old cursor moves by
+        // however many rows were taken from above it
+        let removed_above = (old_top - old.top) * self.cols;
+        let region = &mut self.regions[id];
+        region.top = old_top;
+        region.rows = old.rows - rows;
+        region.position = cmp::min(old.position.saturating_sub(removed_above), region.rows * self.cols - 1);
+
+        let new_id = self.region_count;
+        self.regions[new_id] = new;
+        self.region_count += 1;
+
+        self.erase(&new, new.cells());
+        self.set_view_offset(0);
+        Ok(new_id)
+    }
+
+    /// A writer for region `id`.
+    pub fn region<'a>(&'a mut self, id: RegionId) -> RegionWriter<'a> {
+        assert!(id < self.region_count);
+        RegionWriter { console: self, id }
+    }
+
+    /// Keeps at most `rows` rows of history (up to `SCROLLBACK_MAX_ROWS`);
+    /// zero turns scrollback off. Shrinking it drops the oldest rows.
+    pub fn set_scrollback_limit(&mut self, rows: usize) {
+        let rows = cmp::min(rows, SCROLLBACK_MAX_ROWS);
+
+        // straighten the ring in place so that the newest rows survive at
+        // the start; it's too big for a copy on a thread's stack
+        let count = cmp::min(self.history_len, rows);
+        let first_kept = (self.history_start + self.history_len - count) % SCROLLBACK_MAX_ROWS;
+        self.history.rotate_left(first_kept);
+        let blank = self.regions[MAIN_REGION].blank();
+        for row in self.history[count..self.history_len].iter_mut() {
+            *row = [blank; MAX_COLS];
+        }
+
+        self.history_start = 0;
+        self.history_len = count;
+        self.history_limit = rows;
+        let offset = cmp::min(self.view_offset, count);
+        self.set_view_offset(offset);
+    }
+
+    pub fn scrollback_limit(&self) -> usize {
+        self.history_limit
+    }
+
+    /// Number of rows currently held in the history.
+    pub fn scrollback_len(&self) -> usize {
+        self.history_len
+    }
+
+    /// How many rows above the live view the main region currently shows.
+    pub fn scrollback_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    /// Shows the main region `rows` rows above the live view, as far as the
+    /// history goes.
+    pub fn scroll_to(&mut self, rows: usize) {
+        let offset = cmp::min(rows, self.history_len);
+        self.set_view_offset(offset);
+    }
+
+    fn set_view_offset(&mut self, offset: usize) {
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            let cells = self.regions[MAIN_REGION].cells();
+            self.mark_dirty(cells);
+        }
+    }
+
+    /// Adds `range` to the cells the next flush has to write.
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        if self.dirty.start >= self.dirty.end {
+            self.dirty = range;
+        } else {
+            self.dirty = cmp::min(self.dirty.start, range.start)..cmp::max(self.dirty.end, range.end);
+        }
+    }
+
+    /// Returns the cells that changed since the last call, and forgets them.
+    pub(crate) fn take_dirty(&mut self) -> Range<usize> {
+        let dirty = self.dirty.clone();
+        self.dirty = 0..0;
+        dirty
+    }
+
+    /// Moves the view up by almost a screen, keeping one row for context.
+    pub fn page_up(&mut self) {
+        let offset = self.view_offset + self.regions[MAIN_REGION].rows - 1;
+        self.scroll_to(offset);
+    }
+
+    pub fn page_down(&mut self) {
+        let offset = self.view_offset.saturating_sub(self.regions[MAIN_REGION].rows - 1);
+        self.scroll_to(offset);
+    }
+
+    /// Row `row` of the screen as it should currently be displayed, which
+    /// is part of the history while the main region is scrolled back.
+    pub(crate) fn visible_row(&self, row: usize) -> &[Character] {
+        let main = &self.regions[MAIN_REGION];
+        let row = if row >= main.top && row < main.top + main.rows {
+            let line = self.history_len - self.view_offset + (row - main.top);
+            if line < self.history_len {
+                return &self.history[(self.history_start + line) % SCROLLBACK_MAX_ROWS][..self.cols];
+            }
+            main.top + line - self.history_len
+        } else {
+            row
+        };
+
+        &self.buffer[row * self.cols..(row + 1) * self.cols]
+    }
+
+    /// Where the main region's cursor shows up on screen, if it is not
+    /// scrolled out of view.
+    pub(crate) fn visible_position(&self) -> Option<usize> {
+        let main = &self.regions[MAIN_REGION];
+        let position = main.position + self.view_offset * self.cols;
+        if position < main.rows * self.cols {
+            Some(main.top * self.cols + position)
+        } else {
+            None
+        }
+    }
+
+    /// Appends the top row of `region` to the history, overwriting the
+    /// oldest row once the limit is reached.
+    fn save_top_row(&mut self, region: &Region) {
+        if self.history_limit == 0 {
+            return;
+        }
+
+        let (start, cols) = (region.top * region.cols, region.cols);
+        let mut row = [region.blank(); MAX_COLS];
+        row[..cols].copy_from_slice(&self.buffer[start..start + cols]);
+
+        let index = (self.history_start + self.history_len) % SCROLLBACK_MAX_ROWS;
+        self.history[index] = row;
+
+        if self.history_len < self.history_limit {
+            self.history_len += 1;
+        } else {
+            self.history_start = (self.history_start + 1) % SCROLLBACK_MAX_ROWS;
+        }
+    }
+
+    pub fn set_foreground_color(&mut self, color: Color) {
+        self.region(MAIN_REGION).set_foreground_color(color);
+    }
+
+    pub fn set_background_color(&mut self, color: Color) {
+        self.region(MAIN_REGION).set_background_color(color);
+    }
+
+    pub fn invert(&mut self) {
+        self.region(MAIN_REGION).invert();
+    }
+
+    /// Puts CP437 glyphs into the main region as they are, without
+    /// interpreting control characters or escape sequences.
+    pub fn write_cp437(&mut self, glyphs: &[u8]) {
+        self.region(MAIN_REGION).write_cp437(glyphs);
+    }
+
+    /// Runs `f` on a copy of region `id` and stores the result, so that the
+    /// region can be changed alongside the rest of the console.
+    fn update_region<F: FnOnce(&mut Console, &mut Region)>(&mut self, id: RegionId, f: F) {
+        let mut region = self.regions[id];
+        f(self, &mut region);
+        self.regions[id] = region;
+    }
+
+    fn write_byte(&mut self, region: &mut Region, byte: u8) {
+        match region.parser.advance(byte) {
+            Action::None => {},
+            Action::Print(byte) => self.put(region, byte),
+            Action::Execute(byte) => self.execute(region, byte),
+            Action::Csi(params, byte) => self.control_sequence(region, &params, byte),
+        }
+    }
+
+    /// Writes `c` as its CP437 glyph, or the replacement glyph if there is
+    /// none. Only ASCII goes through the escape sequence parser; some glyphs
+    /// like the arrows share their codes with control characters.
+    fn put_char(&mut self, region: &mut Region, c: char) {
+        if c.is_ascii() {
+            self.write_byte(region, c as u8);
+        } else {
+            region.parser.reset();
+            self.put(region, cp437::encode(c));
+        }
+    }
+
+    fn put(&mut self, region: &mut Region, byte: u8) {
+        let (foreground, background) = region.colors();
+        let position = region.top * region.cols + region.position;
+        self.buffer[position] = Character::new(byte, foreground, background);
+        self.mark_dirty(position..position + 1);
+        region.position += 1;
+
+        if region.position >= region.rows * region.cols {
+            self.scroll(region);
+        }
+    }
+
+    fn execute(&mut self, region: &mut Region, byte: u8) {
+        let row = region.position / region.cols;
+        let column = region.position % region.cols;
+
+        match byte {
+            b'\n' => {
+                region.position = (row + 1) * region.cols;
+                if region.position >= region.rows * region.cols {
+                    self.scroll(region);
+                }
+            },
+            b'\r' => region.position = row * region.cols,
+            b'\t' => {
+                let next_stop = cmp::min((column / 8 + 1) * 8, region.cols - 1);
+                region.position = row * region.cols + next_stop;
+            },
+            0x08 if column > 0 => region.position -= 1,
+            _ => {},
+        }
+    }
+
+    fn control_sequence(&mut self, region: &mut Region, params: &Params, byte: u8) {
+        let row = region.position / region.cols;
+        let column = region.position % region.cols;
+        let n = params.get(0, 1) as usize;
+        let position = region.top * region.cols + region.position;
+
+        match byte {
+            b'A' => region.move_to(row.saturating_sub(n), column),
+            b'B' => region.move_to(row + n, column),
+            b'C' => region.move_to(row, column + n),
+            b'D' => region.move_to(row, column.saturating_sub(n)),
+            b'G' => region.move_to(row, n - 1),
+            b'H' | b'f' => {
+                let column = params.get(1, 1) as usize;
+                region.move_to(n - 1, column - 1);
+            },
+            b'J' => {
+                let cells = region.cells();
+                let range = match params.get(0, 0) {
+                    0 => position..cells.end,
+                    1 => cells.start..position + 1,
+                    _ => cells,
+                };
+                self.erase(region, range);
+            },
+            b'K' => {
+                let line = position - column;
+                let range = match params.get(0, 0) {
+                    0 => position..line + region.cols,
+                    1 => line..position + 1,
+                    _ => line..line + region.cols,
+                };
+                self.erase(region, range);
+            },
+            b'm' => region.select_graphic_rendition(params),
+            b's' => region.saved_position = region.position,
+            b'u' => region.position = region.saved_position,
+            _ => {},
+        }
+    }
+
+    /// Blanks the screen cells in `range` with the region's colors.
+    fn erase(&mut self, region: &Region, range: Range<usize>) {
+        let blank = region.blank();
+        for cell in &mut self.buffer[range.clone()] {
+            *cell = blank;
+        }
+        self.mark_dirty(range);
+    }
+
+    fn scroll(&mut self, region: &mut Region) {
+        // only the main region has a scrollback
+        if region.top == self.regions[MAIN_REGION].top {
+            self.save_top_row(region);
+        }
+
+        // moves every row up in one go; the old top row ends up at the
+        // bottom, where it is blanked
+        let cells = region.cells();
+        self.buffer[cells.clone()].rotate_left(region.cols);
+        self.erase(region, cells.end - region.cols..cells.end);
+
+        region.position = (region.rows - 1) * region.cols;
+        self.mark_dirty(cells);
+    }
+}
+
+impl Default for Console {
+    fn default() -> Console {
+        Console::new()
+    }
+}
+
+/// Writes to the main region.
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        self.region(MAIN_REGION).write_str(s)
+    }
+}
+
+/// Writes to one region of a console, see `Console::region`.
+pub struct RegionWriter<'a> {
+    console: &'a mut Console,
+    id: RegionId,
+}
+
+impl<'a> RegionWriter<'a> {
+    /// Moves the cursor to `row` and `column` of the region, counting from 0.
+    pub fn move_to(&mut self, row: usize, column: usize) {
+        self.console.regions[self.id].move_to(row, column);
+    }
+
+    /// The cursor's row and column within the region.
+    pub fn position(&self) -> (usize, usize) {
+        let position = self.console.regions[self.id].position;
+        (position / self.console.cols, position % self.console.cols)
+    }
+
+    /// Blanks the region and puts the cursor in its top left corner.
+    pub fn clear(&mut self) {
+        self.console.update_region(self.id, |console, region| {
+            console.erase(region, region.cells());
+            region.position = 0;
+        });
+    }
+
+    pub fn set_foreground_color(&mut self, color: Color) {
+        self.console.regions[self.id].foreground_color = color;
+    }
+
+    pub fn set_background_color(&mut self, color: Color) {
+        self.console.regions[self.id].background_color = color;
+    }
+
+    pub fn invert(&mut self) {
+        let region = &mut self.console.regions[self.id];
+        core::mem::swap(&mut region.foreground_color, &mut region.background_color);
+    }
+
+    /// Puts CP437 glyphs on the screen as they are, without interpreting
+    /// control characters or escape sequences.
+    pub fn write_cp437(&mut self, glyphs: &[u8]) {
+        if self.id == MAIN_REGION {
+            self.console.set_view_offset(0);
+        }
+
+        self.console.update_region(self.id, |console, region| {
+            for &glyph in glyphs {
+                console.put(region, glyph);
+            }
+        });
+    }
+}
+
+/// Writing to the main region returns it to the live view. Text is UTF-8
+/// and shown in code page 437, see `cp437::from_char`.
+impl<'a> Write for RegionWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        if self.id == MAIN_REGION {
+            self.console.set_view_offset(0);
+        }
+
+        self.console.update_region(self.id, |console, region| {
+            for c in s.chars() {
+                console.put_char(region, c);
+            }
+        });
+
+        Ok(())
+    }
+}
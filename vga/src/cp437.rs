@@ -0,0 +1,89 @@
+//! Code page 437, the character set built into the VGA's text mode font.
+//!
+//! See https://en.wikipedia.org/wiki/Code_page_437
+
+/// The glyph drawn for characters CP437 has no glyph for: a small square.
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// The glyphs at 0x01..0x1F, where ASCII has its control codes.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The glyphs at 0x80..0xFF.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Look-alikes that share a glyph with one of the characters above.
+const ALIASES: [(char, u8); 6] = [
+    ('\u{3b2}', 0xE1),  // Greek small beta, for sharp s
+    ('\u{3bc}', 0xE6),  // Greek small mu, for the micro sign
+    ('\u{2126}', 0xEA), // ohm sign, for capital omega
+    ('\u{2211}', 0xE4), // n-ary summation, for capital sigma
+    ('\u{2208}', 0xEE), // element of, for small epsilon
+    ('\u{2302}', 0x7F), // house
+];
+
+/// The glyph for `c`, if the font has one.
+pub fn from_char(c: char) -> Option<u8> {
+    if c == ' ' || c.is_ascii_graphic() {
+        return Some(c as u8);
+    }
+
+    if let Some(index) = LOW.iter().position(|&glyph| glyph == c) {
+        return Some(index as u8 + 0x01);
+    }
+    if let Some(index) = HIGH.iter().position(|&glyph| glyph == c) {
+        return Some(index as u8 + 0x80);
+    }
+
+    ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, glyph)| glyph)
+}
+
+/// Like `from_char`, but falls back to the `REPLACEMENT` glyph.
+pub fn encode(c: char) -> u8 {
+    from_char(c).unwrap_or(REPLACEMENT)
+}
+
+/// The character that `glyph` depicts.
+pub fn to_char(glyph: u8) -> char {
+    match glyph {
+        0x00 => ' ',
+        0x01..=0x1f => LOW[glyph as usize - 0x01],
+        0x7f => '⌂',
+        0x80..=0xff => HIGH[glyph as usize - 0x80],
+        _ => glyph as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for glyph in 0x01..=0xffu8 {
+            assert_eq!(from_char(to_char(glyph)), Some(glyph));
+        }
+    }
+
+    #[test]
+    fn mapping() {
+        assert_eq!(from_char('a'), Some(b'a'));
+        assert_eq!(from_char('é'), Some(0x82));
+        assert_eq!(from_char('─'), Some(0xC4));
+        assert_eq!(from_char('→'), Some(0x1A));
+        assert_eq!(from_char('\u{3b2}'), Some(0xE1));
+        assert_eq!(from_char('€'), None);
+        assert_eq!(encode('€'), REPLACEMENT);
+    }
+}
@@ -0,0 +1,289 @@
+//! An 8x8 bitmap font for code page 437.
+//!
+//! ASCII comes from the public domain X11 5x7 font, the line drawing, block
+//! and shading glyphs are drawn to fit the cell so that they join up. The
+//! accented letters are the ASCII ones with their accents added, and the
+//! other symbols are drawn in the same style. Each glyph is eight rows, top
+//! first, with the most significant bit as the leftmost pixel, the layout
+//! the VGA expects in its font plane.
+
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = 8;
+
+/// Bytes the VGA reserves for each glyph in plane 2, whatever its height.
+pub const GLYPH_STRIDE: usize = 32;
+/// Size of a full 256 glyph font in plane 2.
+pub const FONT_PLANE_SIZE: usize = 256 * GLYPH_STRIDE;
+
+pub static FONT_8X8: [[u8; GLYPH_HEIGHT]; 256] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x00
+    [0x7E, 0x81, 0xA5, 0x81, 0xBD, 0x99, 0x81, 0x7E], // 0x01 '☺'
+    [0x7E, 0xFF, 0xDB, 0xFF, 0xC3, 0xE7, 0xFF, 0x7E], // 0x02 '☻'
+    [0x6C, 0xFE, 0xFE, 0xFE, 0x7C, 0x38, 0x10, 0x00], // 0x03 '♥'
+    [0x10, 0x38, 0x7C, 0xFE, 0x7C, 0x38, 0x10, 0x00], // 0x04 '♦'
+    [0x38, 0x7C, 0x38, 0xFE, 0xFE, 0xAA, 0x10, 0x38], // 0x05 '♣'
+    [0x10, 0x10, 0x38, 0x7C, 0xFE, 0x7C, 0x10, 0x38], // 0x06 '♠'
+    [0x00, 0x00, 0x18, 0x3C, 0x3C, 0x18, 0x00, 0x00], // 0x07 '•'
+    [0xFF, 0xFF, 0xE7, 0xC3, 0xC3, 0xE7, 0xFF, 0xFF], // 0x08 '◘'
+    [0x00, 0x3C, 0x66, 0x42, 0x42, 0x66, 0x3C, 0x00], // 0x09 '○'
+    [0xFF, 0xC3, 0x99, 0xBD, 0xBD, 0x99, 0xC3, 0xFF], // 0x0A '◙'
+    [0x0F, 0x07, 0x0F, 0x79, 0xCC, 0xCC, 0xCC, 0x78], // 0x0B '♂'
+    [0x3C, 0x66, 0x66, 0x66, 0x3C, 0x18, 0x7E, 0x18], // 0x0C '♀'
+    [0x3F, 0x33, 0x3F, 0x30, 0x30, 0x70, 0xF0, 0xE0], // 0x0D '♪'
+    [0x7F, 0x63, 0x7F, 0x63, 0x63, 0x67, 0xE6, 0xC0], // 0x0E '♫'
+    [0x18, 0xDB, 0x3C, 0xE7, 0xE7, 0x3C, 0xDB, 0x18], // 0x0F '☼'
+    [0x80, 0xE0, 0xF8, 0xFE, 0xF8, 0xE0, 0x80, 0x00], // 0x10 '►'
+    [0x02, 0x0E, 0x3E, 0xFE, 0x3E, 0x0E, 0x02, 0x00], // 0x11 '◄'
+    [0x10, 0x38, 0x54, 0x10, 0x54, 0x38, 0x10, 0x00], // 0x12 '↕'
+    [0x48, 0x48, 0x48, 0x48, 0x48, 0x00, 0x48, 0x00], // 0x13 '‼'
+    [0x7C, 0xA8, 0xA8, 0x68, 0x28, 0x28, 0x28, 0x00], // 0x14 '¶'
+    [0x38, 0x40, 0x30, 0x48, 0x30, 0x08, 0x70, 0x00], // 0x15 '§'
+    [0x00, 0x00, 0x00, 0x00, 0x7E, 0x7E, 0x7E, 0x00], // 0x16 '▬'
+    [0x10, 0x38, 0x54, 0x10, 0x54, 0x38, 0x10, 0x7C], // 0x17 '↨'
+    [0x10, 0x38, 0x54, 0x10, 0x10, 0x10, 0x10, 0x00], // 0x18 '↑'
+    [0x10, 0x10, 0x10, 0x10, 0x54, 0x38, 0x10, 0x00], // 0x19 '↓'
+    [0x00, 0x08, 0x04, 0x7E, 0x04, 0x08, 0x00, 0x00], // 0x1A '→'
+    [0x00, 0x20, 0x40, 0xFC, 0x40, 0x20, 0x00, 0x00], // 0x1B '←'
+    [0x00, 0x00, 0x40, 0x40, 0x40, 0x7C, 0x00, 0x00], // 0x1C '∟'
+    [0x00, 0x28, 0x44, 0xFE, 0x44, 0x28, 0x00, 0x00], // 0x1D '↔'
+    [0x00, 0x10, 0x38, 0x7C, 0xFE, 0x00, 0x00, 0x00], // 0x1E '▲'
+    [0x00, 0xFE, 0x7C, 0x38, 0x10, 0x00, 0x00, 0x00], // 0x1F '▼'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x20 ' '
+    [0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00, 0x00], // 0x21 '!'
+    [0x28, 0x28, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x22 '"'
+    [0x00, 0x28, 0x7C, 0x28, 0x7C, 0x28, 0x00, 0x00], // 0x23 '#'
+    [0x00, 0x38, 0x50, 0x38, 0x14, 0x38, 0x00, 0x00], // 0x24 '$'
+    [0x40, 0x48, 0x10, 0x20, 0x48, 0x08, 0x00, 0x00], // 0x25 '%'
+    [0x00, 0x20, 0x50, 0x20, 0x50, 0x28, 0x00, 0x00], // 0x26 '&'
+    [0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x27 '\''
+    [0x10, 0x20, 0x20, 0x20, 0x20, 0x10, 0x00, 0x00], // 0x28 '('
+    [0x20, 0x10, 0x10, 0x10, 0x10, 0x20, 0x00, 0x00], // 0x29 ')'
+    [0x00, 0x28, 0x10, 0x38, 0x10, 0x28, 0x00, 0x00], // 0x2A '*'
+    [0x00, 0x10, 0x10, 0x7C, 0x10, 0x10, 0x00, 0x00], // 0x2B '+'
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x10, 0x20, 0x00], // 0x2C ','
+    [0x00, 0x00, 0x00, 0x78, 0x00, 0x00, 0x00, 0x00], // 0x2D '-'
+    [0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00, 0x00], // 0x2E '.'
+    [0x00, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00, 0x00], // 0x2F '/'
+    [0x10, 0x28, 0x28, 0x28, 0x28, 0x10, 0x00, 0x00], // 0x30 '0'
+    [0x10, 0x30, 0x10, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x31 '1'
+    [0x30, 0x48, 0x08, 0x10, 0x20, 0x78, 0x00, 0x00], // 0x32 '2'
+    [0x78, 0x08, 0x30, 0x08, 0x48, 0x30, 0x00, 0x00], // 0x33 '3'
+    [0x10, 0x30, 0x50, 0x78, 0x10, 0x10, 0x00, 0x00], // 0x34 '4'
+    [0x78, 0x40, 0x70, 0x08, 0x48, 0x30, 0x00, 0x00], // 0x35 '5'
+    [0x30, 0x40, 0x70, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x36 '6'
+    [0x78, 0x08, 0x10, 0x10, 0x20, 0x20, 0x00, 0x00], // 0x37 '7'
+    [0x30, 0x48, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x38 '8'
+    [0x30, 0x48, 0x48, 0x38, 0x08, 0x30, 0x00, 0x00], // 0x39 '9'
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x00, 0x00], // 0x3A ':'
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x20, 0x40, 0x00], // 0x3B ';'
+    [0x00, 0x08, 0x10, 0x20, 0x10, 0x08, 0x00, 0x00], // 0x3C '<'
+    [0x00, 0x00, 0x78, 0x00, 0x78, 0x00, 0x00, 0x00], // 0x3D '='
+    [0x00, 0x20, 0x10, 0x08, 0x10, 0x20, 0x00, 0x00], // 0x3E '>'
+    [0x10, 0x28, 0x08, 0x10, 0x00, 0x10, 0x00, 0x00], // 0x3F '?'
+    [0x30, 0x48, 0x58, 0x58, 0x40, 0x30, 0x00, 0x00], // 0x40 '@'
+    [0x30, 0x48, 0x48, 0x78, 0x48, 0x48, 0x00, 0x00], // 0x41 'A'
+    [0x70, 0x48, 0x70, 0x48, 0x48, 0x70, 0x00, 0x00], // 0x42 'B'
+    [0x30, 0x48, 0x40, 0x40, 0x48, 0x30, 0x00, 0x00], // 0x43 'C'
+    [0x70, 0x48, 0x48, 0x48, 0x48, 0x70, 0x00, 0x00], // 0x44 'D'
+    [0x78, 0x40, 0x70, 0x40, 0x40, 0x78, 0x00, 0x00], // 0x45 'E'
+    [0x78, 0x40, 0x70, 0x40, 0x40, 0x40, 0x00, 0x00], // 0x46 'F'
+    [0x30, 0x48, 0x40, 0x58, 0x48, 0x38, 0x00, 0x00], // 0x47 'G'
+    [0x48, 0x48, 0x78, 0x48, 0x48, 0x48, 0x00, 0x00], // 0x48 'H'
+    [0x38, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x49 'I'
+    [0x08, 0x08, 0x08, 0x08, 0x48, 0x30, 0x00, 0x00], // 0x4A 'J'
+    [0x48, 0x50, 0x60, 0x60, 0x50, 0x48, 0x00, 0x00], // 0x4B 'K'
+    [0x40, 0x40, 0x40, 0x40, 0x40, 0x78, 0x00, 0x00], // 0x4C 'L'
+    [0x48, 0x78, 0x78, 0x48, 0x48, 0x48, 0x00, 0x00], // 0x4D 'M'
+    [0x48, 0x68, 0x68, 0x58, 0x58, 0x48, 0x00, 0x00], // 0x4E 'N'
+    [0x30, 0x48, 0x48, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x4F 'O'
+    [0x70, 0x48, 0x48, 0x70, 0x40, 0x40, 0x00, 0x00], // 0x50 'P'
+    [0x30, 0x48, 0x48, 0x48, 0x68, 0x30, 0x08, 0x00], // 0x51 'Q'
+    [0x70, 0x48, 0x48, 0x70, 0x50, 0x48, 0x00, 0x00], // 0x52 'R'
+    [0x30, 0x48, 0x20, 0x10, 0x48, 0x30, 0x00, 0x00], // 0x53 'S'
+    [0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // 0x54 'T'
+    [0x48, 0x48, 0x48, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x55 'U'
+    [0x48, 0x48, 0x48, 0x48, 0x30, 0x30, 0x00, 0x00], // 0x56 'V'
+    [0x48, 0x48, 0x48, 0x78, 0x78, 0x48, 0x00, 0x00], // 0x57 'W'
+    [0x48, 0x48, 0x30, 0x30, 0x48, 0x48, 0x00, 0x00], // 0x58 'X'
+    [0x28, 0x28, 0x28, 0x10, 0x10, 0x10, 0x00, 0x00], // 0x59 'Y'
+    [0x78, 0x08, 0x10, 0x20, 0x40, 0x78, 0x00, 0x00], // 0x5A 'Z'
+    [0x38, 0x20, 0x20, 0x20, 0x20, 0x38, 0x00, 0x00], // 0x5B '['
+    [0x00, 0x40, 0x20, 0x10, 0x08, 0x00, 0x00, 0x00], // 0x5C '\\'
+    [0x38, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00, 0x00], // 0x5D ']'
+    [0x10, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x5E '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x00, 0x00], // 0x5F '_'
+    [0x20, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x60 '`'
+    [0x00, 0x00, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0x61 'a'
+    [0x40, 0x40, 0x70, 0x48, 0x48, 0x70, 0x00, 0x00], // 0x62 'b'
+    [0x00, 0x00, 0x30, 0x40, 0x40, 0x30, 0x00, 0x00], // 0x63 'c'
+    [0x08, 0x08, 0x38, 0x48, 0x48, 0x38, 0x00, 0x00], // 0x64 'd'
+    [0x00, 0x00, 0x30, 0x58, 0x60, 0x30, 0x00, 0x00], // 0x65 'e'
+    [0x10, 0x28, 0x20, 0x70, 0x20, 0x20, 0x00, 0x00], // 0x66 'f'
+    [0x00, 0x00, 0x38, 0x48, 0x30, 0x40, 0x38, 0x00], // 0x67 'g'
+    [0x40, 0x40, 0x70, 0x48, 0x48, 0x48, 0x00, 0x00], // 0x68 'h'
+    [0x10, 0x00, 0x30, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x69 'i'
+    [0x08, 0x00, 0x08, 0x08, 0x08, 0x28, 0x10, 0x00], // 0x6A 'j'
+    [0x40, 0x40, 0x50, 0x60, 0x50, 0x48, 0x00, 0x00], // 0x6B 'k'
+    [0x30, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x6C 'l'
+    [0x00, 0x00, 0x50, 0x78, 0x48, 0x48, 0x00, 0x00], // 0x6D 'm'
+    [0x00, 0x00, 0x70, 0x48, 0x48, 0x48, 0x00, 0x00], // 0x6E 'n'
+    [0x00, 0x00, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x6F 'o'
+    [0x00, 0x00, 0x70, 0x48, 0x48, 0x70, 0x40, 0x00], // 0x70 'p'
+    [0x00, 0x00, 0x38, 0x48, 0x48, 0x38, 0x08, 0x00], // 0x71 'q'
+    [0x00, 0x00, 0x70, 0x48, 0x40, 0x40, 0x00, 0x00], // 0x72 'r'
+    [0x00, 0x00, 0x38, 0x60, 0x18, 0x70, 0x00, 0x00], // 0x73 's'
+    [0x20, 0x20, 0x70, 0x20, 0x20, 0x18, 0x00, 0x00], // 0x74 't'
+    [0x00, 0x00, 0x48, 0x48, 0x48, 0x38, 0x00, 0x00], // 0x75 'u'
+    [0x00, 0x00, 0x28, 0x28, 0x28, 0x10, 0x00, 0x00], // 0x76 'v'
+    [0x00, 0x00, 0x48, 0x48, 0x78, 0x78, 0x00, 0x00], // 0x77 'w'
+    [0x00, 0x00, 0x48, 0x30, 0x30, 0x48, 0x00, 0x00], // 0x78 'x'
+    [0x00, 0x00, 0x48, 0x48, 0x28, 0x10, 0x20, 0x00], // 0x79 'y'
+    [0x00, 0x00, 0x78, 0x10, 0x20, 0x78, 0x00, 0x00], // 0x7A 'z'
+    [0x08, 0x10, 0x30, 0x10, 0x10, 0x08, 0x00, 0x00], // 0x7B '{'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // 0x7C '|'
+    [0x20, 0x10, 0x18, 0x10, 0x10, 0x20, 0x00, 0x00], // 0x7D '}'
+    [0x28, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0x7E '~'
+    [0x00, 0x10, 0x28, 0x44, 0x44, 0x7C, 0x00, 0x00], // 0x7F '⌂'
+    [0x30, 0x48, 0x40, 0x40, 0x48, 0x30, 0x10, 0x20], // 0x80 'Ç'
+    [0x48, 0x00, 0x48, 0x48, 0x48, 0x38, 0x00, 0x00], // 0x81 'ü'
+    [0x10, 0x20, 0x30, 0x58, 0x60, 0x30, 0x00, 0x00], // 0x82 'é'
+    [0x30, 0x48, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0x83 'â'
+    [0x48, 0x00, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0x84 'ä'
+    [0x40, 0x20, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0x85 'à'
+    [0x30, 0x30, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0x86 'å'
+    [0x00, 0x00, 0x30, 0x40, 0x40, 0x30, 0x10, 0x20], // 0x87 'ç'
+    [0x30, 0x48, 0x30, 0x58, 0x60, 0x30, 0x00, 0x00], // 0x88 'ê'
+    [0x48, 0x00, 0x30, 0x58, 0x60, 0x30, 0x00, 0x00], // 0x89 'ë'
+    [0x40, 0x20, 0x30, 0x58, 0x60, 0x30, 0x00, 0x00], // 0x8A 'è'
+    [0x28, 0x00, 0x30, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x8B 'ï'
+    [0x10, 0x28, 0x30, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x8C 'î'
+    [0x20, 0x10, 0x30, 0x10, 0x10, 0x38, 0x00, 0x00], // 0x8D 'ì'
+    [0x48, 0x30, 0x48, 0x78, 0x48, 0x48, 0x00, 0x00], // 0x8E 'Ä'
+    [0x30, 0x30, 0x48, 0x78, 0x48, 0x48, 0x00, 0x00], // 0x8F 'Å'
+    [0x10, 0x78, 0x40, 0x70, 0x40, 0x78, 0x00, 0x00], // 0x90 'É'
+    [0x00, 0x00, 0x6C, 0x3C, 0x50, 0x6C, 0x00, 0x00], // 0x91 'æ'
+    [0x3E, 0x48, 0x48, 0x7C, 0x48, 0x4F, 0x00, 0x00], // 0x92 'Æ'
+    [0x30, 0x48, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x93 'ô'
+    [0x48, 0x00, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x94 'ö'
+    [0x40, 0x20, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x95 'ò'
+    [0x30, 0x48, 0x48, 0x48, 0x48, 0x38, 0x00, 0x00], // 0x96 'û'
+    [0x40, 0x20, 0x48, 0x48, 0x48, 0x38, 0x00, 0x00], // 0x97 'ù'
+    [0x48, 0x00, 0x48, 0x48, 0x28, 0x10, 0x20, 0x00], // 0x98 'ÿ'
+    [0x48, 0x30, 0x48, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x99 'Ö'
+    [0x48, 0x48, 0x48, 0x48, 0x48, 0x30, 0x00, 0x00], // 0x9A 'Ü'
+    [0x10, 0x38, 0x50, 0x50, 0x38, 0x10, 0x00, 0x00], // 0x9B '¢'
+    [0x00, 0x18, 0x20, 0x70, 0x20, 0x78, 0x00, 0x00], // 0x9C '£'
+    [0x44, 0x28, 0x7C, 0x10, 0x7C, 0x10, 0x00, 0x00], // 0x9D '¥'
+    [0xE0, 0x90, 0xE8, 0x9C, 0x88, 0x8C, 0x00, 0x00], // 0x9E '₧'
+    [0x0C, 0x10, 0x38, 0x10, 0x10, 0x10, 0x60, 0x00], // 0x9F 'ƒ'
+    [0x10, 0x20, 0x38, 0x48, 0x58, 0x28, 0x00, 0x00], // 0xA0 'á'
+    [0x08, 0x10, 0x30, 0x10, 0x10, 0x38, 0x00, 0x00], // 0xA1 'í'
+    [0x10, 0x20, 0x30, 0x48, 0x48, 0x30, 0x00, 0x00], // 0xA2 'ó'
+    [0x10, 0x20, 0x48, 0x48, 0x48, 0x38, 0x00, 0x00], // 0xA3 'ú'
+    [0x28, 0x50, 0x70, 0x48, 0x48, 0x48, 0x00, 0x00], // 0xA4 'ñ'
+    [0x28, 0x50, 0x48, 0x68, 0x58, 0x48, 0x00, 0x00], // 0xA5 'Ñ'
+    [0x30, 0x50, 0x38, 0x00, 0x78, 0x00, 0x00, 0x00], // 0xA6 'ª'
+    [0x20, 0x50, 0x20, 0x00, 0x70, 0x00, 0x00, 0x00], // 0xA7 'º'
+    [0x10, 0x00, 0x10, 0x20, 0x48, 0x30, 0x00, 0x00], // 0xA8 '¿'
+    [0x00, 0x00, 0x78, 0x40, 0x00, 0x00, 0x00, 0x00], // 0xA9 '⌐'
+    [0x00, 0x00, 0x78, 0x08, 0x00, 0x00, 0x00, 0x00], // 0xAA '¬'
+    [0x80, 0x88, 0x90, 0x2C, 0x42, 0x84, 0x0E, 0x00], // 0xAB '½'
+    [0x80, 0x88, 0x90, 0x24, 0x4C, 0x94, 0x1E, 0x04], // 0xAC '¼'
+    [0x10, 0x00, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00], // 0xAD '¡'
+    [0x00, 0x24, 0x48, 0x90, 0x48, 0x24, 0x00, 0x00], // 0xAE '«'
+    [0x00, 0x90, 0x48, 0x24, 0x48, 0x90, 0x00, 0x00], // 0xAF '»'
+    [0x88, 0x22, 0x88, 0x22, 0x88, 0x22, 0x88, 0x22], // 0xB0 '░'
+    [0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55], // 0xB1 '▒'
+    [0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77], // 0xB2 '▓'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10], // 0xB3 '│'
+    [0x10, 0x10, 0x10, 0xF0, 0x10, 0x10, 0x10, 0x10], // 0xB4 '┤'
+    [0x10, 0x10, 0xF8, 0x10, 0xF8, 0x10, 0x10, 0x10], // 0xB5 '╡'
+    [0x28, 0x28, 0x28, 0xF8, 0x28, 0x28, 0x28, 0x28], // 0xB6 '╢'
+    [0x00, 0x00, 0x28, 0xF8, 0x28, 0x28, 0x28, 0x28], // 0xB7 '╖'
+    [0x00, 0x00, 0xF8, 0x10, 0xF8, 0x10, 0x10, 0x10], // 0xB8 '╕'
+    [0x28, 0x28, 0xF8, 0x28, 0xF8, 0x28, 0x28, 0x28], // 0xB9 '╣'
+    [0x28, 0x28, 0x28, 0x28, 0x28, 0x28, 0x28, 0x28], // 0xBA '║'
+    [0x00, 0x00, 0xF8, 0x28, 0xF8, 0x28, 0x28, 0x28], // 0xBB '╗'
+    [0x28, 0x28, 0xF8, 0x28, 0xF8, 0x00, 0x00, 0x00], // 0xBC '╝'
+    [0x28, 0x28, 0x28, 0xF8, 0x28, 0x00, 0x00, 0x00], // 0xBD '╜'
+    [0x10, 0x10, 0xF8, 0x10, 0xF8, 0x00, 0x00, 0x00], // 0xBE '╛'
+    [0x00, 0x00, 0x00, 0xF0, 0x10, 0x10, 0x10, 0x10], // 0xBF '┐'
+    [0x10, 0x10, 0x10, 0x1F, 0x00, 0x00, 0x00, 0x00], // 0xC0 '└'
+    [0x10, 0x10, 0x10, 0xFF, 0x00, 0x00, 0x00, 0x00], // 0xC1 '┴'
+    [0x00, 0x00, 0x00, 0xFF, 0x10, 0x10, 0x10, 0x10], // 0xC2 '┬'
+    [0x10, 0x10, 0x10, 0x1F, 0x10, 0x10, 0x10, 0x10], // 0xC3 '├'
+    [0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00], // 0xC4 '─'
+    [0x10, 0x10, 0x10, 0xFF, 0x10, 0x10, 0x10, 0x10], // 0xC5 '┼'
+    [0x10, 0x10, 0x3F, 0x10, 0x3F, 0x10, 0x10, 0x10], // 0xC6 '╞'
+    [0x28, 0x28, 0x28, 0x3F, 0x28, 0x28, 0x28, 0x28], // 0xC7 '╟'
+    [0x28, 0x28, 0x3F, 0x28, 0x3F, 0x00, 0x00, 0x00], // 0xC8 '╚'
+    [0x00, 0x00, 0x3F, 0x28, 0x3F, 0x28, 0x28, 0x28], // 0xC9 '╔'
+    [0x28, 0x28, 0xFF, 0x28, 0xFF, 0x00, 0x00, 0x00], // 0xCA '╩'
+    [0x00, 0x00, 0xFF, 0x28, 0xFF, 0x28, 0x28, 0x28], // 0xCB '╦'
+    [0x28, 0x28, 0x3F, 0x28, 0x3F, 0x28, 0x28, 0x28], // 0xCC '╠'
+    [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0x00], // 0xCD '═'
+    [0x28, 0x28, 0xFF, 0x28, 0xFF, 0x28, 0x28, 0x28], // 0xCE '╬'
+    [0x10, 0x10, 0xFF, 0x10, 0xFF, 0x00, 0x00, 0x00], // 0xCF '╧'
+    [0x28, 0x28, 0x28, 0xFF, 0x28, 0x00, 0x00, 0x00], // 0xD0 '╨'
+    [0x00, 0x00, 0xFF, 0x10, 0xFF, 0x10, 0x10, 0x10], // 0xD1 '╤'
+    [0x00, 0x00, 0x28, 0xFF, 0x28, 0x28, 0x28, 0x28], // 0xD2 '╥'
+    [0x28, 0x28, 0x28, 0x3F, 0x28, 0x00, 0x00, 0x00], // 0xD3 '╙'
+    [0x10, 0x10, 0x3F, 0x10, 0x3F, 0x00, 0x00, 0x00], // 0xD4 '╘'
+    [0x00, 0x00, 0x3F, 0x10, 0x3F, 0x10, 0x10, 0x10], // 0xD5 '╒'
+    [0x00, 0x00, 0x28, 0x3F, 0x28, 0x28, 0x28, 0x28], // 0xD6 '╓'
+    [0x28, 0x28, 0x28, 0xFF, 0x28, 0x28, 0x28, 0x28], // 0xD7 '╫'
+    [0x10, 0x10, 0xFF, 0x10, 0xFF, 0x10, 0x10, 0x10], // 0xD8 '╪'
+    [0x10, 0x10, 0x10, 0xF0, 0x00, 0x00, 0x00, 0x00], // 0xD9 '┘'
+    [0x00, 0x00, 0x00, 0x1F, 0x10, 0x10, 0x10, 0x10], // 0xDA '┌'
+    [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], // 0xDB '█'
+    [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF], // 0xDC '▄'
+    [0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0], // 0xDD '▌'
+    [0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F], // 0xDE '▐'
+    [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00], // 0xDF '▀'
+    [0x00, 0x00, 0x34, 0x48, 0x48, 0x34, 0x00, 0x00], // 0xE0 'α'
+    [0x30, 0x48, 0x70, 0x48, 0x48, 0x70, 0x40, 0x00], // 0xE1 'ß'
+    [0x78, 0x48, 0x40, 0x40, 0x40, 0x40, 0x00, 0x00], // 0xE2 'Γ'
+    [0x00, 0x00, 0x7C, 0x28, 0x28, 0x28, 0x00, 0x00], // 0xE3 'π'
+    [0x78, 0x20, 0x10, 0x20, 0x40, 0x78, 0x00, 0x00], // 0xE4 'Σ'
+    [0x00, 0x00, 0x3C, 0x48, 0x48, 0x30, 0x00, 0x00], // 0xE5 'σ'
+    [0x00, 0x00, 0x48, 0x48, 0x48, 0x74, 0x40, 0x00], // 0xE6 'µ'
+    [0x00, 0x00, 0x78, 0x20, 0x20, 0x10, 0x00, 0x00], // 0xE7 'τ'
+    [0x10, 0x7C, 0x92, 0x92, 0x7C, 0x10, 0x00, 0x00], // 0xE8 'Φ'
+    [0x30, 0x48, 0x78, 0x48, 0x48, 0x30, 0x00, 0x00], // 0xE9 'Θ'
+    [0x30, 0x48, 0x48, 0x48, 0x30, 0x6C, 0x00, 0x00], // 0xEA 'Ω'
+    [0x18, 0x20, 0x10, 0x28, 0x48, 0x30, 0x00, 0x00], // 0xEB 'δ'
+    [0x00, 0x00, 0x6C, 0x92, 0x6C, 0x00, 0x00, 0x00], // 0xEC '∞'
+    [0x00, 0x10, 0x38, 0x54, 0x54, 0x38, 0x10, 0x00], // 0xED 'φ'
+    [0x00, 0x00, 0x38, 0x60, 0x40, 0x38, 0x00, 0x00], // 0xEE 'ε'
+    [0x00, 0x30, 0x48, 0x48, 0x48, 0x48, 0x00, 0x00], // 0xEF '∩'
+    [0x00, 0x78, 0x00, 0x78, 0x00, 0x78, 0x00, 0x00], // 0xF0 '≡'
+    [0x00, 0x10, 0x7C, 0x10, 0x00, 0x7C, 0x00, 0x00], // 0xF1 '±'
+    [0x40, 0x20, 0x10, 0x20, 0x40, 0x00, 0x78, 0x00], // 0xF2 '≥'
+    [0x10, 0x20, 0x40, 0x20, 0x10, 0x00, 0x78, 0x00], // 0xF3 '≤'
+    [0x0C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10], // 0xF4 '⌠'
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x60], // 0xF5 '⌡'
+    [0x00, 0x10, 0x00, 0x7C, 0x00, 0x10, 0x00, 0x00], // 0xF6 '÷'
+    [0x00, 0x28, 0x50, 0x00, 0x28, 0x50, 0x00, 0x00], // 0xF7 '≈'
+    [0x20, 0x50, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00], // 0xF8 '°'
+    [0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00], // 0xF9 '∙'
+    [0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00], // 0xFA '·'
+    [0x0E, 0x08, 0x08, 0x88, 0x48, 0x28, 0x18, 0x00], // 0xFB '√'
+    [0x70, 0x48, 0x48, 0x00, 0x00, 0x00, 0x00, 0x00], // 0xFC 'ⁿ'
+    [0x60, 0x10, 0x20, 0x70, 0x00, 0x00, 0x00, 0x00], // 0xFD '²'
+    [0x00, 0x00, 0x3C, 0x3C, 0x3C, 0x3C, 0x00, 0x00], // 0xFE '■'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // 0xFF
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_glyph_is_drawn() {
+        for (code, glyph) in FONT_8X8.iter().enumerate() {
+            // NUL, space and the no-break space
+            let blank = code == 0x00 || code == 0x20 || code == 0xFF;
+            assert_eq!(glyph.iter().all(|&row| row == 0), blank, "glyph {:#x}", code);
+        }
+    }
+}
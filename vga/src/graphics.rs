@@ -0,0 +1,263 @@
+//! Drawing into an 8 bit per pixel framebuffer, like the one of mode 13h
+//! at 0xA0000 or a linear framebuffer set up by the bootloader.
+
+use core::cmp;
+use core::ptr;
+
+use cp437;
+use font::{FONT_8X8, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// Width of mode 13h in pixels.
+pub const WIDTH: usize = 320;
+/// Height of mode 13h in pixels.
+pub const HEIGHT: usize = 200;
+
+/// A framebuffer with one palette index per pixel.
+pub struct Graphics<T: AsMut<[u8]>> {
+    framebuffer: T,
+    width: usize,
+    height: usize,
+    /// Bytes from the start of one row to the start of the next.
+    stride: usize,
+}
+
+impl<T: AsMut<[u8]>> Graphics<T> {
+    /// Draws into the mode 13h framebuffer.
+    pub fn new(framebuffer: T) -> Graphics<T> {
+        Graphics::with_size(framebuffer, WIDTH, HEIGHT, WIDTH)
+    }
+
+    pub fn with_size(mut framebuffer: T, width: usize, height: usize, stride: usize) -> Graphics<T> {
+        assert!(stride >= width);
+        assert!(framebuffer.as_mut().len() >= stride * height);
+
+        Graphics {
+            framebuffer,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn store(&mut self, offset: usize, color: u8) {
+        // like text mode, this is memory the compiler can't see being read
+        unsafe {
+            ptr::write_volatile(&mut self.framebuffer.as_mut()[offset], color);
+        }
+    }
+
+    pub fn clear(&mut self, color: u8) {
+        let (width, height) = (self.width, self.height);
+        self.fill_rect(0, 0, width, height, color);
+    }
+
+    /// Sets a pixel; coordinates outside the screen are ignored, so shapes
+    /// may hang over the edges.
+    pub fn pixel(&mut self, x: isize, y: isize, color: u8) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+
+        let offset = y as usize * self.stride + x as usize;
+        self.store(offset, color);
+    }
+
+    pub fn get_pixel(&mut self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let offset = y * self.stride + x;
+        Some(self.framebuffer.as_mut()[offset])
+    }
+
+    /// Draws a line from `(x0, y0)` to `(x1, y1)`, both ends included.
+    pub fn line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: u8) {
+        // Bresenham, for all octants
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the outline of a rectangle.
+    pub fn rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let (left, top) = (x as isize, y as isize);
+        let right = left + width as isize - 1;
+        let bottom = top + height as isize - 1;
+        self.line(left, top, right, top, color);
+        self.line(left, bottom, right, bottom, color);
+        self.line(left, top, left, bottom, color);
+        self.line(right, top, right, bottom, color);
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        let right = cmp::min(x.saturating_add(width), self.width);
+        let bottom = cmp::min(y.saturating_add(height), self.height);
+
+        for row in y..bottom {
+            for column in x..right {
+                let offset = row * self.stride + column;
+                self.store(offset, color);
+            }
+        }
+    }
+
+    /// Copies a `width` pixels wide image, stored row by row in `pixels`,
+    /// to `(x, y)`. Whatever falls off the screen is cut off.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[u8]) {
+        if width == 0 {
+            return;
+        }
+
+        for (row, line) in pixels.chunks(width).enumerate() {
+            let screen_y = y + row;
+            if screen_y >= self.height {
+                break;
+            }
+
+            for (column, &color) in line.iter().enumerate() {
+                let screen_x = x + column;
+                if screen_x >= self.width {
+                    break;
+                }
+
+                let offset = screen_y * self.stride + screen_x;
+                self.store(offset, color);
+            }
+        }
+    }
+
+    /// Draws `text` with the 8x8 font, its top left corner at `(x, y)`.
+    /// Without a `background` only the glyphs' pixels are set. Newlines
+    /// start a new line of text below `x`.
+    pub fn text(&mut self, x: usize, y: usize, text: &str, foreground: u8, background: Option<u8>) {
+        let (mut cell_x, mut cell_y) = (x, y);
+
+        for c in text.chars() {
+            if c == '\n' {
+                cell_x = x;
+                cell_y += GLYPH_HEIGHT;
+                continue;
+            }
+
+            let glyph = &FONT_8X8[cp437::encode(c) as usize];
+            for (row, &bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    let set = bits & (0x80 >> column) != 0;
+                    let color = if set { Some(foreground) } else { background };
+                    if let Some(color) = color {
+                        self.pixel((cell_x + column) as isize, (cell_y + row) as isize, color);
+                    }
+                }
+            }
+
+            cell_x += GLYPH_WIDTH;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(graphics: &mut Graphics<&mut [u8]>, color: u8) -> usize {
+        graphics.framebuffer.iter().filter(|&&pixel| pixel == color).count()
+    }
+
+    #[test]
+    fn pixels_and_clipping() {
+        let mut memory = [0u8; WIDTH * HEIGHT];
+        let mut graphics = Graphics::new(&mut memory[..]);
+
+        graphics.pixel(3, 2, 7);
+        graphics.pixel(-1, 0, 7);
+        graphics.pixel(WIDTH as isize, 0, 7);
+        assert_eq!(graphics.get_pixel(3, 2), Some(7));
+        assert_eq!(count(&mut graphics, 7), 1);
+        assert_eq!(memory[2 * WIDTH + 3], 7);
+    }
+
+    #[test]
+    fn lines_include_both_ends() {
+        let mut memory = [0u8; WIDTH * HEIGHT];
+        let mut graphics = Graphics::new(&mut memory[..]);
+
+        graphics.line(10, 10, 0, 5, 1);
+        assert_eq!(graphics.get_pixel(10, 10), Some(1));
+        assert_eq!(graphics.get_pixel(0, 5), Some(1));
+        // one pixel per column along the longer axis
+        assert_eq!(count(&mut graphics, 1), 11);
+
+        graphics.line(0, 0, 0, 9, 2);
+        assert_eq!(count(&mut graphics, 2), 10);
+    }
+
+    #[test]
+    fn rectangles() {
+        let mut memory = [0u8; WIDTH * HEIGHT];
+        let mut graphics = Graphics::new(&mut memory[..]);
+
+        graphics.rect(5, 5, 4, 3, 1);
+        assert_eq!(count(&mut graphics, 1), 10);
+
+        graphics.fill_rect(WIDTH - 2, HEIGHT - 2, 10, 10, 2);
+        assert_eq!(count(&mut graphics, 2), 4);
+    }
+
+    #[test]
+    fn blit_is_clipped() {
+        let mut memory = [0u8; WIDTH * HEIGHT];
+        let mut graphics = Graphics::new(&mut memory[..]);
+
+        graphics.blit(WIDTH - 1, 0, 2, &[1, 2, 3, 4]);
+        assert_eq!(graphics.get_pixel(WIDTH - 1, 0), Some(1));
+        assert_eq!(graphics.get_pixel(WIDTH - 1, 1), Some(3));
+        assert_eq!(count(&mut graphics, 2) + count(&mut graphics, 4), 0);
+    }
+
+    #[test]
+    fn text_uses_the_font() {
+        let mut memory = [0u8; WIDTH * HEIGHT];
+        let mut graphics = Graphics::with_size(&mut memory[..], 16, 8, WIDTH);
+
+        graphics.text(0, 0, "|_", 1, Some(2));
+        // '|' is a vertical bar in the middle of its cell
+        assert_eq!(graphics.get_pixel(3, 0), Some(1));
+        assert_eq!(graphics.get_pixel(0, 0), Some(2));
+        assert_eq!(graphics.get_pixel(8 + 1, 5), Some(1));
+        // nothing beyond the 16x8 window
+        assert_eq!(count(&mut graphics, 1) + count(&mut graphics, 2), 16 * 8);
+    }
+}
@@ -0,0 +1,104 @@
+//! Access to the VGA registers.
+//!
+//! The registers live in I/O port space, which the host tests can't touch,
+//! so they go through the `Io` trait. The kernel implements it with real
+//! port accesses; `NoIo` ignores everything.
+
+/// Reads and writes I/O ports.
+pub trait Io {
+    fn inb(&mut self, port: u16) -> u8;
+    fn outb(&mut self, port: u16, value: u8);
+}
+
+/// An `Io` with no hardware behind it.
+pub struct NoIo;
+
+impl Io for NoIo {
+    fn inb(&mut self, _port: u16) -> u8 {
+        0
+    }
+
+    fn outb(&mut self, _port: u16, _value: u8) {}
+}
+
+// CRT controller (color mode) index/data ports
+pub const CRTC_INDEX: u16 = 0x3D4;
+pub const CRTC_DATA: u16 = 0x3D5;
+
+// CRT controller registers
+pub const CRTC_CURSOR_START: u8 = 0x0A;
+pub const CRTC_CURSOR_END: u8 = 0x0B;
+pub const CRTC_CURSOR_HIGH: u8 = 0x0E;
+pub const CRTC_CURSOR_LOW: u8 = 0x0F;
+
+/// Set in the cursor start register to hide the cursor.
+pub const CURSOR_DISABLE: u8 = 0x20;
+
+pub fn read_crtc<I: Io>(io: &mut I, index: u8) -> u8 {
+    io.outb(CRTC_INDEX, index);
+    io.inb(CRTC_DATA)
+}
+
+pub fn write_crtc<I: Io>(io: &mut I, index: u8, value: u8) {
+    io.outb(CRTC_INDEX, index);
+    io.outb(CRTC_DATA, value);
+}
+
+// the other register groups; the attribute controller uses one port for
+// both index and data, toggling on every write
+pub const ATTRIBUTE_INDEX: u16 = 0x3C0;
+pub const ATTRIBUTE_READ: u16 = 0x3C1;
+pub const MISC_WRITE: u16 = 0x3C2;
+pub const SEQUENCER_INDEX: u16 = 0x3C4;
+pub const SEQUENCER_DATA: u16 = 0x3C5;
+pub const DAC_READ_INDEX: u16 = 0x3C7;
+pub const DAC_WRITE_INDEX: u16 = 0x3C8;
+pub const DAC_DATA: u16 = 0x3C9;
+pub const MISC_READ: u16 = 0x3CC;
+pub const GRAPHICS_INDEX: u16 = 0x3CE;
+pub const GRAPHICS_DATA: u16 = 0x3CF;
+/// Reading it resets the attribute controller to expect an index.
+pub const INPUT_STATUS: u16 = 0x3DA;
+
+// sequencer registers
+pub const SEQUENCER_MAP_MASK: u8 = 0x02;
+pub const SEQUENCER_MEMORY_MODE: u8 = 0x04;
+
+// graphics controller registers
+pub const GRAPHICS_READ_MAP: u8 = 0x04;
+pub const GRAPHICS_MODE: u8 = 0x05;
+pub const GRAPHICS_MISC: u8 = 0x06;
+
+// CRT controller registers that guard the timing registers
+pub const CRTC_HORIZONTAL_BLANK_END: u8 = 0x03;
+pub const CRTC_VERTICAL_RETRACE_END: u8 = 0x11;
+
+/// Set in the attribute index to let the attribute controller drive the
+/// screen again after it was programmed.
+pub const ATTRIBUTE_PALETTE_ENABLE: u8 = 0x20;
+
+pub fn read_sequencer<I: Io>(io: &mut I, index: u8) -> u8 {
+    io.outb(SEQUENCER_INDEX, index);
+    io.inb(SEQUENCER_DATA)
+}
+
+pub fn write_sequencer<I: Io>(io: &mut I, index: u8, value: u8) {
+    io.outb(SEQUENCER_INDEX, index);
+    io.outb(SEQUENCER_DATA, value);
+}
+
+pub fn read_graphics<I: Io>(io: &mut I, index: u8) -> u8 {
+    io.outb(GRAPHICS_INDEX, index);
+    io.inb(GRAPHICS_DATA)
+}
+
+pub fn write_graphics<I: Io>(io: &mut I, index: u8, value: u8) {
+    io.outb(GRAPHICS_INDEX, index);
+    io.outb(GRAPHICS_DATA, value);
+}
+
+pub fn write_attribute<I: Io>(io: &mut I, index: u8, value: u8) {
+    io.inb(INPUT_STATUS);
+    io.outb(ATTRIBUTE_INDEX, index);
+    io.outb(ATTRIBUTE_INDEX, value);
+}
@@ -1,112 +1,276 @@
 #![no_std]
 
+use core::cmp;
 use core::fmt;
 use core::fmt::Write;
 use core::ptr;
 
+mod ansi;
 mod character;
+mod console;
+pub mod cp437;
+pub mod font;
+pub mod graphics;
+pub mod io;
+pub mod mode;
 
-use character::Character;
 pub use character::Color;
-
-const ROWS: usize = 25;
-const COLS: usize = 80;
-
-pub struct Vga<T: AsMut<[u8]>> {
+pub use console::{Console, Edge, RegionError, RegionId, RegionWriter};
+pub use console::{MAIN_REGION, MAX_COLS, MAX_REGIONS, MAX_ROWS, SCROLLBACK_MAX_ROWS};
+pub use font::FONT_PLANE_SIZE;
+pub use graphics::Graphics;
+pub use io::{Io, NoIo};
+pub use mode::Mode;
+
+/// The size of the text mode we boot in.
+const DEFAULT_ROWS: usize = 25;
+const DEFAULT_COLS: usize = 80;
+
+/// Number of virtual consoles.
+pub const CONSOLES: usize = 6;
+
+/// Drives the text mode screen, showing one of several virtual consoles.
+pub struct Vga<T: AsMut<[u8]>, I: Io = NoIo> {
     slice: T,
-    buffer: [Character; ROWS * COLS],
-    pub position: usize,
-    foreground_color: Color,
-    background_color: Color,
+    io: I,
+    consoles: [Console; CONSOLES],
+    active: usize,
+    /// The current text mode's size; the consoles are kept the same size.
+    rows: usize,
+    cols: usize,
+    /// Where the hardware cursor was last put, if it has been.
+    cursor_position: Option<usize>,
+    /// Set when the whole screen has to be rewritten on the next flush,
+    /// e.g. after switching consoles.
+    redraw: bool,
 }
 
 impl<T: AsMut<[u8]>> Vga<T> {
-    pub fn new(mut slice: T) -> Vga<T> {
-        // we must have enough bytes of backing storage to make this work.
-        assert_eq!(slice.as_mut().len(), ROWS * COLS * 2);
-
-        let foreground_color = Color::Green;
-        let background_color = Color::Black;
+    pub fn new(slice: T) -> Vga<T> {
+        Vga::with_io(slice, NoIo)
+    }
+}
 
-        let buffer = [Character::new(b' ', foreground_color, background_color); ROWS * COLS];
+impl<T: AsMut<[u8]>, I: Io> Vga<T, I> {
+    /// Creates a `Vga` that also drives the hardware cursor through `io`.
+    pub fn with_io(mut slice: T, io: I) -> Vga<T, I> {
+        // we must have enough bytes of backing storage to make this work.
+        // More is fine; `save_font` and `restore_font` need `FONT_PLANE_SIZE`.
+        assert!(slice.as_mut().len() >= DEFAULT_ROWS * DEFAULT_COLS * 2);
 
         Vga {
             slice,
-            buffer,
-            position: 0,
-            foreground_color,
-            background_color,
+            io,
+            consoles: [Console::new(), Console::new(), Console::new(),
+                       Console::new(), Console::new(), Console::new()],
+            active: 0,
+            rows: DEFAULT_ROWS,
+            cols: DEFAULT_COLS,
+            cursor_position: None,
+            redraw: true,
         }
     }
 
-    pub fn set_foreground_color(&mut self, color: Color) {
-        self.foreground_color = color;
+    /// The virtual console with the given index; output to it only shows
+    /// up on screen while it is the active one.
+    pub fn console(&mut self, index: usize) -> &mut Console {
+        &mut self.consoles[index]
     }
 
-    pub fn set_background_color(&mut self, color: Color) {
-        self.background_color = color;
+    pub fn active_console(&self) -> usize {
+        self.active
     }
 
-    pub fn invert(&mut self) {
-        core::mem::swap(&mut self.foreground_color, &mut self.background_color);
+    pub fn rows(&self) -> usize {
+        self.rows
     }
 
-    pub fn flush(&mut self) {
-        // we need to use `write_volatile` here so that the writes aren't optimized out
-        unsafe {
-            let p = self.slice.as_mut();
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
 
-            for (chunk, character) in p.chunks_mut(2).zip(self.buffer.iter()) {
-                let bytes = character.as_ushort();
-                let p : *mut u8 = &mut chunk[0];
-                ptr::write_volatile(p as *mut u16, bytes);
+    /// Brings console `index` to the screen; takes effect on the next `flush`.
+    pub fn switch_to(&mut self, index: usize) {
+        assert!(index < CONSOLES);
+        self.active = index;
+        self.cursor_position = None;
+        self.redraw = true;
+    }
 
+    /// Shows or hides the hardware cursor.
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        let start = io::read_crtc(&mut self.io, io::CRTC_CURSOR_START);
+        let start = if visible {
+            start & !io::CURSOR_DISABLE
+        } else {
+            start | io::CURSOR_DISABLE
+        };
+        io::write_crtc(&mut self.io, io::CRTC_CURSOR_START, start);
+    }
+
+    /// Makes the cursor cover scan lines `start` through `end` of the
+    /// character cell, e.g. 14-15 for an underline or 0-15 for a block with
+    /// the standard 8x16 font.
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        let old_start = io::read_crtc(&mut self.io, io::CRTC_CURSOR_START);
+        let old_end = io::read_crtc(&mut self.io, io::CRTC_CURSOR_END);
+        io::write_crtc(&mut self.io, io::CRTC_CURSOR_START, (old_start & 0xE0) | (start & 0x1F));
+        io::write_crtc(&mut self.io, io::CRTC_CURSOR_END, (old_end & 0xE0) | (end & 0x1F));
+    }
+
+    /// Reprograms the display for `mode`. The consoles are drawn again by
+    /// the first `flush` after going back to text mode, resized to fit it.
+    ///
+    /// Text modes with an 8 line font get `font::FONT_8X8`; the font of the
+    /// other text modes is left alone, so going back to one of them needs
+    /// the original font put back with `restore_font`.
+    pub fn set_mode(&mut self, mode: &Mode) -> Result<(), RegionError> {
+        if mode.is_text() {
+            assert!(mode.width <= MAX_COLS && mode.height <= MAX_ROWS);
+            assert!(self.slice.as_mut().len() >= mode.width * mode.height * 2);
+
+            // check every console first, so that a failure changes nothing
+            if self.consoles.iter().any(|console| !console.can_resize(mode.height)) {
+                return Err(RegionError::NoRoom);
             }
         }
-    }
 
-    fn write_byte(&mut self, byte: u8) {
-        let i = self.position;
+        mode::set_mode(&mut self.io, mode);
+        self.cursor_position = None;
+        self.redraw = true;
 
-        if byte == '\n' as u8 {
-            let current_line = self.position / COLS;
-            self.position = (current_line + 1) * COLS;
-        } else {
-            self.buffer[i] = Character::new(byte, self.foreground_color, self.background_color);
+        if mode.is_text() {
+            for console in self.consoles.iter_mut() {
+                console.resize(mode.height, mode.width).unwrap();
+            }
+            self.rows = mode.height;
+            self.cols = mode.width;
 
-            self.position += 1;
+            if mode.font_height == font::GLYPH_HEIGHT {
+                self.load_font(&font::FONT_8X8);
+            }
         }
 
-        if self.position >= self.buffer.len() {
-            self.scroll();
-        }
+        Ok(())
+    }
+
+    /// Sets palette entry `index`; see `mode::set_palette`.
+    pub fn set_palette(&mut self, index: u8, red: u8, green: u8, blue: u8) {
+        mode::set_palette(&mut self.io, index, red, green, blue);
+    }
+
+    /// Copies the start of the font out of video memory, so that it can be
+    /// put back with `restore_font` after a graphics mode overwrote it.
+    pub fn save_font(&mut self, font: &mut [u8]) {
+        self.with_font_plane(|plane| {
+            for (saved, byte) in font.iter_mut().zip(plane.iter()) {
+                *saved = unsafe { ptr::read_volatile(byte) };
+            }
+        });
     }
 
-    fn scroll(&mut self) {
-        for row in 1..ROWS {
-            for cb in 0..COLS {
-                let prev_position = ((row - 1) * COLS) + cb;
-                let current_position = (row * COLS) + cb;
-                self.buffer[prev_position] = self.buffer[current_position];
+    /// Writes a font saved by `save_font` back to video memory.
+    pub fn restore_font(&mut self, font: &[u8]) {
+        self.with_font_plane(|plane| {
+            for (byte, &saved) in plane.iter_mut().zip(font.iter()) {
+                unsafe { ptr::write_volatile(byte, saved) };
             }
+        });
+    }
+
+    /// Writes an 8 line font to video memory, for text modes with 8 scan
+    /// lines per row.
+    pub fn load_font(&mut self, glyphs: &[[u8; font::GLYPH_HEIGHT]; 256]) {
+        self.with_font_plane(|plane| {
+            for (slot, glyph) in plane.chunks_mut(font::GLYPH_STRIDE).zip(glyphs.iter()) {
+                for (byte, &line) in slot.iter_mut().zip(glyph.iter()) {
+                    unsafe { ptr::write_volatile(byte, line) };
+                }
+            }
+        });
+    }
+
+    /// Runs `f` with plane 2, which holds the font, mapped where the text
+    /// normally is, and the registers put back afterwards.
+    fn with_font_plane<F: FnOnce(&mut [u8])>(&mut self, f: F) {
+        let map_mask = io::read_sequencer(&mut self.io, io::SEQUENCER_MAP_MASK);
+        let memory_mode = io::read_sequencer(&mut self.io, io::SEQUENCER_MEMORY_MODE);
+        let read_map = io::read_graphics(&mut self.io, io::GRAPHICS_READ_MAP);
+        let graphics_mode = io::read_graphics(&mut self.io, io::GRAPHICS_MODE);
+        let misc = io::read_graphics(&mut self.io, io::GRAPHICS_MISC);
+
+        // address the planes one at a time instead of interleaving even and
+        // odd bytes, and pick plane 2 for both reads and writes
+        io::write_sequencer(&mut self.io, io::SEQUENCER_MAP_MASK, 1 << 2);
+        io::write_sequencer(&mut self.io, io::SEQUENCER_MEMORY_MODE, memory_mode | 0x04);
+        io::write_graphics(&mut self.io, io::GRAPHICS_READ_MAP, 2);
+        io::write_graphics(&mut self.io, io::GRAPHICS_MODE, graphics_mode & !0x10);
+        io::write_graphics(&mut self.io, io::GRAPHICS_MISC, misc & !0x02);
+
+        let plane = self.slice.as_mut();
+        let length = cmp::min(plane.len(), FONT_PLANE_SIZE);
+        f(&mut plane[..length]);
+
+        io::write_sequencer(&mut self.io, io::SEQUENCER_MAP_MASK, map_mask);
+        io::write_sequencer(&mut self.io, io::SEQUENCER_MEMORY_MODE, memory_mode);
+        io::write_graphics(&mut self.io, io::GRAPHICS_READ_MAP, read_map);
+        io::write_graphics(&mut self.io, io::GRAPHICS_MODE, graphics_mode);
+        io::write_graphics(&mut self.io, io::GRAPHICS_MISC, misc);
+    }
+
+    /// Moves the hardware cursor to the active console's position, unless it
+    /// is already there. While scrolled back far enough for the cursor to
+    /// leave the screen, it is parked just past the last cell.
+    fn update_cursor(&mut self) {
+        let cells = self.rows * self.cols;
+        let position = self.consoles[self.active].visible_position().unwrap_or(cells);
+        if self.cursor_position == Some(position) {
+            return;
         }
 
-        for cb in 0..COLS {
-            self.buffer[((ROWS - 1) * COLS) + cb] =
-                Character::new(b' ', self.foreground_color, self.background_color);
+        self.cursor_position = Some(position);
+        let position = position as u16;
+        io::write_crtc(&mut self.io, io::CRTC_CURSOR_LOW, (position & 0xFF) as u8);
+        io::write_crtc(&mut self.io, io::CRTC_CURSOR_HIGH, (position >> 8) as u8);
+    }
+
+    /// Copies the cells of the active console that changed since the last
+    /// flush to the screen.
+    pub fn flush(&mut self) {
+        let dirty = self.consoles[self.active].take_dirty();
+        let cols = self.cols;
+        let dirty = if self.redraw { 0..self.rows * cols } else { dirty };
+        self.redraw = false;
+
+        // we need to use `write_volatile` here so that the writes aren't optimized out
+        unsafe {
+            let p = self.slice.as_mut();
+
+            let console = &self.consoles[self.active];
+            let mut position = dirty.start;
+            while position < dirty.end {
+                let row = position / cols;
+                let row_end = cmp::min((row + 1) * cols, dirty.end);
+                let cells = &console.visible_row(row)[position % cols..row_end - row * cols];
+
+                for (chunk, character) in p[position * 2..row_end * 2].chunks_mut(2).zip(cells.iter()) {
+                    let bytes = character.as_ushort();
+                    let p : *mut u8 = &mut chunk[0];
+                    ptr::write_volatile(p as *mut u16, bytes);
+                }
+
+                position = row_end;
+            }
         }
 
-        self.position = (ROWS - 1) * COLS;
+        self.update_cursor();
     }
 }
 
-impl<T: AsMut<[u8]>> Write for Vga<T> {
+/// Writing to the `Vga` itself goes to the first console.
+impl<T: AsMut<[u8]>, I: Io> Write for Vga<T, I> {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        for b in s.bytes() {
-            self.write_byte(b);
-        }
-
-        Ok(())
+        self.consoles[0].write_str(s)
     }
 }
 
@@ -114,9 +278,12 @@ impl<T: AsMut<[u8]>> Write for Vga<T> {
 mod tests {
     use core::fmt::Write;
     use Vga;
+    use io::{self, Io};
 
-    use COLS;
-    use ROWS;
+    use DEFAULT_COLS as COLS;
+    use DEFAULT_ROWS as ROWS;
+    use {Edge, RegionError, MAIN_REGION, MAX_REGIONS, SCROLLBACK_MAX_ROWS};
+    use {cp437, font, mode, FONT_PLANE_SIZE};
 
     #[test]
     fn write_a_letter() {
@@ -219,9 +386,9 @@ mod tests {
         let mut mock_memory = [0u8; ROWS * COLS * 2];
         let mut vga = Vga::new(&mut mock_memory[..]);
 
-        for b in "abcdefghijklmnopqrstuvwxyz".bytes() {
-            vga.write_byte(b);
-            vga.write_byte('\n' as u8);
+        for c in "abcdefghijklmnopqrstuvwxyz".chars() {
+            vga.write_char(c).unwrap();
+            vga.write_char('\n').unwrap();
         }
 
         vga.flush();
@@ -233,4 +400,604 @@ mod tests {
         }
     }
 
+    /// Emulates the index/data register pairs of the CRT controller, the
+    /// sequencer and the graphics controller.
+    struct MockIo {
+        index: u8,
+        crtc: [u8; 0x19],
+        writes: usize,
+        sequencer_index: u8,
+        sequencer: [u8; 5],
+        graphics_index: u8,
+        graphics: [u8; 9],
+    }
+
+    impl MockIo {
+        fn new() -> MockIo {
+            MockIo {
+                index: 0,
+                crtc: [0; 0x19],
+                writes: 0,
+                sequencer_index: 0,
+                sequencer: [0; 5],
+                graphics_index: 0,
+                graphics: [0; 9],
+            }
+        }
+    }
+
+    impl Io for MockIo {
+        fn inb(&mut self, port: u16) -> u8 {
+            match port {
+                io::CRTC_DATA => self.crtc[self.index as usize],
+                io::SEQUENCER_DATA => self.sequencer[self.sequencer_index as usize],
+                io::GRAPHICS_DATA => self.graphics[self.graphics_index as usize],
+                _ => 0,
+            }
+        }
+
+        fn outb(&mut self, port: u16, value: u8) {
+            match port {
+                io::CRTC_INDEX => self.index = value,
+                io::CRTC_DATA => {
+                    self.crtc[self.index as usize] = value;
+                    self.writes += 1;
+                },
+                io::SEQUENCER_INDEX => self.sequencer_index = value,
+                io::SEQUENCER_DATA => self.sequencer[self.sequencer_index as usize] = value,
+                io::GRAPHICS_INDEX => self.graphics_index = value,
+                io::GRAPHICS_DATA => self.graphics[self.graphics_index as usize] = value,
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_follows_position() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        vga.write_str("hello\nab").unwrap();
+        vga.flush();
+
+        let position = COLS + 2;
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_LOW as usize], (position & 0xFF) as u8);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_HIGH as usize], (position >> 8) as u8);
+
+        // flushing again without moving doesn't touch the registers
+        let writes = vga.io.writes;
+        vga.flush();
+        assert_eq!(vga.io.writes, writes);
+    }
+
+    #[test]
+    fn cursor_visibility_and_shape() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        vga.set_cursor_shape(14, 15);
+        vga.set_cursor_visible(false);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_START as usize], 0x20 | 14);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_END as usize], 15);
+
+        vga.set_cursor_visible(true);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_START as usize], 14);
+    }
+
+    #[test]
+    fn carriage_return_tab_and_backspace() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("xyz\rab\tc\x08d").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[0], b'a');
+        assert_eq!(mock_memory[2], b'b');
+        assert_eq!(mock_memory[4], b'z');
+        assert_eq!(mock_memory[16], b'd');
+    }
+
+    #[test]
+    fn sgr_colors() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("\x1b[31;44ma\x1b[1mb\x1b[0mc\x1b[7md\x1b[m\x1b[93me").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[1], 0x14); // red on blue
+        assert_eq!(mock_memory[3], 0x1C); // bright red on blue
+        assert_eq!(mock_memory[5], 0x02); // back to the defaults
+        assert_eq!(mock_memory[7], 0x20); // reversed
+        assert_eq!(mock_memory[9], 0x0E); // yellow
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("\x1b[3;5Ha\x1b[2Db\x1b[Ac\x1b[20;99Hd\x1b[Ge").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[(2 * COLS + 4) * 2], b'a');
+        assert_eq!(mock_memory[(2 * COLS + 3) * 2], b'b');
+        assert_eq!(mock_memory[(COLS + 4) * 2], b'c');
+        assert_eq!(mock_memory[(19 * COLS + COLS - 1) * 2], b'd');
+        assert_eq!(mock_memory[(20 * COLS) * 2], b'e');
+    }
+
+    #[test]
+    fn erase_line() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("hello\nworld\x1b[3D\x1b[K").unwrap();
+        vga.flush();
+        assert_eq!(mock_memory[COLS * 2], b'w');
+        assert_eq!(mock_memory[(COLS + 1) * 2], b'o');
+        assert_eq!(mock_memory[(COLS + 2) * 2], b' ');
+        assert_eq!(mock_memory[0], b'h');
+    }
+
+    #[test]
+    fn erase_screen() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("hello\nworld\x1b[2J").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[0], b' ');
+        assert_eq!(mock_memory[COLS * 2], b' ');
+    }
+
+    #[test]
+    fn unknown_sequences_are_swallowed() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("\x1b[?25la\x1b(Bb").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[0], b'a');
+        assert_eq!(mock_memory[2], b'b');
+    }
+
+    #[test]
+    fn consoles_are_independent() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        vga.write_str("log").unwrap();
+        vga.console(1).write_str("shell\n$ ").unwrap();
+        vga.flush();
+        assert_eq!(mock_memory[0], b'l');
+
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        vga.write_str("log").unwrap();
+        vga.console(1).write_str("shell\n$ ").unwrap();
+        vga.switch_to(1);
+        vga.flush();
+        assert_eq!(vga.active_console(), 1);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_LOW as usize], (COLS + 2) as u8);
+        assert_eq!(mock_memory[0], b's');
+        assert_eq!(mock_memory[COLS * 2], b'$');
+    }
+
+    /// Writes lines "0", "1", ... so that each row names its line number.
+    fn write_lines<W: Write>(w: &mut W, count: usize) {
+        for i in 0..count {
+            writeln!(w, "{}", i).unwrap();
+        }
+    }
+
+    fn row_text(memory: &[u8], row: usize) -> u8 {
+        memory[row * COLS * 2]
+    }
+
+    #[test]
+    fn scrollback_shows_old_rows() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        // the screen ends with an empty row, so 3 lines scroll off
+        write_lines(&mut vga, ROWS + 2);
+        assert_eq!(vga.console(0).scrollback_len(), 3);
+
+        vga.console(0).scroll_to(3);
+        vga.flush();
+        assert_eq!(vga.console(0).scrollback_offset(), 3);
+        // the cursor is on the live row below the screen
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_LOW as usize], ((ROWS * COLS) & 0xFF) as u8);
+        let screen = screen(&mut vga);
+        assert_eq!(row_text(&screen, 0), b'0');
+        assert_eq!(row_text(&screen, 2), b'2');
+
+        // scrolling further than the history goes stops at the oldest row
+        vga.console(0).page_up();
+        assert_eq!(vga.console(0).scrollback_offset(), 3);
+
+        vga.console(0).page_down();
+        assert_eq!(vga.console(0).scrollback_offset(), 0);
+    }
+
+    /// A copy of what is currently on screen.
+    fn screen<T: AsMut<[u8]>, I: Io>(vga: &mut Vga<T, I>) -> [u8; ROWS * COLS * 2] {
+        let mut copy = [0u8; ROWS * COLS * 2];
+        copy.copy_from_slice(vga.slice.as_mut());
+        copy
+    }
+
+    #[test]
+    fn scrollback_wraps_around() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.console(0).set_scrollback_limit(4);
+        // 10 rows scroll off; only the last 4 ("6" to "9") are kept
+        write_lines(&mut vga, ROWS + 9);
+        assert_eq!(vga.console(0).scrollback_len(), 4);
+
+        vga.console(0).scroll_to(100);
+        assert_eq!(vga.console(0).scrollback_offset(), 4);
+        vga.flush();
+        let screen = screen(&mut vga);
+        assert_eq!(row_text(&screen, 0), b'6');
+        assert_eq!(row_text(&screen, 3), b'9');
+        assert_eq!(&screen[4 * COLS * 2..4 * COLS * 2 + 4], b"1\x020\x02");
+    }
+
+    #[test]
+    fn scrollback_wraps_around_the_whole_ring() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        write_lines(&mut vga, ROWS - 1 + SCROLLBACK_MAX_ROWS * 2 + 1);
+        assert_eq!(vga.console(0).scrollback_len(), SCROLLBACK_MAX_ROWS);
+
+        // the oldest kept row is the first one after the discarded ones
+        vga.console(0).scroll_to(SCROLLBACK_MAX_ROWS);
+        vga.flush();
+        let screen = screen(&mut vga);
+        let oldest = SCROLLBACK_MAX_ROWS + 1;
+        assert_eq!(screen[0], b'0' + (oldest / 10) as u8);
+        assert_eq!(screen[2], b'0' + (oldest % 10) as u8);
+
+        // shrinking the limit keeps the newest rows
+        vga.console(0).set_scrollback_limit(1);
+        assert_eq!(vga.console(0).scrollback_len(), 1);
+        assert_eq!(vga.console(0).scrollback_offset(), 1);
+        vga.flush();
+        let screen = self::screen(&mut vga);
+        let newest = oldest + SCROLLBACK_MAX_ROWS - 1;
+        assert_eq!(screen[0], b'0' + (newest / 100) as u8);
+        assert_eq!(screen[2], b'0' + (newest / 10 % 10) as u8);
+        assert_eq!(screen[4], b'0' + (newest % 10) as u8);
+    }
+
+    #[test]
+    fn output_returns_to_live_view() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        write_lines(&mut vga, ROWS + 5);
+        vga.console(0).page_up();
+        assert!(vga.console(0).scrollback_offset() > 0);
+
+        vga.write_str("x").unwrap();
+        assert_eq!(vga.console(0).scrollback_offset(), 0);
+    }
+
+    /// Fills the screen with a byte no flush writes, so that afterwards the
+    /// cells that were written can be counted.
+    fn poison<T: AsMut<[u8]>, I: Io>(vga: &mut Vga<T, I>) {
+        for byte in vga.slice.as_mut().iter_mut() {
+            *byte = 0xFF;
+        }
+    }
+
+    fn cells_written<T: AsMut<[u8]>, I: Io>(vga: &mut Vga<T, I>) -> usize {
+        vga.slice.as_mut().chunks(2).filter(|cell| cell[1] != 0xFF).count()
+    }
+
+    #[test]
+    fn utf8_is_shown_in_cp437() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("é┌─→€").unwrap();
+        vga.flush();
+
+        assert_eq!(mock_memory[0], 0x82);
+        assert_eq!(mock_memory[2], 0xDA);
+        assert_eq!(mock_memory[4], 0xC4);
+        assert_eq!(mock_memory[6], 0x1A);
+        assert_eq!(mock_memory[8], cp437::REPLACEMENT);
+        assert_eq!(mock_memory[10], b' ');
+    }
+
+    #[test]
+    fn raw_cp437_bypasses_control_characters() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.console(0).write_cp437(b"\x01\n\x1b[m");
+        vga.flush();
+
+        assert_eq!(&mock_memory[..12], b"\x01\x02\n\x02\x1b\x02[\x02m\x02 \x02");
+    }
+
+    #[test]
+    fn flush_writes_only_changed_cells() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        // the first flush draws everything
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), ROWS * COLS);
+
+        poison(&mut vga);
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), 0);
+
+        poison(&mut vga);
+        vga.write_str("a").unwrap();
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), 1);
+
+        poison(&mut vga);
+        vga.write_str("\x1b[2K").unwrap();
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), COLS);
+
+        // scrolling moves every row
+        poison(&mut vga);
+        vga.write_str("\x1b[25;1H\n").unwrap();
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), ROWS * COLS);
+
+        // as do switching consoles and looking at the scrollback
+        poison(&mut vga);
+        vga.switch_to(1);
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), ROWS * COLS);
+
+        vga.switch_to(0);
+        vga.flush();
+        poison(&mut vga);
+        vga.console(0).scroll_to(1);
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), ROWS * COLS);
+    }
+
+    #[test]
+    fn typing_a_line_writes_one_cell_per_character() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+        vga.flush();
+
+        // like `kprint!`, flush after every character
+        let mut written = 0;
+        for c in "hello, world".chars() {
+            poison(&mut vga);
+            vga.write_char(c).unwrap();
+            vga.flush();
+            written += cells_written(&mut vga);
+        }
+
+        // rewriting the whole screen every time would have been 12 * 2000
+        assert_eq!(written, 12);
+    }
+
+    #[test]
+    fn status_bar_stays_put() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        let status = vga.console(0).split(MAIN_REGION, Edge::Top, 1).unwrap();
+        vga.console(0).region(status).invert();
+        write!(vga.console(0).region(status), "ticks: 1").unwrap();
+
+        // fill the main region and then some
+        write_lines(&mut vga, ROWS + 3);
+        vga.console(0).region(status).clear();
+        write!(vga.console(0).region(status), "ticks: 2").unwrap();
+        vga.flush();
+
+        let live = screen(&mut vga);
+        assert_eq!(&live[..8], b"t\x20i\x20c\x20k\x20");
+        assert_eq!(live[7 * 2], b'2');
+        // the main region starts below the status bar and has scrolled
+        assert_eq!(row_text(&live, 1), b'5');
+        assert_eq!(row_text(&live, ROWS - 2), b'2');
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_LOW as usize], (((ROWS - 1) * COLS) & 0xFF) as u8);
+
+        // the history only holds main region rows
+        vga.console(0).scroll_to(1);
+        vga.flush();
+        let scrolled = screen(&mut vga);
+        assert_eq!(scrolled[7 * 2], b'2');
+        assert_eq!(row_text(&scrolled, 1), b'4');
+    }
+
+    #[test]
+    fn regions_have_their_own_cursor() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.write_str("abc").unwrap();
+        let bottom = vga.console(0).split(MAIN_REGION, Edge::Bottom, 5).unwrap();
+        vga.console(0).region(bottom).write_str("x\x1b[2;3Hy\x1b[J").unwrap();
+        vga.write_str("d").unwrap();
+        vga.flush();
+
+        assert_eq!(vga.console(0).region(bottom).position(), (1, 3));
+        assert_eq!(vga.console(0).region(MAIN_REGION).position(), (0, 4));
+        assert_eq!(mock_memory[6], b'd');
+        assert_eq!(mock_memory[(ROWS - 5) * COLS * 2], b'x');
+        assert_eq!(mock_memory[((ROWS - 4) * COLS + 2) * 2], b'y');
+    }
+
+    #[test]
+    fn splitting_checks_room() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+        let console = vga.console(0);
+
+        assert_eq!(console.split(MAIN_REGION, Edge::Top, ROWS), Err(RegionError::NoRoom));
+        assert_eq!(console.split(MAIN_REGION, Edge::Top, 0), Err(RegionError::NoRoom));
+        for _ in 1..MAX_REGIONS {
+            console.split(MAIN_REGION, Edge::Top, 1).unwrap();
+        }
+        assert_eq!(console.split(MAIN_REGION, Edge::Top, 1), Err(RegionError::TooManyRegions));
+    }
+
+    #[test]
+    fn switching_modes() {
+        let mut mock_memory = [0u8; ROWS * COLS * 2];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+        vga.flush();
+
+        // the timing registers are unlocked before they are written
+        vga.io.crtc[io::CRTC_VERTICAL_RETRACE_END as usize] = 0x80;
+        vga.set_mode(&mode::GRAPHICS_320X200).unwrap();
+        assert_eq!(vga.io.crtc[0x09], 0x41);
+        assert_eq!(vga.io.crtc[io::CRTC_VERTICAL_RETRACE_END as usize], 0x0E);
+        assert_eq!(vga.io.sequencer[io::SEQUENCER_MEMORY_MODE as usize], 0x0E);
+        assert_eq!(vga.io.graphics[io::GRAPHICS_MODE as usize], 0x40);
+
+        // back in text mode everything is drawn again
+        vga.set_mode(&mode::TEXT_80X25).unwrap();
+        assert_eq!(vga.io.graphics[io::GRAPHICS_MISC as usize], 0x0E);
+        poison(&mut vga);
+        vga.flush();
+        assert_eq!(cells_written(&mut vga), ROWS * COLS);
+    }
+
+    #[test]
+    fn font_survives_a_round_trip() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+        vga.set_mode(&mode::TEXT_80X25).unwrap();
+
+        let mut font = [0u8; FONT_PLANE_SIZE];
+        for (i, byte) in font.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        vga.restore_font(&font);
+        // plane 2 was selected for the copy and deselected afterwards
+        assert_eq!(vga.io.sequencer[io::SEQUENCER_MAP_MASK as usize], 0x03);
+        assert_eq!(vga.io.graphics[io::GRAPHICS_MISC as usize], 0x0E);
+
+        let mut saved = [0u8; FONT_PLANE_SIZE];
+        vga.save_font(&mut saved);
+        assert_eq!(&saved[..], &font[..]);
+    }
+
+    #[test]
+    fn taller_text_mode() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        let header = vga.console(0).split(MAIN_REGION, Edge::Bottom, 1).unwrap();
+        vga.console(0).region(header).write_str("status").unwrap();
+        write_lines(&mut vga, 3);
+        vga.set_mode(&mode::TEXT_80X50).unwrap();
+        assert_eq!((vga.rows(), vga.cols()), (50, 80));
+
+        // the font went in with 32 bytes per glyph, read back from plane 2
+        let mut font = [0u8; FONT_PLANE_SIZE];
+        vga.save_font(&mut font);
+        assert_eq!(&font[b'A' as usize * 32..b'A' as usize * 32 + 8], &font::FONT_8X8[b'A' as usize][..]);
+
+        write_lines(&mut vga, 40);
+        vga.flush();
+        let memory = &vga.slice;
+        assert_eq!(memory[0], b'0');
+        assert_eq!(memory[42 * COLS * 2], b'3');
+        // the bottom region moved down to stay at the bottom
+        assert_eq!(memory[49 * COLS * 2], b's');
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_LOW as usize], ((43 * COLS) & 0xFF) as u8);
+        assert_eq!(vga.io.crtc[io::CRTC_CURSOR_HIGH as usize], ((43 * COLS) >> 8) as u8);
+    }
+
+    #[test]
+    fn wider_and_shorter_text_mode() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        // growing keeps every row where it was, now with 90 cells each
+        write_lines(&mut vga, ROWS - 1);
+        vga.write_str("abc").unwrap();
+        vga.set_mode(&mode::TEXT_90X30).unwrap();
+        vga.write_str("d").unwrap();
+        vga.flush();
+
+        let cols = 90;
+        let memory = &vga.slice;
+        assert_eq!(memory[cols * 2], b'1');
+        assert_eq!(memory[((ROWS - 1) * cols + 3) * 2], b'd');
+        assert_eq!(memory[(ROWS - 1) * cols * 2 + COLS * 2], b' ');
+    }
+
+    #[test]
+    fn rows_move_both_ways() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.set_mode(&mode::TEXT_80X50).unwrap();
+        let top = vga.console(0).split(MAIN_REGION, Edge::Top, 1).unwrap();
+        let bottom = vga.console(0).split(MAIN_REGION, Edge::Bottom, 1).unwrap();
+        vga.console(0).region(top).write_str("top").unwrap();
+        vga.console(0).region(bottom).write_str("end").unwrap();
+        write_lines(&mut vga, 47);
+
+        // the main region's rows move down, being wider, while the bottom
+        // region's move up
+        vga.set_mode(&mode::TEXT_90X30).unwrap();
+        vga.flush();
+
+        let cols = 90;
+        let memory = &vga.slice;
+        assert_eq!(&memory[..6], b"t\x02o\x02p\x02");
+        assert_eq!(&memory[cols * 2..cols * 2 + 4], b"2\x020\x02");
+        assert_eq!(&memory[27 * cols * 2..27 * cols * 2 + 4], b"4\x026\x02");
+        assert_eq!(memory[(cols + COLS) * 2], b' ');
+        assert_eq!(memory[28 * cols * 2], b' ');
+        assert_eq!(&memory[29 * cols * 2..29 * cols * 2 + 6], b"e\x02n\x02d\x02");
+    }
+
+    #[test]
+    fn shrinking_scrolls_the_cursor_into_view() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::new(&mut mock_memory[..]);
+
+        vga.set_mode(&mode::TEXT_80X50).unwrap();
+        write_lines(&mut vga, 40);
+        vga.set_mode(&mode::TEXT_80X25).unwrap();
+        vga.flush();
+
+        // the cursor was on row 40, so 16 rows went to the history
+        assert_eq!(vga.console(0).scrollback_len(), 16);
+        assert_eq!(vga.console(0).region(MAIN_REGION).position(), (ROWS - 1, 0));
+        assert_eq!(row_text(vga.slice, 0), b'1');
+        assert_eq!(row_text(vga.slice, ROWS - 2), b'3');
+    }
+
+    #[test]
+    fn resizing_needs_room_for_the_main_region() {
+        let mut mock_memory = [0u8; FONT_PLANE_SIZE];
+        let mut vga = Vga::with_io(&mut mock_memory[..], MockIo::new());
+
+        vga.set_mode(&mode::TEXT_80X50).unwrap();
+        vga.console(3).split(MAIN_REGION, Edge::Top, 30).unwrap();
+        assert_eq!(vga.set_mode(&mode::TEXT_80X25), Err(RegionError::NoRoom));
+        // nothing was touched
+        assert_eq!(vga.rows(), 50);
+        assert_eq!(vga.io.crtc[0x09], 0x47);
+    }
 }
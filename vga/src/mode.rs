@@ -0,0 +1,174 @@
+//! Switching the VGA between display modes by programming its registers
+//! directly, as there is no BIOS to ask once we are in long mode.
+//!
+//! The register values are the standard ones, see
+//! http://wiki.osdev.org/VGA_Hardware#List_of_register_settings
+
+use io::{self, Io};
+
+/// The register settings that make up a display mode.
+pub struct Mode {
+    /// Columns in text modes, pixels in graphics modes.
+    pub width: usize,
+    /// Rows in text modes, pixels in graphics modes.
+    pub height: usize,
+    /// Scan lines per character row; 0 for graphics modes.
+    pub font_height: usize,
+    misc: u8,
+    sequencer: [u8; 5],
+    crtc: [u8; 25],
+    graphics: [u8; 9],
+    attribute: [u8; 21],
+}
+
+impl Mode {
+    pub fn is_text(&self) -> bool {
+        self.font_height != 0
+    }
+}
+
+/// 80x25 text with the 8x16 font, the mode we boot in.
+pub static TEXT_80X25: Mode = Mode {
+    width: 80,
+    height: 25,
+    font_height: 16,
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F,
+        0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x50,
+        0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3,
+        0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07,
+        0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+        0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+/// 80x50 text: the 80x25 timings with rows half as tall, for the 8x8 font.
+pub static TEXT_80X50: Mode = Mode {
+    width: 80,
+    height: 50,
+    font_height: 8,
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F,
+        0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x01, 0x40,
+        0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3,
+        0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07,
+        0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+        0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+/// 90x30 text with the 8x16 font: 8 pixel wide cells on a 720x480 screen.
+pub static TEXT_90X30: Mode = Mode {
+    width: 90,
+    height: 30,
+    font_height: 16,
+    misc: 0xE7,
+    sequencer: [0x03, 0x01, 0x03, 0x00, 0x02],
+    crtc: [
+        0x6B, 0x59, 0x5A, 0x82, 0x60, 0x8D, 0x0B, 0x3E,
+        0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00,
+        0xEA, 0x0C, 0xDF, 0x2D, 0x10, 0xE8, 0x05, 0xA3,
+        0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07,
+        0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+        0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+/// The text modes, for picking one by size.
+pub static TEXT_MODES: [&Mode; 3] = [&TEXT_80X25, &TEXT_80X50, &TEXT_90X30];
+
+/// Mode 13h: 320x200 pixels with 256 colors, one byte per pixel at 0xA0000.
+pub static GRAPHICS_320X200: Mode = Mode {
+    width: 320,
+    height: 200,
+    font_height: 0,
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F,
+        0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3,
+        0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+/// Programs every register of `mode`.
+///
+/// Switching into a graphics mode overwrites the font, which lives in plane
+/// 2 of video memory; see `Vga::save_font` to keep it.
+pub fn set_mode<I: Io>(io: &mut I, mode: &Mode) {
+    io.outb(io::MISC_WRITE, mode.misc);
+
+    for (index, &value) in mode.sequencer.iter().enumerate() {
+        io::write_sequencer(io, index as u8, value);
+    }
+
+    // the timing registers are write protected until these bits say otherwise
+    let blank_end = io::read_crtc(io, io::CRTC_HORIZONTAL_BLANK_END);
+    io::write_crtc(io, io::CRTC_HORIZONTAL_BLANK_END, blank_end | 0x80);
+    let retrace_end = io::read_crtc(io, io::CRTC_VERTICAL_RETRACE_END);
+    io::write_crtc(io, io::CRTC_VERTICAL_RETRACE_END, retrace_end & !0x80);
+
+    for (index, &value) in mode.crtc.iter().enumerate() {
+        let value = match index as u8 {
+            io::CRTC_HORIZONTAL_BLANK_END => value | 0x80,
+            io::CRTC_VERTICAL_RETRACE_END => value & !0x80,
+            _ => value,
+        };
+        io::write_crtc(io, index as u8, value);
+    }
+
+    for (index, &value) in mode.graphics.iter().enumerate() {
+        io::write_graphics(io, index as u8, value);
+    }
+
+    for (index, &value) in mode.attribute.iter().enumerate() {
+        io::write_attribute(io, index as u8, value);
+    }
+
+    io.inb(io::INPUT_STATUS);
+    io.outb(io::ATTRIBUTE_INDEX, io::ATTRIBUTE_PALETTE_ENABLE);
+}
+
+/// Sets palette entry `index` to the given color; each component ranges
+/// from 0 to 63.
+pub fn set_palette<I: Io>(io: &mut I, index: u8, red: u8, green: u8, blue: u8) {
+    io.outb(io::DAC_WRITE_INDEX, index);
+    io.outb(io::DAC_DATA, red & 0x3F);
+    io.outb(io::DAC_DATA, green & 0x3F);
+    io.outb(io::DAC_DATA, blue & 0x3F);
+}
+
+/// Sets consecutive palette entries starting at `first` from `(red, green,
+/// blue)` triples.
+pub fn set_palette_range<I: Io>(io: &mut I, first: u8, colors: &[(u8, u8, u8)]) {
+    // the DAC moves on to the next entry by itself after every third write
+    io.outb(io::DAC_WRITE_INDEX, first);
+    for &(red, green, blue) in colors {
+        io.outb(io::DAC_DATA, red & 0x3F);
+        io.outb(io::DAC_DATA, green & 0x3F);
+        io.outb(io::DAC_DATA, blue & 0x3F);
+    }
+}